@@ -0,0 +1,199 @@
+//derives v1's BinaryEncode/BinaryDecode from field order.
+//the generated code is byte-compatible with the hand-written impls in v1::utils::common:
+//  integers, bool(one byte) and String are written inline, String with a length,
+//  nested structs and Vec<T> carry their own item length,
+//  Option<T> is an item whose length is 0 when None,
+//  Vec<T> of a scalar holds the values inline, of a struct one item per element,
+//  and the whole struct is wrapped with its item length.
+//every length uses the LengthPrefix passed to encode_with/decode_with, i16 by default.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Fields, GenericArgument, Ident, PathArguments,
+    PathSegment, Type,
+};
+
+enum FieldKind {
+    Scalar,
+    Vec,
+    ScalarVec,
+    Option,
+    ScalarOption,
+    Item,
+}
+
+fn is_scalar(ty: &Type) -> bool {
+    match ty {
+        Type::Path(p) => match p.path.segments.last() {
+            Some(seg) => matches!(
+                seg.ident.to_string().as_str(),
+                "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" | "f32" | "f64"
+                    | "bool" | "String"
+            ),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+//T of Vec<T> or Option<T>.
+fn inner_type(seg: &PathSegment) -> Option<&Type> {
+    match &seg.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+fn field_kind(ty: &Type) -> FieldKind {
+    if is_scalar(ty) {
+        return FieldKind::Scalar;
+    }
+
+    if let Type::Path(p) = ty {
+        if let Some(seg) = p.path.segments.last() {
+            let scalar_inner = inner_type(seg).map(is_scalar).unwrap_or(false);
+            match seg.ident.to_string().as_str() {
+                "Vec" if scalar_inner => return FieldKind::ScalarVec,
+                "Vec" => return FieldKind::Vec,
+                "Option" if scalar_inner => return FieldKind::ScalarOption,
+                "Option" => return FieldKind::Option,
+                _ => {}
+            }
+        }
+    }
+
+    FieldKind::Item
+}
+
+fn named_fields(input: &DeriveInput) -> Result<Vec<(Ident, Type)>, Error> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "generic structs are not supported"));
+    }
+
+    match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(fields) => Ok(fields
+                .named
+                .iter()
+                .map(|f| (f.ident.clone().unwrap(), f.ty.clone()))
+                .collect()),
+            _ => Err(Error::new_spanned(&input.ident, "only structs with named fields are supported")),
+        },
+        _ => Err(Error::new_spanned(&input.ident, "only structs are supported")),
+    }
+}
+
+#[proc_macro_derive(BinaryEncode)]
+pub fn derive_binary_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand_encode(&input) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[proc_macro_derive(BinaryDecode)]
+pub fn derive_binary_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand_decode(&input) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_encode(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+
+    let writes = named_fields(input)?.into_iter().map(|(field, ty)| match field_kind(&ty) {
+        FieldKind::Scalar => quote! {
+            ::v1::utils::common::BinaryScalar::write_scalar(&self.#field, &mut encoded, prefix)?;
+        },
+        FieldKind::ScalarVec => quote! {
+            encoded.extend(::v1::utils::common::encode_scalar_list(&self.#field, prefix)?);
+        },
+        FieldKind::Option => quote! {
+            encoded.extend(::v1::utils::common::encode_option_item(&self.#field, prefix)?);
+        },
+        FieldKind::ScalarOption => quote! {
+            encoded.extend(::v1::utils::common::encode_option_scalar(&self.#field, prefix)?);
+        },
+        FieldKind::Vec | FieldKind::Item => quote! {
+            encoded.extend(::v1::BinaryEncode::encode_with(&self.#field, prefix)?);
+        },
+    });
+
+    Ok(quote! {
+        impl ::v1::BinaryEncode for #name {
+            fn encode_with(
+                &self,
+                prefix: ::v1::utils::common::LengthPrefix,
+            ) -> ::anyhow::Result<::std::vec::Vec<u8>> {
+                let mut encoded: ::std::vec::Vec<u8> = ::std::vec::Vec::new();
+
+                #(#writes)*
+
+                //set item length
+                ::v1::BinaryEncode::encode_with(&encoded, prefix)
+            }
+        }
+    })
+}
+
+fn expand_decode(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let fields = named_fields(input)?;
+
+    let reads = fields.iter().map(|(field, ty)| {
+        let local = format_ident!("__field_{}", field);
+
+        match field_kind(ty) {
+            FieldKind::Scalar => quote! {
+                let #local: #ty = ::v1::utils::common::BinaryScalar::read_scalar(cursor, bytes, prefix)?;
+            },
+            FieldKind::Option => quote! {
+                let #local: #ty = ::v1::utils::common::decode_option_item(cursor, bytes, prefix)?;
+            },
+            FieldKind::ScalarOption => quote! {
+                let #local: #ty = ::v1::utils::common::decode_option_scalar(cursor, bytes, prefix)?;
+            },
+            FieldKind::ScalarVec => quote! {
+                let #local: #ty = ::v1::utils::common::decode_scalar_list(cursor, bytes, prefix)?;
+            },
+            FieldKind::Vec => quote! {
+                let #local: #ty = ::v1::BinaryDecode::decode_with(cursor, bytes, prefix)?;
+            },
+            FieldKind::Item => quote! {
+                let #local: #ty = ::v1::utils::common::decode_item(cursor, bytes, prefix)?;
+            },
+        }
+    });
+
+    let inits = fields.iter().map(|(field, _)| {
+        let local = format_ident!("__field_{}", field);
+        quote! { #field: #local }
+    });
+
+    Ok(quote! {
+        impl<'a> ::v1::BinaryDecode<'a> for #name {
+            fn decode_with(
+                cursor: &mut ::std::io::Cursor<&'a [u8]>,
+                bytes: &'a [u8],
+                prefix: ::v1::utils::common::LengthPrefix,
+            ) -> ::anyhow::Result<#name> {
+                #(#reads)*
+
+                Ok(#name {
+                    #(#inits),*
+                })
+            }
+        }
+    })
+}
@@ -0,0 +1,11 @@
+-- read position of a user in the kingdom chat, the (created_timestamp, mid) of the newest message read.
+CREATE TABLE chat_kingdom_reads (
+    krid BIGINT PRIMARY KEY,
+    uuid BIGINT NOT NULL,
+    kingdom_id BIGINT NOT NULL,
+    latest_timestamp BIGINT NOT NULL DEFAULT 0,
+    latest_mid BIGINT NOT NULL DEFAULT 0,
+    modify_time TIMESTAMP NOT NULL DEFAULT NOW(),
+    created_time TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (uuid, kingdom_id)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE chat_user_unread_counts DROP COLUMN latest_mid;
+ALTER TABLE chat_groups_uids DROP COLUMN latest_mid;
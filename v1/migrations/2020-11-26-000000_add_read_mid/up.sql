@@ -0,0 +1,3 @@
+-- group and p2p read positions become (latest_timestamp, latest_mid) cursors like chat_kingdom_reads.
+ALTER TABLE chat_groups_uids ADD COLUMN latest_mid BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE chat_user_unread_counts ADD COLUMN latest_mid BIGINT NOT NULL DEFAULT 0;
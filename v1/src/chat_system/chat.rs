@@ -1,41 +1,42 @@
-use crate::diesel::Connection;
 use crate::models::{
-    chat_groups_uids::ChatGroupsUid, chat_messages::ChatMessage,
-    chat_messages::FrontDisplayChatMessageUnreadCount, servers::Server, user::User,
-    chat_user_unread_counts::ChatUserUnreadCount,
-    blacklist::Blacklist,
+    chat_groups_uids::ChatGroupsUid,
+    chat_messages::FrontDisplayChatMessageUnreadCount,
     chat_messages::FrontDisplayP2pChatMessageCount,
+    chat_messages::GroupMessagePage,
+    chat_messages::KingdomMessagePage,
+    chat_messages::MessageCursor,
+    chat_messages::P2pMessagePage,
+    chat_messages::PageQuery,
+    chat_messages::PushChatMessage,
+};
+use crate::utils::dedup::SendState;
+use crate::chat_system::push::{
+    ack_chat_message, publish_chat_message, publish_unread_badge,
+};
+use crate::chat_system::request::{
+    ChannelUnreadCountRequest, ConnectionStateRequest, GroupMessageContentRequest,
+    GroupMessagePageRequest, KingdomMessageContentRequest, KingdomMessagePageRequest,
+    MarkReadRequest, P2pMessageContentRequest, P2pMessagePageRequest, PushAckRequest,
+    SendMessageRequest, UserUnreadCountRequest, MAX_PAGE_LIMIT,
 };
 use crate::ResponseResult;
 use crate::{
-    get_connection, ChatMessageUnReadCount, Clients, Connection as LocalConn, GroupUnReadCountMsg,
-    KingdomUnReadCountMsg, MessageStateCode, ONLINE_USERS_SETS_REDIS_KEY,
+    ChatMessageUnReadCount, Clients, Connection as LocalConn, GroupUnReadCountMsg,
+    KingdomUnReadCountMsg, MessageStateCode, UnreadBadge,
 };
-use anyhow::{anyhow, Error};
-use byteorder::{LittleEndian, ReadBytesExt};
-use redis::Commands;
+use anyhow::anyhow;
+use chrono::Utc;
 use tracing::{error, info};
 use crate::default_log_pre;
 use function_name::named;
 
 #[named]
-pub async fn connection_state(clients: Clients, conn: LocalConn) -> ResponseResult {
-    let redis_conn = get_connection()?;
-    let mut cursor = std::io::Cursor::new(conn.msg.body.clone());
-
-    let uid = cursor.read_u64::<LittleEndian>();
-
-    let uid = match uid {
-        Ok(uid) => uid,
-        Err(e) => {
-            error!("{}\tinvalid uid param reason:{}.",
-                   default_log_pre!(conn.msg.code as i16,""),
-                   e
-            );
-            let m = "invaild user param.";
-            return conn.get_general_error(m);
-        }
-    };
+pub async fn connection_state(
+    clients: Clients,
+    conn: LocalConn,
+    req: ConnectionStateRequest,
+) -> ResponseResult {
+    let uid = req.uid;
 
     info!(
         "{}\tsubmit content\tuuid:{}",
@@ -43,16 +44,38 @@ pub async fn connection_state(clients: Clients, conn: LocalConn) -> ResponseResu
         uid,
     );
 
-    //insert redis
-    match redis_conn
+    //verify login token and bind the identity to this socket
+    if let Err(e) = conn.verify_token(uid, req.token).await {
+        error!("{}\tfailed verify login token reason:{}.",
+               default_log_pre!(conn.msg.code as i16,uid),
+               e
+        );
+        let m = "invalid token.";
+        return conn.get_general_error(m);
+    }
+
+    if let Err(e) = conn.session.bind(uid) {
+        error!("{}\tfailed bind session reason:{}.",
+               default_log_pre!(conn.msg.code as i16,uid),
+               e
+        );
+        return conn.get_general_error(e.to_string().as_str());
+    }
+
+    let conn_id = conn.session.conn_id;
+
+    //mark online, the broker counts connections so logging in again on a socket counts once
+    let registered = clients
         .lock()
         .await
-        .sadd::<&str, u64, u64>(ONLINE_USERS_SETS_REDIS_KEY, uid)
-    {
-        Ok(_) => {}
-        Err(e) => {
+        .get(&uid)
+        .into_iter()
+        .flatten()
+        .any(|c| c.session.conn_id == conn_id);
+    if !registered {
+        if let Err(e) = conn.with_broker(move |b| b.user_online(uid)).await {
             error!(
-                "{}\tfailed add uid to redis db: {}",
+                "{}\tfailed mark user online: {}",
                 default_log_pre!(conn.msg.code as i16,uid),
                 e
             );
@@ -64,124 +87,87 @@ pub async fn connection_state(clients: Clients, conn: LocalConn) -> ResponseResu
     let m = "success";
     let resp = conn.get_bin_code(MessageStateCode::Ok, m, "")?;
 
-    clients.lock().await.insert(uid, conn);
+    //every device of the user stays registered, logging in again on a socket replaces its own entry
+    {
+        let mut clients = clients.lock().await;
+        let conns = clients.entry(uid).or_insert_with(Vec::new);
+        conns.retain(|c| c.session.conn_id != conn_id);
+        conns.push(conn);
+    }
+
     Ok(resp)
 }
 
 #[named]
-pub async fn send_message(clients: Clients, conn: LocalConn) -> ResponseResult {
-    let new_body = conn.msg.body.clone();
-    //get message
-    let mut cursor = std::io::Cursor::new(conn.msg.body.to_vec());
-    let uid = cursor.read_u64::<LittleEndian>();
-    let uid = match uid {
-        Ok(uid) => uid,
-        Err(e) => {
-            error!(
-                "{}\tinvalid uid param reason:{}.",
-                default_log_pre!(conn.msg.code as i16,""),
-                e
-            );
-            let m = "invaild uid param.";
-            return conn.get_general_error(m);
-        }
-    };
+pub async fn send_message(clients: Clients, conn: LocalConn, req: SendMessageRequest) -> ResponseResult {
+    let SendMessageRequest { uid, tid, dst_id, msg_type, content, client_msg_id } = req;
 
-    //chat channel type.
-    let tid = cursor.read_u8();
-    let tid = match tid {
-        Ok(v) => v,
-        Err(e) => {
-            error!(
-                "{}\tinvalid tid param reason:{}.",
-                default_log_pre!(conn.msg.code as i16,uid),
-                e
-            );
-            let m = "invaild tid param.";
-            return conn.get_general_error(m);
-        }
-    };
+    info!("{}\tsubmit content\tuid:{}\ttid:{}\tdst_id:{}\tclient_msg_id:{}\tmessage:{:?}", default_log_pre!(conn.msg.code as i16,uid), uid, &tid, &dst_id, &client_msg_id, &content);
 
-    let dst_id = cursor.read_u64::<LittleEndian>();
-    let dst_id = match dst_id {
-        Ok(v) => v,
-        Err(e) => {
-            error!(
-                "{}\tinvalid dst_id param reason:{}.",
-                default_log_pre!(conn.msg.code as i16,uid),
-                e
-            );
-            let m = "invaild dst_id param.";
-            return conn.get_general_error(m);
+    match tid {
+        1..=3 => {}
+        4 => return Err(anyhow!("not finished.")), //allience
+        _ => return Err(anyhow!("invalid tid.")),
+    }
+
+    //a retried send is answered with the message stored the first time
+    let mut dedup = !client_msg_id.is_empty();
+    if dedup {
+        let id = client_msg_id.clone();
+        match conn.with_send_dedup(move |d| d.begin(uid, &id)).await {
+            Ok(SendState::New) => {}
+            Ok(SendState::InFlight) => return conn.get_general_error("message is being sent."),
+            Ok(SendState::Done(data)) => {
+                info!("{}\tduplicate send\tclient_msg_id:{}\tmid:{}", default_log_pre!(conn.msg.code as i16,uid), &client_msg_id, data.mid);
+                return conn.get_bin_code(MessageStateCode::Ok, "success.", data);
+            }
+            //still send, only a retry of this message could be stored twice.
+            Err(e) => {
+                error!("{}\tfailed check client_msg_id:{}\terror:{:?}", default_log_pre!(conn.msg.code as i16,uid), &client_msg_id, e);
+                dedup = false;
+            }
         }
+    }
+
+    let sent = match tid {
+        1 => kingdom_chat(clients, &conn, tid, uid, dst_id, content, msg_type).await, //kd
+        2 => group_chat(clients, &conn, tid, uid as i64, dst_id, content, msg_type).await, //group
+        _ => p2p_chat(clients, &conn, tid, uid, dst_id, content, msg_type).await,     //p2p
     };
 
-    let msg_type = cursor.read_u16::<LittleEndian>();
-    let msg_type = match msg_type {
-        Ok(v) => v,
-        Err(e) => {
-            error!(
-                "{}\tinvalid msg_type param reason:{}.",
-                default_log_pre!(conn.msg.code as i16,uid),
-                e
-            );
-            let m = "invaild msg type param.";
-            return conn.get_general_error(m);
+    match sent {
+        Ok(data) => {
+            if dedup {
+                let (id, stored) = (client_msg_id.clone(), data.clone());
+                if let Err(e) = conn.with_send_dedup(move |d| d.finish(uid, &id, stored)).await {
+                    error!("{}\tfailed store client_msg_id:{}\terror:{:?}", default_log_pre!(conn.msg.code as i16,uid), &client_msg_id, e);
+                }
+            }
+            conn.get_bin_code(MessageStateCode::Ok, "success.", data)
         }
-    };
-    let content_length = cursor.read_u16::<LittleEndian>();
-    let _content_length = match content_length {
-        Ok(v) => v,
-        Err(e) => {
-            error!(
-                "{}\tinvalid dst_id param reason:{}.",
-                default_log_pre!(conn.msg.code as i16,uid),
-                e
-            );
-            let m = "invaild content length param.";
-            return conn.get_general_error(m);
+        Err(m) => {
+            if dedup {
+                let id = client_msg_id.clone();
+                if let Err(e) = conn.with_send_dedup(move |d| d.abort(uid, &id)).await {
+                    error!("{}\tfailed release client_msg_id:{}\terror:{:?}", default_log_pre!(conn.msg.code as i16,uid), &client_msg_id, e);
+                }
+            }
+            conn.get_general_error(m.as_str())
         }
-    };
-
-    //message
-    let message = &new_body[cursor.position() as usize..];
-
-    info!("{}\tsubmit content\tuid:{}\ttid:{}\tdst_id:{}\tmessage:{:?}", default_log_pre!(conn.msg.code as i16,uid), uid, &tid, &dst_id, std::str::from_utf8(message));
-
-    match tid {
-        1 => kingdom_chat(clients, &conn, tid, uid, dst_id, message, msg_type), //kd
-        2 => group_chat(clients, &conn, tid, uid as i64, dst_id, message, msg_type), //group
-        3 => p2p_chat(clients, &conn, tid, uid, dst_id, message, msg_type),     //p2p
-        4 => Err(anyhow!("not finished.")),                           //allience
-        _ => Err(anyhow!("invalid tid.")),
     }
 }
 
 #[named]
-fn kingdom_chat(
-    _clients: Clients,
+async fn kingdom_chat(
+    clients: Clients,
     conn: &LocalConn,
     tid: u8,
     from_uid: u64,
     dst_id: u64,
-    msg: &[u8],
+    msg: String,
     msg_type: u16,
-) -> ResponseResult {
-    let master_db_conn = conn.db_conn(true);
-    let slave_db_conn = conn.db_conn(false);
-
-    let decode_msg = std::str::from_utf8(msg);
-    if let Err(e) = decode_msg {
-        error!(
-            "{}\tfailed parse message to utf8 reason:{}.",
-            default_log_pre!(conn.msg.code as i16,from_uid),
-            e
-        );
-        let m = "invalid utf8 encode.";
-        return conn.get_general_error(m);
-    }
-
-    let kingdom_id = match Server::get_server_id(&slave_db_conn, dst_id as i32) {
+) -> Result<FrontDisplayP2pChatMessageCount, String> {
+    let kingdom_id = match conn.with_store(move |s| s.server_kingdom_id(dst_id as i32)).await {
         Ok(v) => v,
         Err(e) => {
             error!(
@@ -189,18 +175,17 @@ fn kingdom_chat(
                 default_log_pre!(conn.msg.code as i16,from_uid),
                 &e
             );
-            return conn.get_general_error(e.to_string().as_str());
+            return Err(e.to_string());
         }
     };
 
-    let msg_content = match ChatMessage::add(
-        &master_db_conn,
+    let msg_content = match conn.with_store(move |s| s.add_message(
         from_uid as i64,
         kingdom_id,
-        decode_msg.unwrap().into(),
+        msg,
         tid as i16,
         msg_type as i16,
-    ) {
+    )).await {
         Ok(v) => v,
 
         Err(e) => {
@@ -209,11 +194,28 @@ fn kingdom_chat(
                 default_log_pre!(conn.msg.code as i16,from_uid),
                 e
             );
-            let m = "server error.";
-            return conn.get_general_error(m);
+            return Err("server error.".to_string());
         }
     };
 
+    //publish to online kingdom members
+    let display_msg = msg_content.clone();
+    match conn.with_store(move |s| s.kingdom_display_message(display_msg)).await {
+        Ok(v) => publish_chat_message(
+            conn,
+            clients,
+            from_uid,
+            dst_id,
+            PushChatMessage::Kingdom(v),
+        )
+        .await,
+        Err(e) => error!(
+            "{}\tfailed build kingdom push message:{:?}.",
+            default_log_pre!(conn.msg.code as i16,from_uid),
+            e
+        ),
+    }
+
     let data = FrontDisplayP2pChatMessageCount {
         mid: msg_content.mid,
         content: msg_content.content,
@@ -222,47 +224,27 @@ fn kingdom_chat(
         msg_type: msg_content.msg_type,
     };
 
-    conn.get_bin_code(MessageStateCode::Ok, "success.", data)
+    Ok(data)
 }
 
 #[named]
-fn group_chat(
-    _clients: Clients,
+async fn group_chat(
+    clients: Clients,
     conn: &LocalConn,
     tid: u8,
     from_uid: i64,
     dst_id: u64,
-    msg: &[u8],
+    msg: String,
     msg_type: u16,
-) -> ResponseResult {
-    let master_db_conn = conn.db_conn(true);
-
-    let decode_msg = std::str::from_utf8(msg);
-    if let Err(e) = decode_msg {
-        error!(
-            "{}\tfailed parse message to utf8 reason:{}.",
-            default_log_pre!(conn.msg.code as i16,from_uid),
-            e
-        );
-        let m = "invalid utf8 encode.";
-        return conn.get_general_error(m);
-    }
-
-    let msg_content = match master_db_conn.transaction::<ChatMessage, Error, _>(|| {
-        let chat_message_content = ChatMessage::add(
-            &master_db_conn,
-            from_uid as i64,
-            dst_id as i64,
-            decode_msg.unwrap().into(),
-            tid as i16,
-            msg_type as i16,
-        )?;
-
-        //update user and group table unread count
-        ChatGroupsUid::update_unread_count(&master_db_conn, dst_id as i64, from_uid as i64)?;
-
-        Ok(chat_message_content)
-    }) {
+) -> Result<FrontDisplayP2pChatMessageCount, String> {
+    //also bumps the other group members unread count
+    let msg_content = match conn.with_store(move |s| s.add_message(
+        from_uid,
+        dst_id as i64,
+        msg,
+        tid as i16,
+        msg_type as i16,
+    )).await {
         Ok(v) => v,
         Err(e) => {
             error!(
@@ -270,11 +252,27 @@ fn group_chat(
                 default_log_pre!(conn.msg.code as i16,from_uid),
                 e
             );
-            let m = "server error.";
-            return conn.get_general_error(m);
+            return Err("server error.".to_string());
         }
     };
 
+    //publish to online group members
+    let display_msg = msg_content.clone();
+    match conn.with_store(move |s| s.group_display_message(display_msg)).await {
+        Ok(v) => publish_chat_message(
+            conn,
+            clients,
+            from_uid as u64,
+            dst_id,
+            PushChatMessage::Group(v),
+        )
+        .await,
+        Err(e) => error!(
+            "{}\tfailed build group push message:{:?}.",
+            default_log_pre!(conn.msg.code as i16,from_uid),
+            e
+        ),
+    }
 
     let data = FrontDisplayP2pChatMessageCount {
         mid: msg_content.mid,
@@ -284,54 +282,34 @@ fn group_chat(
         msg_type: msg_content.msg_type,
     };
 
-    conn.get_bin_code(MessageStateCode::Ok, "success.", data)
+    Ok(data)
 }
 
 #[named]
-fn p2p_chat(
-    _clients: Clients,
+async fn p2p_chat(
+    clients: Clients,
     conn: &LocalConn,
     tid: u8,
     from_uid: u64,
     dst_uid: u64,
-    msg: &[u8],
+    msg: String,
     msg_type: u16,
-) -> ResponseResult {
-    //add user unread count to pgsql
+) -> Result<FrontDisplayP2pChatMessageCount, String> {
     //check is black list
-    let master_db_conn = conn.db_conn(true);
-    let slave_db_conn = conn.db_conn(false);
-
-    if let Ok(exists) = Blacklist::find_user_black_list_exists(&slave_db_conn, dst_uid as i64,from_uid as i64 ) {
+    if let Ok(exists) = conn.with_store(move |s| s.is_blacklisted(dst_uid as i64, from_uid as i64)).await {
         if exists {
-            let m = "you are blacklisted.";
-            return conn.get_general_error(m);
+            return Err("you are blacklisted.".to_string());
         }
     }
 
-    let decode_msg = std::str::from_utf8(msg);
-    if let Err(e) = decode_msg {
-        error!(
-            "{}\tfailed parse message to utf8 reason:{}.",
-            default_log_pre!(conn.msg.code as i16,from_uid),
-            e
-        );
-        let m = "invalid utf8 encode.";
-        return conn.get_general_error(m);
-    }
-
-    let msg_content = match master_db_conn.transaction::<ChatMessage, Error, _>(|| {
-        let msg_content = ChatMessage::add(
-            &master_db_conn,
-            from_uid as i64,
-            dst_uid as i64,
-            decode_msg.unwrap().into(),
-            tid as i16,
-            msg_type as i16,
-        )?;
-        ChatUserUnreadCount::add(&master_db_conn, dst_uid as i64, from_uid as i64, 1)?;
-        Ok(msg_content)
-    }) {
+    //also bumps the peer unread count
+    let msg_content = match conn.with_store(move |s| s.add_message(
+        from_uid as i64,
+        dst_uid as i64,
+        msg,
+        tid as i16,
+        msg_type as i16,
+    )).await {
         Ok(v) => v,
         Err(e) => {
             error!(
@@ -339,11 +317,28 @@ fn p2p_chat(
                 default_log_pre!(conn.msg.code as i16,from_uid),
                 e
             );
-            let m = "server error.";
-            return conn.get_general_error(m);
+            return Err("server error.".to_string());
         }
     };
 
+    //push to the peer
+    let display_msg = msg_content.clone();
+    match conn.with_store(move |s| s.p2p_display_message(display_msg)).await {
+        Ok(v) => publish_chat_message(
+            conn,
+            clients,
+            from_uid,
+            dst_uid,
+            PushChatMessage::P2p(v),
+        )
+        .await,
+        Err(e) => error!(
+            "{}\tfailed build p2p push message:{:?}.",
+            default_log_pre!(conn.msg.code as i16,from_uid),
+            e
+        ),
+    }
+
     let data = FrontDisplayP2pChatMessageCount {
         mid: msg_content.mid,
         content: msg_content.content,
@@ -352,37 +347,39 @@ fn p2p_chat(
         msg_type: msg_content.msg_type,
     };
 
-    conn.get_bin_code(MessageStateCode::Ok, "success.", data)
+    Ok(data)
 }
 
-pub async fn client_feedback_server_message(_clients: Clients, _conn: LocalConn) -> ResponseResult {
-    Err(anyhow!("not uknown."))
+//client ack for a pushed message
+#[named]
+pub async fn client_feedback_server_message(
+    _clients: Clients,
+    conn: LocalConn,
+    req: PushAckRequest,
+) -> ResponseResult {
+    let PushAckRequest { uid, mid } = req;
+
+    info!("{}\tsubmit content\tuid:{}\tmid:{}", default_log_pre!(conn.msg.code as i16,uid), uid, mid);
+
+    if !ack_chat_message(&conn.pending_pushes, uid, conn.session.conn_id, mid).await {
+        info!("{}\tack for message not pending\tmid:{}", default_log_pre!(conn.msg.code as i16,uid), mid);
+    }
+
+    conn.get_bin_code(MessageStateCode::Ok, "success.", "")
 }
 
 //pull get user message unread count
 #[named]
-pub async fn get_user_message_unread_count(_clients: Clients, conn: LocalConn) -> ResponseResult {
-    let db_conn = conn.db_conn(false);
-    let mut cursor = std::io::Cursor::new(&conn.msg.body);
-
-    let kingdom_read_timestamp = cursor.read_i64::<LittleEndian>().unwrap_or(0);
-    let uid = cursor.read_i64::<LittleEndian>();
-    let uid = match uid {
-        Ok(uid) => uid,
-        Err(e) => {
-            error!(
-                "{}\tinvalid uid param reason:{}.",
-                default_log_pre!(conn.msg.code as i16,""),
-                e
-            );
-            let m = "invaild user param.";
-            return conn.get_general_error(m);
-        }
-    };
+pub async fn get_user_message_unread_count(
+    _clients: Clients,
+    conn: LocalConn,
+    req: UserUnreadCountRequest,
+) -> ResponseResult {
+    let UserUnreadCountRequest { kingdom_read_timestamp, uid } = req;
 
     info!("{}\tsubmit content\tkingdom_read_timestamp:{}\tuuid:{}", default_log_pre!(conn.msg.code as i16,uid), kingdom_read_timestamp, uid);
 
-    let kingdom_id = match User::get_kingdom_id(&db_conn, uid) {
+    let kingdom_id = match conn.with_store(move |s| s.kingdom_id(uid)).await {
         Ok(v) => v,
         Err(e) => {
             error!(
@@ -394,12 +391,19 @@ pub async fn get_user_message_unread_count(_clients: Clients, conn: LocalConn) -
         }
     };
 
+    let kingdom_read_cursor = match kingdom_read_position(&conn, uid, kingdom_id, reported_timestamp(kingdom_read_timestamp)).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}\tget kingdom read position error:{:?}", default_log_pre!(conn.msg.code as i16,uid), &e);
+            return conn.get_general_error(e.to_string().as_str());
+        }
+    };
+
     let (kingdom_unread_count, kingdom_msg) =
-        match ChatMessage::get_kingdom_unread_count_and_latest_message(
-            &db_conn,
-            kingdom_id,
-            kingdom_read_timestamp,
-        ) {
+        match conn
+            .with_store(move |s| s.kingdom_unread_count_and_latest_message(kingdom_id, kingdom_read_cursor))
+            .await
+        {
             Ok(v) => (v.0, Some(v.1)),
             Err(e) => {
                 error!("{}\tget kingdom unread count and latest message error:{:?}", default_log_pre!(conn.msg.code as i16,uid), &e);
@@ -407,7 +411,7 @@ pub async fn get_user_message_unread_count(_clients: Clients, conn: LocalConn) -
             }
         };
     //find unread count >0 for group
-    let gids: Vec<ChatGroupsUid> = match ChatGroupsUid::get_gids_by_uid(&db_conn, uid) {
+    let gids: Vec<ChatGroupsUid> = match conn.with_store(move |s| s.unread_groups(uid)).await {
         Ok(v) => v,
         Err(e) => {
             error!("{}\tget group chat id related uuid list error:{:?}", default_log_pre!(conn.msg.code as i16,uid), &e);
@@ -419,11 +423,10 @@ pub async fn get_user_message_unread_count(_clients: Clients, conn: LocalConn) -
 
     for gid in gids.into_iter() {
         let (group_unread_count, group_msg) =
-            match ChatMessage::get_group_unread_count_and_latest_message(
-                &db_conn,
-                gid.gid,
-                gid.latest_timestamp,
-            ) {
+            match conn
+                .with_store(move |s| s.group_unread_count_and_latest_message(gid.gid, uid, gid.read_cursor()))
+                .await
+            {
                 Ok(v) => v,
                 Err(e) => {
                     error!("{}\tget group chat unread and latest message list error:{:?}", default_log_pre!(conn.msg.code as i16,uid), &e);
@@ -437,7 +440,7 @@ pub async fn get_user_message_unread_count(_clients: Clients, conn: LocalConn) -
     }
 
     //p2p
-    let p2p_msg = match ChatUserUnreadCount::get_user_unread_message_count(&db_conn, uid) {
+    let p2p_msg = match conn.with_store(move |s| s.user_unread_counts(uid)).await {
         Ok(v) => v,
         Err(e) => {
             error!(
@@ -462,37 +465,44 @@ pub async fn get_user_message_unread_count(_clients: Clients, conn: LocalConn) -
 }
 
 #[named]
-pub async fn get_kingdom_message_content(_clients: Clients, conn: LocalConn) -> ResponseResult {
-    let db_conn = conn.db_conn(false);
-    let mut cursor = std::io::Cursor::new(&conn.msg.body);
+pub async fn get_kingdom_message_content(
+    _clients: Clients,
+    conn: LocalConn,
+    req: KingdomMessageContentRequest,
+) -> ResponseResult {
+    let KingdomMessageContentRequest { timestamp, limit, order, uid } = req;
+    let limit = limit.min(MAX_PAGE_LIMIT);
 
-    let timestamp = cursor.read_i64::<LittleEndian>().unwrap_or(0);
-    let mut limit = cursor.read_i16::<LittleEndian>().unwrap_or(10);
+    info!("{}\tsubmit content\ttimestamp:{}\tlimit:{}\torder:{}\tuuid:{}", default_log_pre!(conn.msg.code as i16,uid), timestamp, limit, order, uid);
 
-    if limit > 50 {
-        limit = 50;
+    let page = PageQuery::from_timestamp(timestamp, order, limit as i64);
+    match kingdom_page(&conn, uid, page).await {
+        Ok(v) => conn.get_bin_code(MessageStateCode::Ok, "success.", in_order(v.messages, order)),
+        Err(resp) => resp,
     }
+}
 
-    //0:asc,1:desc
-    let order = cursor.read_i16::<LittleEndian>().unwrap_or(1);
+#[named]
+pub async fn get_kingdom_message_page(
+    _clients: Clients,
+    conn: LocalConn,
+    req: KingdomMessagePageRequest,
+) -> ResponseResult {
+    let page = req.page();
+    let KingdomMessagePageRequest { cursor, limit, mode, uid } = req;
 
-    let uid = cursor.read_i64::<LittleEndian>();
-    let uid = match uid {
-        Ok(uid) => uid,
-        Err(e) => {
-            error!(
-                "{}\tinvalid uid param reason:{}.",
-                default_log_pre!(conn.msg.code as i16,""),
-                e
-            );
-            let m = "invaild user param.";
-            return conn.get_general_error(m);
-        }
-    };
+    info!("{}\tsubmit content\tcursor:{}\tlimit:{}\tmode:{}\tuuid:{}", default_log_pre!(conn.msg.code as i16,uid), cursor, limit, mode, uid);
 
-    info!("{}\tsubmit content\ttimestamp:{}\tlimit:{}\torder:{}\tuuid:{}", default_log_pre!(conn.msg.code as i16,uid), timestamp, limit, order, uid);
+    match kingdom_page(&conn, uid, page).await {
+        Ok(v) => conn.get_bin_code(MessageStateCode::Ok, "success.", v),
+        Err(resp) => resp,
+    }
+}
 
-    let kingdom_id = match User::get_kingdom_id(&db_conn, uid) {
+//the kingdom page of uid, read up to its newest message.
+#[named]
+async fn kingdom_page(conn: &LocalConn, uid: i64, page: PageQuery) -> Result<KingdomMessagePage, ResponseResult> {
+    let kingdom_id = match conn.with_store(move |s| s.kingdom_id(uid)).await {
         Ok(v) => v,
         Err(e) => {
             error!(
@@ -500,17 +510,14 @@ pub async fn get_kingdom_message_content(_clients: Clients, conn: LocalConn) ->
                 default_log_pre!(conn.msg.code as i16,uid),
                 e
             );
-            return conn.get_general_error(e.to_string().as_str());
+            return Err(conn.get_general_error(e.to_string().as_str()));
         }
     };
 
-    let res_data = match ChatMessage::get_kingdom_message(
-        &db_conn,
-        kingdom_id,
-        timestamp,
-        limit as i64,
-        order,
-    ) {
+    let res_data = match conn
+        .with_store(move |s| s.kingdom_messages(kingdom_id, page))
+        .await
+    {
         Ok(v) => v,
         Err(e) => {
             error!(
@@ -518,177 +525,150 @@ pub async fn get_kingdom_message_content(_clients: Clients, conn: LocalConn) ->
                 default_log_pre!(conn.msg.code as i16,uid),
                 e
             );
-            return conn.get_general_error(e.to_string().as_str());
+            return Err(conn.get_general_error(e.to_string().as_str()));
         }
     };
 
-    conn.get_bin_code(MessageStateCode::Ok, "success.", res_data)
+    if let Some(latest) = res_data.messages.last() {
+        let cursor = MessageCursor { created_timestamp: latest.created_timestamp, mid: latest.mid };
+        if let Err(e) = conn.with_store(move |s| s.mark_kingdom_read(uid, kingdom_id, cursor)).await {
+            error!("{}\tfailed update kingdom read position:{:?}",
+                   default_log_pre!(conn.msg.code as i16,uid), e
+            );
+        }
+    }
+
+    Ok(res_data)
 }
 
 #[named]
-pub async fn get_group_message_content(_clients: Clients, conn: LocalConn) -> ResponseResult {
-    let master_db_conn = conn.db_conn(true);
-    let slave_db_conn = conn.db_conn(false);
-    let mut cursor = std::io::Cursor::new(&conn.msg.body);
+pub async fn get_group_message_content(
+    _clients: Clients,
+    conn: LocalConn,
+    req: GroupMessageContentRequest,
+) -> ResponseResult {
+    let GroupMessageContentRequest { timestamp, limit, order, gid, uid } = req;
+    let limit = limit.min(MAX_PAGE_LIMIT);
 
-    let timestamp = cursor.read_i64::<LittleEndian>().unwrap_or(0);
-    let mut limit = cursor.read_i16::<LittleEndian>().unwrap_or(10);
+    info!("{}\tsubmit content\tuid:{}\ttimestamp:{}\tlimit:{}\torder:{}\tgid:{}", default_log_pre!(conn.msg.code as i16,uid), uid, timestamp, limit, order, gid);
 
-    if limit > 50 {
-        limit = 50;
+    let page = PageQuery::from_timestamp(timestamp, order, limit as i64);
+    match group_page(&conn, gid, uid, page).await {
+        Ok(v) => conn.get_bin_code(MessageStateCode::Ok, "success", in_order(v.messages, order)),
+        Err(resp) => resp,
     }
+}
 
-    //0:asc,1:desc
-    let order = cursor.read_i16::<LittleEndian>().unwrap_or(1);
-
-    let gid = cursor.read_i64::<LittleEndian>().unwrap_or(0);
-
-    if gid <= 0 {
-        error!(
-            "{}\tinvalid gid param.",
-            default_log_pre!(conn.msg.code as i16,""),
-        );
-        let m = "invaild gid param.";
-        return conn.get_general_error(m);
-    }
+#[named]
+pub async fn get_group_message_page(
+    _clients: Clients,
+    conn: LocalConn,
+    req: GroupMessagePageRequest,
+) -> ResponseResult {
+    let page = req.page();
+    let GroupMessagePageRequest { cursor, limit, mode, gid, uid } = req;
 
-    let uid = cursor.read_i64::<LittleEndian>().unwrap_or(0);
+    info!("{}\tsubmit content\tuid:{}\tcursor:{}\tlimit:{}\tmode:{}\tgid:{}", default_log_pre!(conn.msg.code as i16,uid), uid, cursor, limit, mode, gid);
 
-    if uid <= 0 {
-        error!(
-            "{}\tinvalid uid param.",
-            default_log_pre!(conn.msg.code as i16,""),
-        );
-        let m = "invaild gid param.";
-        return conn.get_general_error(m);
+    match group_page(&conn, gid, uid, page).await {
+        Ok(v) => conn.get_bin_code(MessageStateCode::Ok, "success", v),
+        Err(resp) => resp,
     }
+}
 
-    info!("{}\tsubmit content\tuid:{}\ttimestamp:{}\tlimit:{}\torder:{}\tgid:{}", default_log_pre!(conn.msg.code as i16,uid), uid, timestamp, limit, order, gid);
+#[named]
+async fn group_page(conn: &LocalConn, gid: i64, uid: i64, page: PageQuery) -> Result<GroupMessagePage, ResponseResult> {
+    let res_data = match conn.with_store(move |s| s.group_messages(gid, page)).await {
+        Ok(v) => v,
+        Err(e) => return Err(conn.get_general_error(e.to_string().as_str())),
+    };
 
-    let res_data =
-        match ChatMessage::get_group_message(&slave_db_conn, gid, timestamp, limit as i64, order) {
-            Ok(v) => v,
-            Err(e) => return conn.get_general_error(e.to_string().as_str()),
+    //read up to the newest message of the page, whatever is newer stays unread
+    if let Some(latest) = res_data.messages.last() {
+        let cursor = MessageCursor { created_timestamp: latest.created_timestamp, mid: latest.mid };
+        if let Err(e) = conn.with_store(move |s| s.mark_group_read(gid, uid, cursor)).await {
+            error!("{}\tfailed update user group unread count and latest timestamp:{:?}",
+                   default_log_pre!(conn.msg.code as i16,uid), e
+            );
         };
+    }
 
-    //update group
-    if let Err(e) = ChatGroupsUid::update_unread_count_and_timestamp(&master_db_conn, gid, timestamp, uid)
-    {
-        error!("{}\tfailed update user group unread count and latest timestamp:{:?}",
-               default_log_pre!(conn.msg.code as i16,uid), e
-        );
-    };
-
-    conn.get_bin_code(MessageStateCode::Ok, "success", res_data)
+    Ok(res_data)
 }
 
 #[named]
-pub async fn get_p2p_user_message_content(_clients: Clients, conn: LocalConn) -> ResponseResult {
-    let master_db_conn = conn.db_conn(true);
-    let slave_db_conn = conn.db_conn(false);
-    let mut cursor = std::io::Cursor::new(&conn.msg.body);
+pub async fn get_p2p_user_message_content(
+    _clients: Clients,
+    conn: LocalConn,
+    req: P2pMessageContentRequest,
+) -> ResponseResult {
+    let P2pMessageContentRequest { timestamp, limit, order, send_uid, my_uid } = req;
+    let limit = limit.min(MAX_PAGE_LIMIT);
 
-    let timestamp = cursor.read_i64::<LittleEndian>().unwrap_or(0);
-    let mut limit = cursor.read_i16::<LittleEndian>().unwrap_or(10);
+    info!("{}\tsubmit content\ttimestamp:{}\tlimit:{}\torder:{}\tsend_uid:{}\tmy_uid:{}", default_log_pre!(conn.msg.code as i16,my_uid), timestamp, limit, order, send_uid, my_uid);
 
-    if limit > 50 {
-        limit = 50;
+    let page = PageQuery::from_timestamp(timestamp, order, limit as i64);
+    match p2p_page(&conn, send_uid, my_uid, page).await {
+        Ok(v) => conn.get_bin_code(MessageStateCode::Ok, "success", in_order(v.messages, order)),
+        Err(resp) => resp,
     }
+}
 
-    //0:asc,1:desc
-    let order = cursor.read_i16::<LittleEndian>().unwrap_or(1);
+#[named]
+pub async fn get_p2p_user_message_page(
+    _clients: Clients,
+    conn: LocalConn,
+    req: P2pMessagePageRequest,
+) -> ResponseResult {
+    let page = req.page();
+    let P2pMessagePageRequest { cursor, limit, mode, send_uid, my_uid } = req;
 
-    let send_uid = cursor.read_i64::<LittleEndian>();
-    let send_uid = match send_uid {
-        Ok(uid) => uid,
-        Err(_e) => {
-            error!(
-                "{}\tinvalid send id param.",
-                default_log_pre!(conn.msg.code as i16,""),
-            );
-            let m = "invaild send id param.";
-            return conn.get_general_error(m);
-        }
-    };
-    let my_uid = cursor.read_i64::<LittleEndian>();
-    let my_uid = match my_uid {
-        Ok(uid) => uid,
-        Err(_e) => {
-            error!(
-                "{}\tinvalid self uuid param.",
-                default_log_pre!(conn.msg.code as i16,""),
-            );
-            let m = "invaild uid param.";
-            return conn.get_general_error(m);
-        }
-    };
+    info!("{}\tsubmit content\tcursor:{}\tlimit:{}\tmode:{}\tsend_uid:{}\tmy_uid:{}", default_log_pre!(conn.msg.code as i16,my_uid), cursor, limit, mode, send_uid, my_uid);
 
-    info!("{}\tsubmit content\ttimestamp:{}\tlimit:{}\torder:{}\tsend_uid:{}\tmy_uid:{}", default_log_pre!(conn.msg.code as i16,my_uid), timestamp, limit, order, send_uid, my_uid);
+    match p2p_page(&conn, send_uid, my_uid, page).await {
+        Ok(v) => conn.get_bin_code(MessageStateCode::Ok, "success", v),
+        Err(resp) => resp,
+    }
+}
 
-    let res_data = match ChatMessage::get_p2p_message(
-        &slave_db_conn,
-        send_uid,
-        my_uid,
-        timestamp,
-        limit as i64,
-        order,
-    ) {
+#[named]
+async fn p2p_page(conn: &LocalConn, send_uid: i64, my_uid: i64, page: PageQuery) -> Result<P2pMessagePage, ResponseResult> {
+    let res_data = match conn
+        .with_store(move |s| s.p2p_messages(send_uid, my_uid, page))
+        .await
+    {
         Ok(v) => v,
-        Err(e) => return conn.get_general_error(e.to_string().as_str()),
+        Err(e) => return Err(conn.get_general_error(e.to_string().as_str())),
     };
 
-    //update latest timestamp
-    if let Err(e) = ChatUserUnreadCount::update_user_unread_count(&master_db_conn, my_uid, send_uid) {
-        error!("{}\tfailed update user group unread count and latest timestamp:{:?}",
-               default_log_pre!(conn.msg.code as i16,my_uid), e
-        );
-    };
+    //same as the group page
+    if let Some(latest) = res_data.messages.last() {
+        let cursor = MessageCursor { created_timestamp: latest.created_timestamp, mid: latest.mid };
+        if let Err(e) = conn.with_store(move |s| s.mark_p2p_read(my_uid, send_uid, cursor)).await {
+            error!("{}\tfailed update user unread count and latest timestamp:{:?}",
+                   default_log_pre!(conn.msg.code as i16,my_uid), e
+            );
+        };
+    }
 
-    conn.get_bin_code(MessageStateCode::Ok, "success", res_data)
+    Ok(res_data)
 }
 
-#[named]
-pub async fn get_chat_channel_unread_count(_clients: Clients, conn: LocalConn) -> ResponseResult {
-    let db_conn = conn.db_conn(false);
-    let mut cursor = std::io::Cursor::new(&conn.msg.body);
-
-    let tid = cursor.read_i16::<LittleEndian>();
-    let tid = match tid {
-        Ok(uid) => uid,
-        Err(_e) => {
-            error!(
-                "{}\tinvalid tid param.",
-                default_log_pre!(conn.msg.code as i16,""),
-            );
-            let m = "invaild tid param.";
-            return conn.get_general_error(m);
-        }
-    };
+//pages are oldest first, v1 answers order 1 newest first.
+fn in_order<T>(mut msgs: Vec<T>, order: i16) -> Vec<T> {
+    if order == 1 {
+        msgs.reverse();
+    }
+    msgs
+}
 
-    let dst_id_or_kingdom_timestamp = cursor.read_i64::<LittleEndian>();
-    let dst_id_or_kingdom_timestamp = match dst_id_or_kingdom_timestamp {
-        Ok(uid) => uid,
-        Err(_e) => {
-            error!(
-                "{}\tinvaild dst id or kingdom_timestamp param.",
-                default_log_pre!(conn.msg.code as i16,""),
-            );
-            let m = "invaild dst id or kingdom_timestamp param.";
-            return conn.get_general_error(m);
-        }
-    };
-    let uid = cursor.read_i64::<LittleEndian>();
-    let uid = match uid {
-        Ok(uid) => uid,
-        Err(_e) => {
-            error!(
-                "{}\tinvaild uid param.",
-                default_log_pre!(conn.msg.code as i16,""),
-            );
-            let m = "invaild uid param.";
-            return conn.get_general_error(m);
-        }
-    };
+#[named]
+pub async fn get_chat_channel_unread_count(
+    _clients: Clients,
+    conn: LocalConn,
+    req: ChannelUnreadCountRequest,
+) -> ResponseResult {
+    let ChannelUnreadCountRequest { tid, dst_id_or_kingdom_timestamp, uid } = req;
 
     info!("{}\tsubmit content\ttid:{}\tdst_id_or_kingdom_timestamp:{}\tuid:{}", default_log_pre!(conn.msg.code as i16,uid), tid, dst_id_or_kingdom_timestamp, uid);
 
@@ -697,7 +677,7 @@ pub async fn get_chat_channel_unread_count(_clients: Clients, conn: LocalConn) -
         1 => {
             //kingdom
 
-            let kingdom_id = match User::get_kingdom_id(&db_conn, uid) {
+            let kingdom_id = match conn.with_store(move |s| s.kingdom_id(uid)).await {
                 Ok(v) => v,
                 Err(e) => {
                     error!(
@@ -708,40 +688,34 @@ pub async fn get_chat_channel_unread_count(_clients: Clients, conn: LocalConn) -
                     return conn.get_general_error(e.to_string().as_str());
                 }
             };
-            if let Ok(v) = ChatMessage::get_kingdom_unread_count(
-                &db_conn,
-                kingdom_id,
-                dst_id_or_kingdom_timestamp,
-            ) {
+            let cursor = match kingdom_read_position(&conn, uid, kingdom_id, reported_timestamp(dst_id_or_kingdom_timestamp)).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(
+                        "{}\tget kingdom read position:{:?}",
+                        default_log_pre!(conn.msg.code as i16,uid),
+                        e
+                    );
+                    return conn.get_general_error(e.to_string().as_str());
+                }
+            };
+            if let Ok(v) = conn.with_store(move |s| s.kingdom_unread_count_after(kingdom_id, cursor)).await {
                 unread_count = v;
             }
         }
         2 => {
-            if let Ok(group_info) =
-            ChatGroupsUid::get_groups_info_by_gid(&db_conn, dst_id_or_kingdom_timestamp)
-            {
-                if let Ok(v) = ChatMessage::get_group_unread_count(
-                    &db_conn,
-                    dst_id_or_kingdom_timestamp,
-                    group_info.latest_timestamp,
-                ) {
-                    unread_count = v;
-                }
+            let gid = dst_id_or_kingdom_timestamp;
+            if let Ok(v) = conn.with_store(move |s| s.group_unread_count(gid, uid)).await {
+                unread_count = v;
             }
         }
         3 => {
-            if let Ok(user_unread_count) = ChatUserUnreadCount::get_user_unread_count(
-                &db_conn,
-                uid,
-                dst_id_or_kingdom_timestamp,
-            ) {
+            let send_uid = dst_id_or_kingdom_timestamp;
+            if let Ok(user_unread_count) = conn.with_store(move |s| s.user_unread_count(uid, send_uid)).await {
                 unread_count = user_unread_count as i64;
             };
         }
-        _ => error!(
-            "{}\tinvalid tid param.",
-            default_log_pre!(conn.msg.code as i16,uid),
-        ),
+        _ => {}
     }
 
     let res_data = FrontDisplayChatMessageUnreadCount {
@@ -751,3 +725,94 @@ pub async fn get_chat_channel_unread_count(_clients: Clients, conn: LocalConn) -
 
     conn.get_bin_code(MessageStateCode::Ok, "success", res_data)
 }
+
+//mark a conversation read up to cursor and sync the badge to the user's other devices
+#[named]
+pub async fn mark_read(clients: Clients, conn: LocalConn, req: MarkReadRequest) -> ResponseResult {
+    let MarkReadRequest { tid, dst_id, cursor, uid } = req;
+
+    info!("{}\tsubmit content\ttid:{}\tdst_id:{}\tcursor:{}\tuid:{}", default_log_pre!(conn.msg.code as i16,uid), tid, dst_id, cursor, uid);
+
+    let read_cursor = match MessageCursor::parse(&cursor) {
+        Ok(Some(v)) => v,
+        _ => return conn.get_general_error("invaild cursor param."),
+    };
+
+    let (dst_id, unread_count) = match tid {
+        1 => {
+            let kingdom_id = match conn.with_store(move |s| s.kingdom_id(uid)).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("{}\tget kingdom id error:{:?}", default_log_pre!(conn.msg.code as i16,uid), e);
+                    return conn.get_general_error(e.to_string().as_str());
+                }
+            };
+            let unread_count = match kingdom_read_position(&conn, uid, kingdom_id, Some(read_cursor)).await {
+                Ok(cursor) => conn.with_store(move |s| s.kingdom_unread_count_after(kingdom_id, cursor)).await,
+                Err(e) => Err(e),
+            };
+            (kingdom_id, unread_count)
+        }
+        2 => (dst_id, conn.with_store(move |s| s.mark_group_read(dst_id, uid, read_cursor)).await),
+        _ => (dst_id, conn.with_store(move |s| s.mark_p2p_read(uid, dst_id, read_cursor)).await),
+    };
+
+    let unread_count = match unread_count {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}\tfailed mark read tid:{}\tdst_id:{}\terror:{:?}", default_log_pre!(conn.msg.code as i16,uid), tid, dst_id, e);
+            return conn.get_general_error(e.to_string().as_str());
+        }
+    };
+
+    let total_unread = match conn.with_store(move |s| s.user_unread_total(uid)).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}\tget user unread total error:{:?}", default_log_pre!(conn.msg.code as i16,uid), e);
+            return conn.get_general_error(e.to_string().as_str());
+        }
+    };
+
+    let badge = UnreadBadge {
+        tid,
+        dst_id,
+        read_cursor: cursor,
+        unread_count: unread_count as i32,
+        total_unread: total_unread as i32,
+    };
+
+    publish_unread_badge(&conn, clients, uid as u64, badge.clone()).await;
+
+    conn.get_bin_code(MessageStateCode::Ok, "success", badge)
+}
+
+//older clients keep reporting their local position as a timestamp, 0 reports nothing.
+fn reported_timestamp(timestamp: i64) -> Option<MessageCursor> {
+    if timestamp > 0 {
+        Some(MessageCursor::through(timestamp))
+    } else {
+        None
+    }
+}
+
+//stored kingdom read position of uid, a reported cursor moves it forward first.
+async fn kingdom_read_position(
+    conn: &LocalConn,
+    uid: i64,
+    kingdom_id: i64,
+    reported: Option<MessageCursor>,
+) -> anyhow::Result<MessageCursor> {
+    if let Some(cursor) = reported {
+        //a position in the future would hide every new message
+        let now = Utc::now().timestamp_millis();
+        let cursor = if cursor.created_timestamp > now { MessageCursor::through(now) } else { cursor };
+        conn.with_store(move |s| s.mark_kingdom_read(uid, kingdom_id, cursor)).await?;
+    }
+
+    conn.with_store(move |s| s.kingdom_read_cursor(uid, kingdom_id)).await
+}
+
+//ping, any frame also resets the idle timeout
+pub async fn heartbeat(_clients: Clients, conn: LocalConn) -> ResponseResult {
+    conn.get_bin_code(MessageStateCode::Ok, "pong", "")
+}
@@ -1 +1,4 @@
 pub mod chat;
+pub mod presence;
+pub mod push;
+pub mod request;
\ No newline at end of file
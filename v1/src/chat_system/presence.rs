@@ -0,0 +1,60 @@
+use crate::utils::broker::Broker;
+use crate::utils::thread_pool::ThreadPool;
+use crate::{Clients, PendingPushes, RouterCode};
+use crate::default_log_pre;
+use function_name::named;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+//clean up after a socket closes: drop its registrations and unacked pushes and release
+//the connection in the broker, which marks the user offline once no node holds one.
+#[named]
+pub async fn connection_closed(
+    pool: &ThreadPool,
+    broker: Arc<dyn Broker>,
+    clients: Clients,
+    pending: PendingPushes,
+    conn_id: u64,
+) {
+    let code = RouterCode::ConnectionState as u16;
+
+    let registered = {
+        let mut clients = clients.lock().await;
+        let mut registered = Vec::new();
+
+        clients.retain(|uid, conns| {
+            let before = conns.len();
+            conns.retain(|c| c.session.conn_id != conn_id);
+            if conns.len() < before {
+                registered.push(*uid);
+            }
+            !conns.is_empty()
+        });
+
+        registered
+    };
+
+    if registered.is_empty() {
+        return;
+    }
+
+    {
+        let mut pending = pending.lock().await;
+        for uid in registered.iter() {
+            pending.remove_connection(*uid, conn_id);
+        }
+    }
+
+    //a user stays online while another of its connections is open, on this node or another.
+    for uid in registered.into_iter() {
+        let broker = broker.clone();
+        match pool.run(move || broker.user_offline(uid)).await.and_then(|r| r) {
+            Ok(true) => info!("{}\tuser offline\tconn_id:{}", default_log_pre!(code, uid), conn_id),
+            Ok(false) => {}
+            Err(e) => error!("{}\tfailed mark user offline:{:?}", default_log_pre!(code, uid), e),
+        }
+    }
+}
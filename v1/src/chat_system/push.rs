@@ -0,0 +1,529 @@
+use crate::models::chat_messages::PushChatMessage;
+use crate::utils::common::LengthPrefix;
+use crate::utils::broker::Broker;
+use crate::utils::snowflake::worker_id;
+use crate::utils::store::ChatStore;
+use crate::utils::thread_pool::ThreadPool;
+use crate::{
+    ChatPublishMessage, Clients, Connection, MessageStateCode, PendingPushes, ResponseContext, RouterCode,
+    SocketSender, UnreadBadge,
+};
+use crate::default_log_pre;
+use anyhow::Result;
+use function_name::named;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+pub const DEFAULT_PUSH_ACK_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_PUSH_MAX_ATTEMPTS: u32 = 5;
+pub const DEFAULT_PUSH_MAX_PENDING_PER_USER: usize = 256;
+pub const DEFAULT_PUSH_MAX_PENDING: usize = 100_000;
+//publish tid of a badge update, chat messages use their kind 1..=3.
+pub const UNREAD_BADGE_PUBLISH_TID: u8 = 0;
+
+//a pushed message waiting for the client ack on the push route.
+//conn_ids are the connections of the user that were sent it and did not ack yet.
+#[derive(Debug, Clone)]
+pub struct PendingPush {
+    pub msg: PushChatMessage,
+    pub conn_ids: HashSet<u64>,
+    pub sent_at: Instant,
+    pub attempts: u32,
+}
+
+//unacknowledged pushes of the local connections, uid -> mid -> push.
+//a push is forgotten with its connection, a client catches up through the history and unread routes.
+//past max_per_user the oldest push of the user is forgotten, past max_total new pushes are sent untracked.
+#[derive(Debug)]
+pub struct PendingPushMap {
+    users: HashMap<u64, HashMap<i64, PendingPush>>,
+    total: usize,
+    max_per_user: usize,
+    max_total: usize,
+}
+
+impl PendingPushMap {
+    pub fn new(max_per_user: usize, max_total: usize) -> Self {
+        PendingPushMap {
+            users: HashMap::new(),
+            total: 0,
+            max_per_user,
+            max_total,
+        }
+    }
+
+    //pending pushes over every user.
+    pub fn len(&self) -> usize {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    //connections of uid still waiting to ack mid.
+    pub fn waiting(&self, uid: u64, mid: i64) -> Vec<u64> {
+        let mut conn_ids = self
+            .users
+            .get(&uid)
+            .and_then(|msgs| msgs.get(&mid))
+            .map(|p| p.conn_ids.iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        conn_ids.sort_unstable();
+        conn_ids
+    }
+
+    //returns false when the push is sent untracked.
+    #[named]
+    pub fn track(&mut self, uid: u64, msg: &PushChatMessage, conn_ids: HashSet<u64>) -> bool {
+        let code = RouterCode::PushMessage as u16;
+
+        let msgs = self.users.entry(uid).or_default();
+        if !msgs.contains_key(&msg.mid()) {
+            if msgs.len() >= self.max_per_user {
+                if let Some(oldest) = msgs.keys().min().copied() {
+                    msgs.remove(&oldest);
+                    self.total -= 1;
+                    error!("{}\tforget unacked push mid:{}\tper user limit:{}", default_log_pre!(code, uid), oldest, self.max_per_user);
+                }
+            }
+            if self.total >= self.max_total {
+                if msgs.is_empty() {
+                    self.users.remove(&uid);
+                }
+                error!("{}\tpush mid:{} untracked\tpending limit:{}", default_log_pre!(code, uid), msg.mid(), self.max_total);
+                return false;
+            }
+            self.total += 1;
+        }
+
+        msgs.insert(
+            msg.mid(),
+            PendingPush {
+                msg: msg.clone(),
+                conn_ids,
+                sent_at: Instant::now(),
+                attempts: 1,
+            },
+        );
+
+        true
+    }
+
+    //conn_id acknowledged mid, returns false when it was not waiting for it.
+    pub fn ack(&mut self, uid: u64, conn_id: u64, mid: i64) -> bool {
+        let msgs = match self.users.get_mut(&uid) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        let (acked, done) = match msgs.get_mut(&mid) {
+            Some(p) => (p.conn_ids.remove(&conn_id), p.conn_ids.is_empty()),
+            None => (false, false),
+        };
+
+        if done {
+            msgs.remove(&mid);
+            self.total -= 1;
+        }
+        if msgs.is_empty() {
+            self.users.remove(&uid);
+        }
+
+        acked
+    }
+
+    //forget what conn_id of uid did not ack, its socket is gone.
+    pub fn remove_connection(&mut self, uid: u64, conn_id: u64) {
+        let msgs = match self.users.get_mut(&uid) {
+            Some(v) => v,
+            None => return,
+        };
+
+        let before = msgs.len();
+        msgs.retain(|_, p| {
+            p.conn_ids.remove(&conn_id);
+            !p.conn_ids.is_empty()
+        });
+        self.total -= before - msgs.len();
+
+        if msgs.is_empty() {
+            self.users.remove(&uid);
+        }
+    }
+
+    //drop pushes resent max_attempts times and return the ones to resend now,
+    //each with the connections still waiting for it.
+    #[named]
+    pub fn due(&mut self, ack_timeout: Duration, max_attempts: u32) -> Vec<(u64, Vec<u64>, PushChatMessage)> {
+        let code = RouterCode::PushMessage as u16;
+        let mut resend = Vec::new();
+        let mut dropped = 0;
+
+        for (uid, msgs) in self.users.iter_mut() {
+            let before = msgs.len();
+            msgs.retain(|mid, p| {
+                if p.attempts >= max_attempts && p.sent_at.elapsed() >= ack_timeout {
+                    error!("{}\tdrop unacked push mid:{}\tattempts:{}", default_log_pre!(code, uid), mid, p.attempts);
+                    return false;
+                }
+                true
+            });
+            dropped += before - msgs.len();
+
+            for p in msgs.values_mut() {
+                if p.sent_at.elapsed() >= ack_timeout {
+                    p.attempts += 1;
+                    p.sent_at = Instant::now();
+                    resend.push((*uid, p.conn_ids.iter().copied().collect(), p.msg.clone()));
+                }
+            }
+        }
+        self.users.retain(|_, msgs| !msgs.is_empty());
+        self.total -= dropped;
+
+        resend
+    }
+}
+
+//a badge update on the publish channel, the origin connection already has it in its response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnreadBadgePublish {
+    pub worker_id: u16,
+    pub conn_id: u64,
+    pub badge: UnreadBadge,
+}
+
+//publish a committed chat message so every node delivers it to its own online recipients.
+#[named]
+pub async fn publish_chat_message(
+    conn: &Connection,
+    clients: Clients,
+    from_uid: u64,
+    to_uid: u64,
+    msg: PushChatMessage,
+) {
+    let code = RouterCode::PushMessage as u16;
+
+    let content = match serde_json::to_string(&msg) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}\tfailed encode publish message mid:{}\terror:{:?}", default_log_pre!(code, from_uid), msg.mid(), e);
+            return;
+        }
+    };
+
+    let data = ChatPublishMessage {
+        tid: msg.kind() as u8,
+        mid: msg.mid(),
+        from_uid,
+        to_uid,
+        content,
+    };
+
+    if let Err(e) = conn.with_broker(move |broker| broker.publish(&data)).await {
+        //keep local recipients real-time even when the broker is unavailable.
+        error!("{}\tfailed publish message mid:{}\terror:{:?}", default_log_pre!(code, from_uid), msg.mid(), e);
+        tokio::spawn(deliver_published_message(
+            clients,
+            conn.pending_pushes.clone(),
+            conn.pool.clone(),
+            conn.store.clone(),
+            from_uid,
+            to_uid,
+            msg,
+        ));
+    }
+}
+
+//run the channel subscriber on its own thread and deliver every published message to local clients.
+#[named]
+pub fn subscribe_chat_messages(
+    broker: Arc<dyn Broker>,
+    clients: Clients,
+    pending: PendingPushes,
+    pool: Arc<ThreadPool>,
+    store: Arc<dyn ChatStore>,
+) {
+    let code = RouterCode::PushMessage as u16;
+    let (sender, mut receiver) = mpsc::unbounded_channel::<ChatPublishMessage>();
+
+    std::thread::spawn(move || loop {
+        match broker.subscribe(&sender) {
+            Ok(()) => return,
+            Err(e) => {
+                error!("{}\tchat publish channel subscriber error:{:?}", default_log_pre!(code, ""), e);
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(data) = receiver.recv().await {
+            if data.tid == UNREAD_BADGE_PUBLISH_TID {
+                match serde_json::from_str::<UnreadBadgePublish>(&data.content) {
+                    Ok(v) => deliver_unread_badge(clients.clone(), data.to_uid, v).await,
+                    Err(e) => error!("{}\tinvalid publish badge\terror:{:?}", default_log_pre!(code, data.to_uid), e),
+                }
+                continue;
+            }
+
+            let msg: PushChatMessage = match serde_json::from_str(&data.content) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("{}\tinvalid publish message mid:{}\terror:{:?}", default_log_pre!(code, data.from_uid), data.mid, e);
+                    continue;
+                }
+            };
+
+            deliver_published_message(
+                clients.clone(),
+                pending.clone(),
+                pool.clone(),
+                store.clone(),
+                data.from_uid,
+                data.to_uid,
+                msg,
+            )
+            .await;
+        }
+    });
+}
+
+//publish the badge after a read so every node pushes it to the user's other connections.
+#[named]
+pub async fn publish_unread_badge(conn: &Connection, clients: Clients, uid: u64, badge: UnreadBadge) {
+    let code = RouterCode::UnreadBadge as u16;
+
+    let publish = UnreadBadgePublish {
+        worker_id: worker_id(),
+        conn_id: conn.session.conn_id,
+        badge,
+    };
+
+    let content = match serde_json::to_string(&publish) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}\tfailed encode publish badge\terror:{:?}", default_log_pre!(code, uid), e);
+            return;
+        }
+    };
+
+    let data = ChatPublishMessage {
+        tid: UNREAD_BADGE_PUBLISH_TID,
+        mid: 0,
+        from_uid: uid,
+        to_uid: uid,
+        content,
+    };
+
+    if let Err(e) = conn.with_broker(move |broker| broker.publish(&data)).await {
+        error!("{}\tfailed publish badge\terror:{:?}", default_log_pre!(code, uid), e);
+        deliver_unread_badge(clients, uid, publish).await;
+    }
+}
+
+//push the badge to every local connection of uid except the one that read.
+#[named]
+pub async fn deliver_unread_badge(clients: Clients, uid: u64, publish: UnreadBadgePublish) {
+    let code = RouterCode::UnreadBadge as u16;
+    let local = publish.worker_id == worker_id();
+
+    let targets = user_targets(&clients, uid)
+        .await
+        .into_iter()
+        .filter(|(conn_id, ..)| !(local && *conn_id == publish.conn_id));
+
+    for (_, session_id, prefix, socket) in targets {
+        let resp = match ResponseContext::get_bincode_with(
+            code,
+            session_id,
+            MessageStateCode::Ok,
+            "badge",
+            publish.badge.clone(),
+            prefix,
+        ) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{}\tfailed encode badge:{:?}", default_log_pre!(code, uid), e);
+                return;
+            }
+        };
+
+        if socket.send(resp).is_err() {
+            error!("{}\tfailed push badge\tconnection closed", default_log_pre!(code, uid));
+        }
+    }
+}
+
+//conn_id, session id, length prefix and socket of every local connection of uid.
+async fn user_targets(clients: &Clients, uid: u64) -> Vec<(u64, u64, LengthPrefix, SocketSender)> {
+    match clients.lock().await.get(&uid) {
+        Some(conns) => conns
+            .iter()
+            .map(|c| (c.session.conn_id, c.msg.session_id, c.length_prefix(), c.socket.clone()))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+//resolve which locally connected users receive the message, then push it to them.
+#[named]
+pub async fn deliver_published_message(
+    clients: Clients,
+    pending: PendingPushes,
+    pool: Arc<ThreadPool>,
+    store: Arc<dyn ChatStore>,
+    from_uid: u64,
+    to_uid: u64,
+    msg: PushChatMessage,
+) {
+    let code = RouterCode::PushMessage as u16;
+
+    let online = clients
+        .lock()
+        .await
+        .keys()
+        .map(|uid| *uid as i64)
+        .collect::<Vec<_>>();
+
+    if online.is_empty() {
+        return;
+    }
+
+    let recipients = {
+        let msg = msg.clone();
+        pool.run(move || get_recipients(store.as_ref(), online, from_uid, to_uid, &msg))
+    };
+
+    let uids = match recipients.await.and_then(|r| r) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}\tfailed get push recipients mid:{}\terror:{:?}", default_log_pre!(code, from_uid), msg.mid(), e);
+            return;
+        }
+    };
+
+    if uids.is_empty() {
+        return;
+    }
+
+    deliver_chat_message(clients, pending, uids, msg).await;
+}
+
+fn get_recipients(
+    store: &dyn ChatStore,
+    online: Vec<i64>,
+    from_uid: u64,
+    to_uid: u64,
+    msg: &PushChatMessage,
+) -> Result<Vec<u64>> {
+    let uids = match msg {
+        PushChatMessage::Kingdom(_) => store.server_uids(to_uid as i32, online)?,
+        PushChatMessage::Group(_) => store
+            .group_member_uids(to_uid as i64)?
+            .into_iter()
+            .filter(|uid| online.contains(uid))
+            .collect(),
+        PushChatMessage::P2p(_) => vec![to_uid as i64],
+    };
+
+    Ok(uids
+        .into_iter()
+        .map(|uid| uid as u64)
+        .filter(|uid| *uid != from_uid)
+        .collect())
+}
+
+pub async fn deliver_chat_message(
+    clients: Clients,
+    pending: PendingPushes,
+    uids: Vec<u64>,
+    msg: PushChatMessage,
+) {
+    for uid in uids.into_iter() {
+        let targets = user_targets(&clients, uid).await;
+        if targets.is_empty() {
+            continue;
+        }
+
+        //track before writing so a fast ack can not race the insert.
+        let conn_ids = targets.iter().map(|(conn_id, ..)| *conn_id).collect();
+        pending.lock().await.track(uid, &msg, conn_ids);
+
+        for (_, session_id, prefix, socket) in targets.iter() {
+            write_push(uid, *session_id, *prefix, socket, &msg);
+        }
+    }
+}
+
+//remove an acknowledged message of one connection, returns false when it was not waiting for mid.
+pub async fn ack_chat_message(pending: &PendingPushes, uid: u64, conn_id: u64, mid: i64) -> bool {
+    pending.lock().await.ack(uid, conn_id, mid)
+}
+
+//periodically resend pushes whose ack did not arrive in time to the connections that did not ack,
+//giving up after max attempts.
+pub async fn redeliver_unacked_pushes(
+    clients: Clients,
+    pending: PendingPushes,
+    ack_timeout: Duration,
+    max_attempts: u32,
+) {
+    let mut interval = tokio::time::interval(ack_timeout);
+
+    loop {
+        interval.tick().await;
+
+        let resend = pending.lock().await.due(ack_timeout, max_attempts);
+
+        for (uid, conn_ids, msg) in resend.into_iter() {
+            let targets = user_targets(&clients, uid)
+                .await
+                .into_iter()
+                .filter(|(conn_id, ..)| conn_ids.contains(conn_id));
+
+            for (_, session_id, prefix, socket) in targets {
+                write_push(uid, session_id, prefix, &socket, &msg);
+            }
+        }
+    }
+}
+
+#[named]
+fn write_push(
+    uid: u64,
+    session_id: u64,
+    prefix: LengthPrefix,
+    socket: &SocketSender,
+    msg: &PushChatMessage,
+) -> bool {
+    let code = RouterCode::PushMessage as u16;
+
+    let resp = match ResponseContext::get_bincode_with(
+        code,
+        session_id,
+        MessageStateCode::Ok,
+        "push",
+        msg.clone(),
+        prefix,
+    ) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}\tfialed encode push message:{:?}\t", default_log_pre!(code, uid), e);
+            return false;
+        }
+    };
+
+    if socket.send(resp).is_err() {
+        error!("{}\tfailed push message mid:{}\tconnection closed", default_log_pre!(code, uid), msg.mid());
+        return false;
+    }
+
+    info!("{}\tpushed message mid:{}", default_log_pre!(code, uid), msg.mid());
+    true
+}
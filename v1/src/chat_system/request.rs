@@ -0,0 +1,354 @@
+use crate::models::chat_messages::{MessageCursor, PageMode, PageQuery};
+use crate::utils::common::LengthPrefix;
+use crate::utils::request::{ensure, RequestBody};
+use crate::{BinaryDecode, BinaryEncode};
+use anyhow::{anyhow, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
+
+pub const MAX_PAGE_LIMIT: i16 = 50;
+pub const MAX_CLIENT_MSG_ID_LENGTH: usize = 64;
+
+fn validate_order(limit: i16, order: i16) -> Result<()> {
+    ensure(limit > 0, "invaild limit param.")?;
+    //0:asc,1:desc
+    ensure(order == 0 || order == 1, "invaild order param.")
+}
+
+fn validate_page(cursor: &str, limit: i16, mode: i16) -> Result<()> {
+    ensure(limit > 0, "invaild limit param.")?;
+    PageQuery::new(cursor, mode, limit as i64).map(|_| ())
+}
+
+//only called on a validated request, every field already parsed once.
+fn page_query(cursor: &str, limit: i16, mode: i16) -> PageQuery {
+    PageQuery {
+        cursor: MessageCursor::parse(cursor).unwrap_or(None),
+        mode: PageMode::from_i16(mode).unwrap_or(PageMode::Before),
+        limit: limit.min(MAX_PAGE_LIMIT) as i64,
+    }
+}
+
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct ConnectionStateRequest {
+    pub uid: u64,
+    pub token: String,
+}
+
+impl RequestBody for ConnectionStateRequest {
+    fn validate(&self) -> Result<()> {
+        ensure(self.uid > 0, "invaild user param.")?;
+        ensure(!self.token.is_empty(), "invaild token param.")
+    }
+}
+
+//uid, tid(u8), dst_id, msg_type(u16), content length(u16, u32 in large payload mode) then the utf8 content.
+//an optional client message id string may follow, retries carrying the same id are stored once.
+#[derive(Debug)]
+pub struct SendMessageRequest {
+    pub uid: u64,
+    pub tid: u8,
+    pub dst_id: u64,
+    pub msg_type: u16,
+    pub content: String,
+    pub client_msg_id: String,
+}
+
+impl<'a> BinaryDecode<'a> for SendMessageRequest {
+    fn decode_with(
+        cursor: &mut Cursor<&'a [u8]>,
+        bytes: &'a [u8],
+        prefix: LengthPrefix,
+    ) -> Result<SendMessageRequest> {
+        let uid = cursor.read_u64::<LittleEndian>()?;
+        let tid = cursor.read_u8()?;
+        let dst_id = cursor.read_u64::<LittleEndian>()?;
+        let msg_type = cursor.read_u16::<LittleEndian>()?;
+        let content_length = match prefix {
+            LengthPrefix::I16 => cursor.read_u16::<LittleEndian>()? as usize,
+            LengthPrefix::U32 => cursor.read_u32::<LittleEndian>()? as usize,
+        };
+
+        let start = cursor.position() as usize;
+        let content = bytes
+            .get(start..start + content_length)
+            .ok_or_else(|| anyhow!("content length out of range."))?;
+        cursor.set_position((start + content_length) as u64);
+
+        //older clients end the body with the content
+        let client_msg_id = if (cursor.position() as usize) < bytes.len() {
+            prefix.read_string(cursor, bytes)?
+        } else {
+            String::new()
+        };
+
+        Ok(SendMessageRequest {
+            uid,
+            tid,
+            dst_id,
+            msg_type,
+            content: std::str::from_utf8(content)?.to_string(),
+            client_msg_id,
+        })
+    }
+}
+
+impl BinaryEncode for SendMessageRequest {
+    fn encode_with(&self, prefix: LengthPrefix) -> Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+
+        encoded.write_u64::<LittleEndian>(self.uid)?;
+        encoded.write_u8(self.tid)?;
+        encoded.write_u64::<LittleEndian>(self.dst_id)?;
+        encoded.write_u16::<LittleEndian>(self.msg_type)?;
+        match prefix {
+            LengthPrefix::I16 => {
+                if self.content.len() > u16::MAX as usize {
+                    return Err(anyhow!("content length {} exceeds {}.", self.content.len(), u16::MAX));
+                }
+                encoded.write_u16::<LittleEndian>(self.content.len() as u16)?;
+            }
+            LengthPrefix::U32 => encoded.write_u32::<LittleEndian>(self.content.len() as u32)?,
+        }
+        encoded.extend_from_slice(self.content.as_bytes());
+        if !self.client_msg_id.is_empty() {
+            prefix.write_string(&mut encoded, &self.client_msg_id)?;
+        }
+
+        //set item length
+        encoded.encode_with(prefix)
+    }
+}
+
+impl RequestBody for SendMessageRequest {
+    fn validate(&self) -> Result<()> {
+        ensure(self.uid > 0, "invaild uid param.")?;
+        ensure((1..=4).contains(&self.tid), "invaild tid param.")?;
+        ensure(!self.content.is_empty(), "empty content.")?;
+        ensure(self.client_msg_id.len() <= MAX_CLIENT_MSG_ID_LENGTH, "invaild client message id param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.uid)
+    }
+}
+
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct PushAckRequest {
+    pub uid: u64,
+    pub mid: i64,
+}
+
+impl RequestBody for PushAckRequest {
+    fn validate(&self) -> Result<()> {
+        ensure(self.uid > 0, "invaild uid param.")?;
+        ensure(self.mid > 0, "invaild mid param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.uid)
+    }
+}
+
+//kingdom_read_timestamp only moves the stored kingdom read position forward, 0 leaves it.
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct UserUnreadCountRequest {
+    pub kingdom_read_timestamp: i64,
+    pub uid: i64,
+}
+
+impl RequestBody for UserUnreadCountRequest {
+    fn validate(&self) -> Result<()> {
+        ensure(self.kingdom_read_timestamp >= 0, "invaild timestamp param.")?;
+        ensure(self.uid > 0, "invaild user param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.uid as u64)
+    }
+}
+
+//v1 history, order 0:asc after timestamp,1:desc before timestamp.
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct KingdomMessageContentRequest {
+    pub timestamp: i64,
+    pub limit: i16,
+    pub order: i16,
+    pub uid: i64,
+}
+
+impl RequestBody for KingdomMessageContentRequest {
+    fn validate(&self) -> Result<()> {
+        validate_order(self.limit, self.order)?;
+        ensure(self.uid > 0, "invaild user param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.uid as u64)
+    }
+}
+
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct GroupMessageContentRequest {
+    pub timestamp: i64,
+    pub limit: i16,
+    pub order: i16,
+    pub gid: i64,
+    pub uid: i64,
+}
+
+impl RequestBody for GroupMessageContentRequest {
+    fn validate(&self) -> Result<()> {
+        validate_order(self.limit, self.order)?;
+        ensure(self.gid > 0, "invaild gid param.")?;
+        ensure(self.uid > 0, "invaild uid param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.uid as u64)
+    }
+}
+
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct P2pMessageContentRequest {
+    pub timestamp: i64,
+    pub limit: i16,
+    pub order: i16,
+    pub send_uid: i64,
+    pub my_uid: i64,
+}
+
+impl RequestBody for P2pMessageContentRequest {
+    fn validate(&self) -> Result<()> {
+        validate_order(self.limit, self.order)?;
+        ensure(self.send_uid > 0, "invaild send id param.")?;
+        ensure(self.my_uid > 0, "invaild uid param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.my_uid as u64)
+    }
+}
+
+//CURSOR_PAGE_VERSION history, the same routes answer with a page.
+//cursor from a previous page, empty for either end. mode 0:after,1:before,2:around the cursor.
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct KingdomMessagePageRequest {
+    pub cursor: String,
+    pub limit: i16,
+    pub mode: i16,
+    pub uid: i64,
+}
+
+impl KingdomMessagePageRequest {
+    pub fn page(&self) -> PageQuery {
+        page_query(&self.cursor, self.limit, self.mode)
+    }
+}
+
+impl RequestBody for KingdomMessagePageRequest {
+    fn validate(&self) -> Result<()> {
+        validate_page(&self.cursor, self.limit, self.mode)?;
+        ensure(self.uid > 0, "invaild user param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.uid as u64)
+    }
+}
+
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct GroupMessagePageRequest {
+    pub cursor: String,
+    pub limit: i16,
+    pub mode: i16,
+    pub gid: i64,
+    pub uid: i64,
+}
+
+impl GroupMessagePageRequest {
+    pub fn page(&self) -> PageQuery {
+        page_query(&self.cursor, self.limit, self.mode)
+    }
+}
+
+impl RequestBody for GroupMessagePageRequest {
+    fn validate(&self) -> Result<()> {
+        validate_page(&self.cursor, self.limit, self.mode)?;
+        ensure(self.gid > 0, "invaild gid param.")?;
+        ensure(self.uid > 0, "invaild uid param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.uid as u64)
+    }
+}
+
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct P2pMessagePageRequest {
+    pub cursor: String,
+    pub limit: i16,
+    pub mode: i16,
+    pub send_uid: i64,
+    pub my_uid: i64,
+}
+
+impl P2pMessagePageRequest {
+    pub fn page(&self) -> PageQuery {
+        page_query(&self.cursor, self.limit, self.mode)
+    }
+}
+
+impl RequestBody for P2pMessagePageRequest {
+    fn validate(&self) -> Result<()> {
+        validate_page(&self.cursor, self.limit, self.mode)?;
+        ensure(self.send_uid > 0, "invaild send id param.")?;
+        ensure(self.my_uid > 0, "invaild uid param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.my_uid as u64)
+    }
+}
+
+//for the kingdom the timestamp is a reported read position like UserUnreadCountRequest.
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct ChannelUnreadCountRequest {
+    pub tid: i16,
+    pub dst_id_or_kingdom_timestamp: i64,
+    pub uid: i64,
+}
+
+impl RequestBody for ChannelUnreadCountRequest {
+    fn validate(&self) -> Result<()> {
+        ensure((1..=3).contains(&self.tid), "invaild tid param.")?;
+        ensure(self.uid > 0, "invaild uid param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.uid as u64)
+    }
+}
+
+//tid 1:kingdom,2:group,3:p2p. dst_id is the gid or the peer uid, ignored for the kingdom.
+//cursor is the newest message read, everything up to it counts as read.
+#[derive(Debug, BinaryEncode, BinaryDecode)]
+pub struct MarkReadRequest {
+    pub tid: i16,
+    pub dst_id: i64,
+    pub cursor: String,
+    pub uid: i64,
+}
+
+impl RequestBody for MarkReadRequest {
+    fn validate(&self) -> Result<()> {
+        ensure((1..=3).contains(&self.tid), "invaild tid param.")?;
+        ensure(self.tid == 1 || self.dst_id > 0, "invaild dst id param.")?;
+        ensure(matches!(MessageCursor::parse(&self.cursor), Ok(Some(_))), "invaild cursor param.")?;
+        ensure(self.uid > 0, "invaild uid param.")
+    }
+
+    fn claimed_uid(&self) -> Option<u64> {
+        Some(self.uid as u64)
+    }
+}
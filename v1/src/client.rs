@@ -0,0 +1,527 @@
+use crate::chat_system::request::{
+    ChannelUnreadCountRequest, ConnectionStateRequest, GroupMessageContentRequest,
+    GroupMessagePageRequest, KingdomMessageContentRequest, KingdomMessagePageRequest,
+    MarkReadRequest, P2pMessageContentRequest, P2pMessagePageRequest, PushAckRequest,
+    SendMessageRequest, UserUnreadCountRequest,
+};
+use crate::models::chat_messages::{
+    FrontDisplayChatMessageUnreadCount, FrontDisplayP2pChatMessageCount, GroupMessagePage,
+    KingdomMessagePage, P2pMessagePage,
+};
+use crate::utils::codec::{ResponseCodec, ResponseFrame};
+use crate::utils::common::{decode_item, LengthPrefix, CURSOR_PAGE_VERSION};
+use crate::utils::request::encode_body;
+use crate::{
+    BinaryDecode, BinaryEncode, ChatMessageUnReadCount, FrontDisplayGroupChatMessage,
+    FrontDisplayKingdomChatMessage, FrontDisplayP2pChatMessage, MessageCodec, MessageStateCode,
+    PushChatMessage, RequestFrame, RouterCode, SigningKey, SigningKeys, UnreadBadge,
+    DEFAULT_MAX_FRAME_LENGTH,
+};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+use tokio_util::codec::{FramedRead, FramedWrite};
+use tracing::error;
+
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+type PendingRequests = Arc<Mutex<HashMap<(u16, u64), oneshot::Sender<ResponseFrame>>>>;
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub signing_key: SigningKey,
+    //Message.version sent with every request, LARGE_PAYLOAD_VERSION switches to u32 lengths.
+    pub version: u8,
+    pub request_timeout: Duration,
+    pub max_frame_length: usize,
+}
+
+impl ClientConfig {
+    pub fn new(signing_key: SigningKey) -> Self {
+        ClientConfig {
+            signing_key,
+            version: 1,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+        }
+    }
+}
+
+//a response whose state was not Ok, callers can downcast the anyhow error to it.
+#[derive(Debug, Clone)]
+pub struct ResponseError {
+    pub code: u16,
+    pub state: u16,
+    pub msg: String,
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "code:{} state:{} msg:{}", self.code, self.state, self.msg)
+    }
+}
+
+impl std::error::Error for ResponseError {}
+
+//a decoded response: the msg string and the still encoded data after it.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub code: u16,
+    pub session_id: u64,
+    pub state: u16,
+    pub msg: String,
+    data: Vec<u8>,
+    prefix: LengthPrefix,
+}
+
+impl Response {
+    fn from_frame(frame: ResponseFrame, prefix: LengthPrefix) -> Result<Self> {
+        let mut cursor = Cursor::new(&frame.body[..]);
+        let msg = prefix.read_string(&mut cursor, &frame.body)?;
+        let data = frame.body[cursor.position() as usize..].to_vec();
+
+        Ok(Response {
+            code: frame.code,
+            session_id: frame.session_id,
+            state: frame.state,
+            msg,
+            data,
+            prefix,
+        })
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.state == MessageStateCode::Ok as u16
+    }
+
+    pub fn error(&self) -> ResponseError {
+        ResponseError {
+            code: self.code,
+            state: self.state,
+            msg: self.msg.clone(),
+        }
+    }
+
+    //a single struct, written with its item length.
+    pub fn item<T: for<'a> BinaryDecode<'a>>(&self) -> Result<T> {
+        let mut cursor = Cursor::new(&self.data[..]);
+        decode_item(&mut cursor, &self.data, self.prefix)
+    }
+
+    //anything decoding its own length, e.g. a list.
+    pub fn data<T: for<'a> BinaryDecode<'a>>(&self) -> Result<T> {
+        let mut cursor = Cursor::new(&self.data[..]);
+        T::decode_with(&mut cursor, &self.data, self.prefix)
+    }
+}
+
+//async client for the chat protocol over plain tcp.
+//responses are matched to requests by (code, session_id), server pushes go to the push and badge streams.
+pub struct ChatClient {
+    keys: SigningKeys,
+    key_id: u16,
+    version: u8,
+    request_timeout: Duration,
+    next_session_id: AtomicU64,
+    uid: AtomicU64,
+    outbound: mpsc::UnboundedSender<RequestFrame>,
+    pending: PendingRequests,
+    pushes: Mutex<Option<mpsc::UnboundedReceiver<PushChatMessage>>>,
+    badges: Mutex<Option<mpsc::UnboundedReceiver<UnreadBadge>>>,
+}
+
+impl ChatClient {
+    pub async fn connect(addr: SocketAddr, config: ClientConfig) -> Result<ChatClient> {
+        let stream = TcpStream::connect(addr).await?;
+        let (recv, send) = tokio::io::split(stream);
+        let prefix = LengthPrefix::for_version(config.version);
+
+        let (outbound, mut requests) = mpsc::unbounded_channel::<RequestFrame>();
+        let mut writer = FramedWrite::new(send, MessageCodec::new(config.max_frame_length));
+        tokio::spawn(async move {
+            while let Some(frame) = requests.recv().await {
+                if let Err(e) = writer.send(frame).await {
+                    error!("chat client failed write frame:{}", e);
+                    return;
+                }
+            }
+            //client dropped, let the server see the close.
+            let _ = writer.get_mut().shutdown().await;
+        });
+
+        let pending = PendingRequests::default();
+        let (push_sender, pushes) = mpsc::unbounded_channel::<PushChatMessage>();
+        let (badge_sender, badges) = mpsc::unbounded_channel::<UnreadBadge>();
+        let mut reader = FramedRead::new(recv, ResponseCodec::new(config.max_frame_length));
+        let reader_pending = pending.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = reader.next().await {
+                let frame = match frame {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("chat client failed read frame:{}", e);
+                        break;
+                    }
+                };
+
+                let waiter = reader_pending
+                    .lock()
+                    .unwrap()
+                    .remove(&(frame.code, frame.session_id));
+
+                match waiter {
+                    Some(waiter) => {
+                        let _ = waiter.send(frame);
+                    }
+                    None if frame.code == RouterCode::PushMessage as u16 => {
+                        let msg = Response::from_frame(frame, prefix)
+                            .and_then(|resp| resp.item::<PushChatMessage>());
+                        match msg {
+                            Ok(msg) => {
+                                let _ = push_sender.send(msg);
+                            }
+                            Err(e) => error!("chat client invalid push message:{:?}", e),
+                        }
+                    }
+                    None if frame.code == RouterCode::UnreadBadge as u16 => {
+                        let badge = Response::from_frame(frame, prefix)
+                            .and_then(|resp| resp.item::<UnreadBadge>());
+                        match badge {
+                            Ok(badge) => {
+                                let _ = badge_sender.send(badge);
+                            }
+                            Err(e) => error!("chat client invalid badge:{:?}", e),
+                        }
+                    }
+                    None => error!(
+                        "chat client unexpected response code:{}\tsession_id:{}",
+                        frame.code, frame.session_id
+                    ),
+                }
+            }
+
+            //wake every waiting request with a closed connection.
+            reader_pending.lock().unwrap().clear();
+        });
+
+        Ok(ChatClient {
+            key_id: config.signing_key.key_id,
+            keys: SigningKeys::new(vec![config.signing_key]),
+            version: config.version,
+            request_timeout: config.request_timeout,
+            next_session_id: AtomicU64::new(1),
+            uid: AtomicU64::new(0),
+            outbound,
+            pending,
+            pushes: Mutex::new(Some(pushes)),
+            badges: Mutex::new(Some(badges)),
+        })
+    }
+
+    pub fn length_prefix(&self) -> LengthPrefix {
+        LengthPrefix::for_version(self.version)
+    }
+
+    //uid bound by login, 0 before it.
+    pub fn uid(&self) -> u64 {
+        self.uid.load(Ordering::SeqCst)
+    }
+
+    //messages the server pushes to this user, can be taken once.
+    pub fn pushes(&self) -> Option<mpsc::UnboundedReceiver<PushChatMessage>> {
+        self.pushes.lock().unwrap().take()
+    }
+
+    //badges after this user read a conversation on another device, can be taken once.
+    pub fn badges(&self) -> Option<mpsc::UnboundedReceiver<UnreadBadge>> {
+        self.badges.lock().unwrap().take()
+    }
+
+    //sign and send one request, then wait for the response with the same code and session id.
+    pub async fn request(&self, code: RouterCode, body: Vec<u8>) -> Result<Response> {
+        self.request_version(code, self.version, body).await
+    }
+
+    //request at another Message.version than the client's, the body must use its length prefix.
+    pub async fn request_version(&self, code: RouterCode, version: u8, body: Vec<u8>) -> Result<Response> {
+        let code = code as u16;
+        let session_id = self.next_session_id.fetch_add(1, Ordering::SeqCst);
+        let timestamp = Utc::now().timestamp() as u64;
+        let signature = self.keys.sign(self.key_id, code, version, session_id, timestamp, &body)?;
+
+        let (waiter, resp) = oneshot::channel();
+        self.pending.lock().unwrap().insert((code, session_id), waiter);
+
+        let frame = RequestFrame {
+            code,
+            version,
+            key_id: self.key_id,
+            session_id,
+            signature,
+            timestamp,
+            body,
+        };
+
+        if self.outbound.send(frame).is_err() {
+            self.pending.lock().unwrap().remove(&(code, session_id));
+            return Err(anyhow!("connection closed."));
+        }
+
+        let frame = match timeout(self.request_timeout, resp).await {
+            Ok(Ok(v)) => v,
+            Ok(Err(_)) => return Err(anyhow!("connection closed.")),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&(code, session_id));
+                return Err(anyhow!("request timeout code:{}\tsession_id:{}", code, session_id));
+            }
+        };
+
+        Response::from_frame(frame, LengthPrefix::for_version(version))
+    }
+
+    //request with a typed body, non Ok states become a ResponseError.
+    async fn call<T: BinaryEncode>(&self, code: RouterCode, req: &T) -> Result<Response> {
+        self.call_version(code, self.version, req).await
+    }
+
+    async fn call_version<T: BinaryEncode>(&self, code: RouterCode, version: u8, req: &T) -> Result<Response> {
+        let body = encode_body(req, LengthPrefix::for_version(version))?;
+        let resp = self.request_version(code, version, body).await?;
+
+        if !resp.is_ok() {
+            return Err(resp.error().into());
+        }
+
+        Ok(resp)
+    }
+
+    async fn call_body(&self, code: RouterCode, body: Vec<u8>) -> Result<Response> {
+        let resp = self.request(code, body).await?;
+
+        if !resp.is_ok() {
+            return Err(resp.error().into());
+        }
+
+        Ok(resp)
+    }
+
+    pub async fn login(&self, uid: u64, token: &str) -> Result<()> {
+        let req = ConnectionStateRequest {
+            uid,
+            token: token.to_string(),
+        };
+        self.call(RouterCode::ConnectionState, &req).await?;
+        self.uid.store(uid, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    pub async fn heartbeat(&self) -> Result<()> {
+        self.call_body(RouterCode::Heartbeat, Vec::new()).await?;
+        Ok(())
+    }
+
+    //tid 1:kingdom(dst_id is the server id),2:group,3:p2p.
+    pub async fn send_message(
+        &self,
+        tid: u8,
+        dst_id: u64,
+        msg_type: u16,
+        content: &str,
+    ) -> Result<FrontDisplayP2pChatMessageCount> {
+        self.send_message_with_id(tid, dst_id, msg_type, content, "").await
+    }
+
+    //resending with the same client_msg_id returns the first stored message instead of a new one.
+    pub async fn send_message_with_id(
+        &self,
+        tid: u8,
+        dst_id: u64,
+        msg_type: u16,
+        content: &str,
+        client_msg_id: &str,
+    ) -> Result<FrontDisplayP2pChatMessageCount> {
+        let req = SendMessageRequest {
+            uid: self.uid(),
+            tid,
+            dst_id,
+            msg_type,
+            content: content.to_string(),
+            client_msg_id: client_msg_id.to_string(),
+        };
+
+        self.call(RouterCode::SendMessage, &req).await?.item()
+    }
+
+    //acknowledge a pushed message so the server stops redelivering it.
+    pub async fn ack_push(&self, mid: i64) -> Result<()> {
+        let req = PushAckRequest {
+            uid: self.uid(),
+            mid,
+        };
+        self.call(RouterCode::PushMessage, &req).await?;
+
+        Ok(())
+    }
+
+    //kingdom unread counts follow the read position kept by the server, 0 reports none.
+    pub async fn unread_counts(&self, kingdom_read_timestamp: i64) -> Result<ChatMessageUnReadCount> {
+        let req = UserUnreadCountRequest {
+            kingdom_read_timestamp,
+            uid: self.uid() as i64,
+        };
+
+        self.call(RouterCode::GetUserUnReadMessageCount, &req).await?.item()
+    }
+
+    //v1 history, order 0:asc after timestamp,1:desc before timestamp, timestamp 0 for either end.
+    pub async fn fetch_kingdom_messages(
+        &self,
+        timestamp: i64,
+        limit: i16,
+        order: i16,
+    ) -> Result<Vec<FrontDisplayKingdomChatMessage>> {
+        let req = KingdomMessageContentRequest {
+            timestamp,
+            limit,
+            order,
+            uid: self.uid() as i64,
+        };
+
+        self.call(RouterCode::GetKingdomMessageContent, &req).await?.data()
+    }
+
+    pub async fn fetch_group_messages(
+        &self,
+        gid: i64,
+        timestamp: i64,
+        limit: i16,
+        order: i16,
+    ) -> Result<Vec<FrontDisplayGroupChatMessage>> {
+        let req = GroupMessageContentRequest {
+            timestamp,
+            limit,
+            order,
+            gid,
+            uid: self.uid() as i64,
+        };
+
+        self.call(RouterCode::GetGroupMessageContent, &req).await?.data()
+    }
+
+    pub async fn fetch_p2p_messages(
+        &self,
+        send_uid: i64,
+        timestamp: i64,
+        limit: i16,
+        order: i16,
+    ) -> Result<Vec<FrontDisplayP2pChatMessage>> {
+        let req = P2pMessageContentRequest {
+            timestamp,
+            limit,
+            order,
+            send_uid,
+            my_uid: self.uid() as i64,
+        };
+
+        self.call(RouterCode::GetP2pUserMessageContent, &req).await?.data()
+    }
+
+    //sent at CURSOR_PAGE_VERSION whatever the client version.
+    //cursor from a previous page, empty for either end. mode 0:after,1:before,2:around the cursor.
+    pub async fn fetch_kingdom_history(
+        &self,
+        cursor: &str,
+        limit: i16,
+        mode: i16,
+    ) -> Result<KingdomMessagePage> {
+        let req = KingdomMessagePageRequest {
+            cursor: cursor.to_string(),
+            limit,
+            mode,
+            uid: self.uid() as i64,
+        };
+
+        self.call_version(RouterCode::GetKingdomMessageContent, CURSOR_PAGE_VERSION, &req)
+            .await?
+            .item()
+    }
+
+    pub async fn fetch_group_history(
+        &self,
+        gid: i64,
+        cursor: &str,
+        limit: i16,
+        mode: i16,
+    ) -> Result<GroupMessagePage> {
+        let req = GroupMessagePageRequest {
+            cursor: cursor.to_string(),
+            limit,
+            mode,
+            gid,
+            uid: self.uid() as i64,
+        };
+
+        self.call_version(RouterCode::GetGroupMessageContent, CURSOR_PAGE_VERSION, &req)
+            .await?
+            .item()
+    }
+
+    pub async fn fetch_p2p_history(
+        &self,
+        send_uid: i64,
+        cursor: &str,
+        limit: i16,
+        mode: i16,
+    ) -> Result<P2pMessagePage> {
+        let req = P2pMessagePageRequest {
+            cursor: cursor.to_string(),
+            limit,
+            mode,
+            send_uid,
+            my_uid: self.uid() as i64,
+        };
+
+        self.call_version(RouterCode::GetP2pUserMessageContent, CURSOR_PAGE_VERSION, &req)
+            .await?
+            .item()
+    }
+
+    //tid 1:kingdom(dst is a reported read timestamp, 0 for none),2:group id,3:p2p peer uid.
+    pub async fn channel_unread_count(
+        &self,
+        tid: i16,
+        dst_id_or_kingdom_timestamp: i64,
+    ) -> Result<FrontDisplayChatMessageUnreadCount> {
+        let req = ChannelUnreadCountRequest {
+            tid,
+            dst_id_or_kingdom_timestamp,
+            uid: self.uid() as i64,
+        };
+
+        self.call(RouterCode::GetChannelChatMessageUnreadCount, &req).await?.item()
+    }
+
+    //tid 1:kingdom,2:group id,3:p2p peer uid, cursor of the newest message read.
+    pub async fn mark_read(&self, tid: i16, dst_id: i64, cursor: &str) -> Result<UnreadBadge> {
+        let req = MarkReadRequest {
+            tid,
+            dst_id,
+            cursor: cursor.to_string(),
+            uid: self.uid() as i64,
+        };
+
+        self.call(RouterCode::MarkRead, &req).await?.item()
+    }
+}
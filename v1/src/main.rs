@@ -1,27 +1,32 @@
-use anyhow::{anyhow, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
-use cityhash::city_hash_64;
-use futures::StreamExt;
+use anyhow::{anyhow, Context, Result};
 use std::collections::HashMap;
 use std::env;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
-use tokio::net::tcp::OwnedWriteHalf;
-use tokio::net::TcpListener;
+use std::time::Duration;
 use tokio::sync::Mutex;
-use tracing::error;
-use function_name::named;
-use v1::default_log_pre;
+use v1::chat_system::presence::DEFAULT_IDLE_TIMEOUT;
+use v1::utils::middleware::{
+    log_router_metrics, DEFAULT_METRICS_LOG_INTERVAL, DEFAULT_RATE_LIMIT_PER_SECOND,
+};
+use v1::utils::thread_pool::{
+    log_thread_pool_metrics, DEFAULT_BLOCKING_QUEUE_CAPACITY, DEFAULT_BLOCKING_THREADS,
+};
+use v1::utils::dedup::DEFAULT_SEND_DEDUP_WINDOW_SECS;
+use v1::utils::replay::{
+    DEFAULT_REPLAY_CACHE_CAPACITY, DEFAULT_REPLAY_SESSION_CAPACITY, DEFAULT_REPLAY_WINDOW_SECS,
+};
+use v1::chat_system::push::{
+    DEFAULT_PUSH_ACK_TIMEOUT_SECS, DEFAULT_PUSH_MAX_ATTEMPTS, DEFAULT_PUSH_MAX_PENDING,
+    DEFAULT_PUSH_MAX_PENDING_PER_USER, PendingPushMap,
+};
+use v1::server::{load_tls_acceptor, ChatServer, ChatServerConfig, ServerContext};
 
 use v1::{
-    build_routers, get_slave_diesel_pool,get_master_diesel_pool, Clients, Connection, Message, MessageStateCode,
-    ResponseContext, RouterCode,
+    build_routers, get_redis_pool, get_slave_diesel_pool,get_master_diesel_pool, Broker, ChatStore, Clients, PendingPushes, PgStore, RedisBroker,
+    RedisSendDedup, RedisTokenVerifier, ReplayGuard, RouterMetrics, SendDedup, set_worker_id, SigningKeys, ThreadPool, TokenVerifier, DEFAULT_MAX_FRAME_LENGTH,
 };
 
-const KEY: &str = "F9B14CEC-60B6-810F-1FF7-8BAE688466AC";
-
-#[named]
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing::subscriber::set_global_default(
@@ -34,315 +39,136 @@ async fn main() -> Result<()> {
     )
         .unwrap();
 
-    let chat_api_port = env::var("CHAT_API_PORT").expect("must set CHAT_API_PORT env.");
-    let mut listener = TcpListener::bind(format!("0.0.0.0:{}", chat_api_port)).await?;
-
-    let routers = build_routers();
-    let clients = Clients::new(Mutex::new(HashMap::new()));
-    let master_diesel_pool = get_master_diesel_pool();
-    let slave_diesel_pool = get_slave_diesel_pool();
-
-    let mut incoming = listener.incoming();
-
-    while let Some(stream) = incoming.next().await {
-        match stream {
-            Err(_e) => {
-                error!("{}\tfailed tcp socket recv message:{:?}\t", default_log_pre!("",""), _e);
-            }
-            Ok(sock) => {
-                let clients = clients.clone();
-                let master_diesel_pool = master_diesel_pool.clone();
-                let slave_diesel_pool = slave_diesel_pool.clone();
-                let routers = routers.clone();
-
-                tokio::spawn(async move {
-                    let clients = clients.clone();
-                    let master_diesel_pool = master_diesel_pool.clone();
-                    let slave_diesel_pool = slave_diesel_pool.clone();
-                    let (mut recv, sender) = sock.into_split();
-
-                    let sender = Arc::new(Mutex::new(sender));
-
-                    loop {
-                        let mut stream_buf = [0; 65535];
-                        let master_diesel_pool = master_diesel_pool.clone();
-                        let slave_diesel_pool = slave_diesel_pool.clone();
-                        let receiverd = match AsyncReadExt::read(&mut recv, &mut stream_buf).await {
-                            Ok(v) if v == 0 => return,
-                            Ok(v) => v,
-                            Err(ref e) if e.kind() == tokio::io::ErrorKind::ConnectionReset => {
-                                return;
-                            }
-                            Err(e) => {
-                                error!("{}\tfailed tcp socket recv message:{:?}\t", default_log_pre!("",""), e);
-                                return;
-                            }
-                        };
-                        let mut cursor = std::io::Cursor::new(&stream_buf[..receiverd]);
-                        let code = match ReadBytesExt::read_u16::<LittleEndian>(&mut cursor) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error!("{}\tinvalid code formats:{:?}\t", default_log_pre!("",""), e);
-                                let resp = match ResponseContext::get_bincode(
-                                    0,
-                                    0,
-                                    MessageStateCode::GeneralError,
-                                    "invalid code.",
-                                    "",
-                                ) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        error!("{}\tfialed encode response:{:?}\t", default_log_pre!("",""), e);
-                                        return;
-                                    }
-                                };
-
-                                handle_stream(sender.clone(), resp).await;
-                                return;
-                            }
-                        };
-
-                        let version = match ReadBytesExt::read_u8(&mut cursor) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error!("{}\tinvalid version formats:{:?}\t", default_log_pre!("",""), e);
-                                let resp = match ResponseContext::get_bincode(
-                                    code,
-                                    0,
-                                    MessageStateCode::GeneralError,
-                                    "invalid version.",
-                                    "",
-                                ) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        error!("{}\tfialed encode response:{:?}\t", default_log_pre!("",""), e);
-                                        return;
-                                    }
-                                };
-
-                                handle_stream(sender.clone(), resp).await;
-                                return;
-                            }
-                        };
-
-                        let session_id = match ReadBytesExt::read_u64::<LittleEndian>(&mut cursor) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error!("{}\tinvalid session id formats:{:?}", default_log_pre!(code,""), e);
-                                let resp = match ResponseContext::get_bincode(
-                                    code,
-                                    0,
-                                    MessageStateCode::GeneralError,
-                                    "invalid session id.",
-                                    "",
-                                ) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        error!("{}\tfialed encode response:{:?}\t", default_log_pre!(code,""), e);
-                                        return;
-                                    }
-                                };
-
-                                handle_stream(sender.clone(), resp).await;
-                                return;
-                            }
-                        };
-
-                        let signature = match ReadBytesExt::read_u64::<LittleEndian>(&mut cursor) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error!("{}\tinvalid signature formats:{:?}", default_log_pre!(code,""), e);
-                                let resp = match ResponseContext::get_bincode(
-                                    code,
-                                    session_id,
-                                    MessageStateCode::GeneralError,
-                                    "invalid signature format.",
-                                    "",
-                                ) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        error!("{}\tfialed encode response:{:?}\t", default_log_pre!(code,""), e);
-                                        return;
-                                    }
-                                };
-
-                                handle_stream(sender.clone(), resp).await;
-                                return;
-                            }
-                        };
-
-                        let timestamp = match ReadBytesExt::read_u64::<LittleEndian>(&mut cursor) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error!("{}\tinvalid timestamp formats:{:?}\t", default_log_pre!(code,""), e);
-                                let resp = match ResponseContext::get_bincode(
-                                    code,
-                                    session_id,
-                                    MessageStateCode::GeneralError,
-                                    "invalid timestamp.",
-                                    "",
-                                ) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        error!("{}\tfialed encode response:{:?}\t", default_log_pre!(code,""), e);
-                                        return;
-                                    }
-                                };
-
-                                handle_stream(sender.clone(), resp).await;
-                                return;
-                            }
-                        };
-
-                        let len = match ReadBytesExt::read_u32::<LittleEndian>(&mut cursor) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error!("{}\tinvalid length formats:{:?}", default_log_pre!(code,""), e);
-                                let resp = match ResponseContext::get_bincode(
-                                    code,
-                                    session_id,
-                                    MessageStateCode::GeneralError,
-                                    "invalid body length.",
-                                    "",
-                                ) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        error!("{}\tfialed encode response:{:?}\t", default_log_pre!(code,""), e);
-                                        return;
-                                    }
-                                };
+    //plaintext, tls and websocket listeners can run side by side, at least one must be configured.
+    let chat_api_port = env::var("CHAT_API_PORT").ok();
+    let chat_tls_port = env::var("CHAT_TLS_PORT").ok();
+    let chat_ws_port = env::var("CHAT_WS_PORT").ok();
 
-                                handle_stream(sender.clone(), resp).await;
-                                return;
-                            }
-                        };
-                        let position = cursor.position() as usize;
-                        let mut new_body = Vec::new();
-
-                        if len > 0 {
-                            let body = &stream_buf[position..position + (len as usize)];
-                            new_body.extend_from_slice(body);
-                        }
-
-                        //signature valid
-                        if let Err(e) = signature_valid(signature, timestamp, &new_body) {
-                            error!("{}\tinvalid signature formats:{:?}\t", default_log_pre!(code,""), e);
-
-                            let resp = match ResponseContext::get_bincode(
-                                code,
-                                session_id,
-                                MessageStateCode::GeneralError,
-                                "invalid signature.",
-                                "",
-                            ) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    error!("{}\tfialed encode response:{:?}\t", default_log_pre!(code,""), e);
-                                    return;
-                                }
-                            };
-
-                            handle_stream(sender.clone(), resp).await;
-                            return;
-                        }
-
-                        let code_enum = RouterCode::from_u16(code);
-                        let msg = Message {
-                            code: code_enum,
-                            version,
-                            session_id,
-                            body_len: len,
-                            body: new_body,
-                        };
-
-                        let conn = Connection {
-                            socket: sender.clone(),
-                            master_db: master_diesel_pool.clone(),
-                            slave_db: slave_diesel_pool.clone(),
-                            msg,
-                        };
-
-                        match routers.call(code_enum) {
-                            Ok(f) => {
-                                let resp = match f(clients.clone(), conn).await {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        error!("{}\tfailed method exec:{:?}", default_log_pre!(code,""), e);
-                                        let resp = match ResponseContext::get_bincode(
-                                            code,
-                                            session_id,
-                                            MessageStateCode::GeneralError,
-                                            &e.to_string(),
-                                            "",
-                                        ) {
-                                            Ok(v) => v,
-                                            Err(e) => {
-                                                error!("{}\tfialed encode response:{:?}\t", default_log_pre!(code,""), e);
-                                                return;
-                                            }
-                                        };
-                                        handle_stream(sender.clone(), resp).await;
-                                        return;
-                                    }
-                                };
-
-                                handle_stream(sender.clone(), resp).await;
-                            }
-
-                            Err(e) => {
-                                error!("{}\trouter code not found:{:?}.\t", default_log_pre!(code,""), e);
-                                let resp = match ResponseContext::get_bincode(
-                                    code,
-                                    session_id,
-                                    MessageStateCode::GeneralError,
-                                    anyhow!("router code not found.").to_string().as_str(),
-                                    "",
-                                ) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        error!("{}\tfialed encode response:{:?}\t", default_log_pre!(code,""), e);
-                                        return;
-                                    }
-                                };
-                                handle_stream(sender.clone(), resp).await;
-                                return;
-                            }
-                        }
-                    }
-                });
-            }
-        }
+    if chat_api_port.is_none() && chat_tls_port.is_none() && chat_ws_port.is_none() {
+        return Err(anyhow!("must set CHAT_API_PORT, CHAT_TLS_PORT or CHAT_WS_PORT env."));
     }
 
-    Ok(())
-}
-
-async fn handle_stream(socket: Arc<Mutex<OwnedWriteHalf>>, body: Vec<u8>) {
-    let mut w = socket.lock().await;
-    let _written = match w.write_all(&body).await {
-        Ok(v) => v,
-
-        Err(_e) => {
-            return;
+    let max_frame_length = env::var("CHAT_MAX_FRAME_LENGTH")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_FRAME_LENGTH);
+
+    let idle_timeout = env::var("CHAT_IDLE_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_IDLE_TIMEOUT);
+
+    let push_ack_timeout = env::var("CHAT_PUSH_ACK_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_PUSH_ACK_TIMEOUT_SECS);
+    let push_max_attempts = env::var("CHAT_PUSH_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_PUSH_MAX_ATTEMPTS);
+    let push_max_pending_per_user = env::var("CHAT_PUSH_MAX_PENDING_PER_USER")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_PUSH_MAX_PENDING_PER_USER);
+    let push_max_pending = env::var("CHAT_PUSH_MAX_PENDING")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_PUSH_MAX_PENDING);
+
+    let replay_window = env::var("CHAT_REPLAY_WINDOW_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_REPLAY_WINDOW_SECS);
+    let replay_cache_capacity = env::var("CHAT_REPLAY_CACHE_CAPACITY")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_REPLAY_CACHE_CAPACITY);
+    let replay_session_capacity = env::var("CHAT_REPLAY_SESSION_CAPACITY")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_REPLAY_SESSION_CAPACITY);
+
+    let send_dedup_window = env::var("CHAT_SEND_DEDUP_WINDOW_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_SEND_DEDUP_WINDOW_SECS);
+
+    let rate_limit_per_second = env::var("CHAT_RATE_LIMIT_PER_SECOND")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_RATE_LIMIT_PER_SECOND);
+
+    //storage and redis calls block, they share this bounded pool instead of the runtime threads.
+    let blocking_threads = env::var("CHAT_BLOCKING_THREADS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_BLOCKING_THREADS);
+    let blocking_queue_capacity = env::var("CHAT_BLOCKING_QUEUE_CAPACITY")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_BLOCKING_QUEUE_CAPACITY);
+
+    //every node must run with its own worker id, otherwise generated ids can collide.
+    let worker_id = env::var("CHAT_WORKER_ID")
+        .context("must set CHAT_WORKER_ID env.")?
+        .parse::<u16>()
+        .map_err(|e| anyhow!("invalid CHAT_WORKER_ID: {}", e))?;
+    set_worker_id(worker_id).context("invalid CHAT_WORKER_ID")?;
+
+    let router_metrics = Arc::new(RouterMetrics::default());
+    let routers = build_routers(router_metrics.clone(), rate_limit_per_second);
+    let clients = Clients::new(Mutex::new(HashMap::new()));
+    let pending_pushes = PendingPushes::new(Mutex::new(PendingPushMap::new(push_max_pending_per_user, push_max_pending)));
+    let redis_pool = get_redis_pool();
+    let verifier: Arc<dyn TokenVerifier> = Arc::new(RedisTokenVerifier::new(redis_pool.clone()));
+    let send_dedup: Arc<dyn SendDedup> = Arc::new(RedisSendDedup::new(redis_pool.clone(), send_dedup_window));
+    let broker: Arc<dyn Broker> = Arc::new(RedisBroker::new(redis_pool));
+    let signing_keys = SigningKeys::from_env()?;
+    let replay_guard = ReplayGuard::new(replay_window, replay_cache_capacity, replay_session_capacity);
+    let store: Arc<dyn ChatStore> = Arc::new(PgStore::new(get_master_diesel_pool(), get_slave_diesel_pool()));
+
+    let pool = Arc::new(ThreadPool::new(blocking_threads, blocking_queue_capacity));
+
+    tokio::spawn(log_router_metrics(router_metrics, DEFAULT_METRICS_LOG_INTERVAL));
+    tokio::spawn(log_thread_pool_metrics(pool.clone(), DEFAULT_METRICS_LOG_INTERVAL));
+
+    let ctx = Arc::new(ServerContext::new(
+        routers,
+        clients,
+        pending_pushes,
+        store,
+        pool,
+        verifier,
+        broker,
+        signing_keys,
+        replay_guard,
+        send_dedup,
+        max_frame_length,
+        idle_timeout,
+    ));
+
+    let listen_addr = |port: String| format!("0.0.0.0:{}", port).parse::<SocketAddr>();
+
+    let tls = match chat_tls_port {
+        Some(port) => {
+            let cert_file = env::var("CHAT_TLS_CERT_FILE").expect("must set CHAT_TLS_CERT_FILE env.");
+            let key_file = env::var("CHAT_TLS_KEY_FILE").expect("must set CHAT_TLS_KEY_FILE env.");
+            Some((listen_addr(port)?, load_tls_acceptor(&cert_file, &key_file)?))
         }
+        None => None,
     };
-}
-
-//signature valid
-fn signature_valid(sign: u64, timestamp: u64, body: &[u8]) -> Result<()> {
-    let mut signature = Vec::new();
 
-    signature.extend_from_slice(KEY.as_bytes());
-
-    byteorder::WriteBytesExt::write_u64::<LittleEndian>(&mut signature, timestamp)?;
-
-    signature.extend_from_slice(body);
-
-    let signature = city_hash_64(&signature);
+    let config = ChatServerConfig {
+        tcp_addr: chat_api_port.map(listen_addr).transpose()?,
+        tls,
+        ws_addr: chat_ws_port.map(listen_addr).transpose()?,
+        push_ack_timeout: Duration::from_secs(push_ack_timeout),
+        push_max_attempts,
+    };
 
-    if sign != signature {
-        return Err(anyhow!("invalid signature."));
-    }
+    ChatServer::start(ctx, config).await?.wait().await;
 
     Ok(())
 }
@@ -1,8 +1,10 @@
+use crate::models::chat_messages::MessageCursor;
 use crate::schema::{chat_groups_uids, users};
 use chrono::NaiveDateTime;
 use chrono::Utc;
 use diesel::prelude::*;
 
+//group messages after (latest_timestamp, latest_mid) are unread for uuid.
 #[derive(Debug, Clone, Identifiable, Queryable, Associations)]
 #[primary_key(guid)]
 pub struct ChatGroupsUid {
@@ -13,6 +15,7 @@ pub struct ChatGroupsUid {
     pub unread_count: i16,
     pub modify_time: NaiveDateTime,
     pub created_time: NaiveDateTime,
+    pub latest_mid: i64,
 }
 
 #[derive(Debug, Default, Insertable)]
@@ -26,6 +29,10 @@ pub struct NewChatGroupUid {
 }
 
 impl ChatGroupsUid {
+    pub fn read_cursor(&self) -> MessageCursor {
+        MessageCursor { created_timestamp: self.latest_timestamp, mid: self.latest_mid }
+    }
+
     pub fn get_groups_users_by_gid(
         conn: &PgConnection,
         gid: i64,
@@ -64,41 +71,72 @@ impl ChatGroupsUid {
 
         Ok(())
     }
-    pub fn update_unread_count_and_timestamp(
+    pub fn get_gids_by_uid(conn: &PgConnection, uuid: i64) -> QueryResult<Vec<ChatGroupsUid>> {
+        chat_groups_uids::table
+            .filter(chat_groups_uids::uuid.eq(uuid))
+            .filter(chat_groups_uids::unread_count.gt(0))
+            .load(conn)
+    }
+
+    pub fn get_group_user_info(
+        conn: &PgConnection,
+        gid: i64,
+        uuid: i64,
+    ) -> QueryResult<ChatGroupsUid> {
+        chat_groups_uids::table
+            .filter(chat_groups_uids::gid.eq(gid))
+            .filter(chat_groups_uids::uuid.eq(uuid))
+            .first(conn)
+    }
+
+    //the member row locked until the transaction ends, a concurrent send waits before bumping unread_count.
+    pub fn lock_group_user(conn: &PgConnection, gid: i64, uuid: i64) -> QueryResult<ChatGroupsUid> {
+        chat_groups_uids::table
+            .filter(chat_groups_uids::gid.eq(gid))
+            .filter(chat_groups_uids::uuid.eq(uuid))
+            .for_update()
+            .first(conn)
+    }
+
+    //move the read position to cursor with what is left unread after it, an older cursor is ignored.
+    pub fn mark_read(
         conn: &PgConnection,
         gid: i64,
-        timestamp: i64,
-        uid:i64,
+        uid: i64,
+        cursor: &MessageCursor,
+        unread_count: i16,
     ) -> QueryResult<()> {
+        use crate::schema::chat_groups_uids::dsl::{latest_mid, latest_timestamp};
+
         let now = Utc::now();
         diesel::update(chat_groups_uids::table)
             .set((
-                chat_groups_uids::unread_count.eq(0),
-                chat_groups_uids::latest_timestamp.eq(timestamp),
+                chat_groups_uids::unread_count.eq(unread_count),
+                latest_timestamp.eq(cursor.created_timestamp),
+                latest_mid.eq(cursor.mid),
                 chat_groups_uids::modify_time.eq(now.naive_local()),
             ))
             .filter(chat_groups_uids::gid.eq(gid))
             .filter(chat_groups_uids::uuid.eq(uid))
+            .filter(
+                latest_timestamp
+                    .lt(cursor.created_timestamp)
+                    .or(latest_timestamp.eq(cursor.created_timestamp).and(latest_mid.le(cursor.mid))),
+            )
             .execute(conn)?;
 
         Ok(())
     }
 
-    pub fn get_gids_by_uid(conn: &PgConnection, uuid: i64) -> QueryResult<Vec<ChatGroupsUid>> {
-        chat_groups_uids::table
-            .filter(chat_groups_uids::uuid.eq(uuid))
-            .filter(chat_groups_uids::unread_count.gt(0))
-            .load(conn)
-    }
+    //unread group messages of uuid over every group.
+    pub fn get_unread_total(conn: &PgConnection, uuid: i64) -> QueryResult<i64> {
+        use diesel::expression::dsl::sum;
 
-    pub fn get_group_user_info(
-        conn: &PgConnection,
-        gid: i64,
-        uuid: i64,
-    ) -> QueryResult<ChatGroupsUid> {
-        chat_groups_uids::table
-            .filter(chat_groups_uids::gid.eq(gid))
+        let total: Option<i64> = chat_groups_uids::table
             .filter(chat_groups_uids::uuid.eq(uuid))
-            .first(conn)
+            .select(sum(chat_groups_uids::unread_count))
+            .first(conn)?;
+
+        Ok(total.unwrap_or(0))
     }
 }
@@ -0,0 +1,81 @@
+use crate::next_id;
+use crate::models::chat_messages::MessageCursor;
+use crate::schema::chat_kingdom_reads;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use diesel::prelude::*;
+
+//kingdom messages after (latest_timestamp, latest_mid) are unread for uuid.
+#[derive(Debug, Clone, Identifiable, Queryable)]
+#[primary_key(krid)]
+pub struct ChatKingdomRead {
+    pub krid: i64,
+    pub uuid: i64,
+    pub kingdom_id: i64,
+    pub latest_timestamp: i64,
+    pub latest_mid: i64,
+    pub modify_time: NaiveDateTime,
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Debug, Default, Insertable)]
+#[table_name = "chat_kingdom_reads"]
+pub struct NewChatKingdomRead {
+    pub krid: i64,
+    pub uuid: i64,
+    pub kingdom_id: i64,
+    pub latest_timestamp: i64,
+    pub latest_mid: i64,
+}
+
+impl ChatKingdomRead {
+    //(0, 0) until the user reads the kingdom for the first time.
+    pub fn get_read_cursor(conn: &PgConnection, uuid: i64, kingdom_id: i64) -> QueryResult<MessageCursor> {
+        let position: Option<(i64, i64)> = chat_kingdom_reads::table
+            .filter(chat_kingdom_reads::uuid.eq(uuid))
+            .filter(chat_kingdom_reads::kingdom_id.eq(kingdom_id))
+            .select((chat_kingdom_reads::latest_timestamp, chat_kingdom_reads::latest_mid))
+            .first(conn)
+            .optional()?;
+
+        let (created_timestamp, mid) = position.unwrap_or((0, 0));
+        Ok(MessageCursor { created_timestamp, mid })
+    }
+
+    //move the read position to cursor, an older cursor is ignored.
+    pub fn mark_read(conn: &PgConnection, uuid: i64, kingdom_id: i64, cursor: &MessageCursor) -> QueryResult<()> {
+        use crate::schema::chat_kingdom_reads::dsl::{latest_mid, latest_timestamp};
+
+        let data = NewChatKingdomRead {
+            krid: next_id(),
+            uuid,
+            kingdom_id,
+            latest_timestamp: cursor.created_timestamp,
+            latest_mid: cursor.mid,
+        };
+
+        diesel::insert_into(chat_kingdom_reads::table)
+            .values(data)
+            .on_conflict((chat_kingdom_reads::uuid, chat_kingdom_reads::kingdom_id))
+            .do_nothing()
+            .execute(conn)?;
+
+        let now = Utc::now();
+        diesel::update(chat_kingdom_reads::table)
+            .set((
+                latest_timestamp.eq(cursor.created_timestamp),
+                latest_mid.eq(cursor.mid),
+                chat_kingdom_reads::modify_time.eq(now.naive_local()),
+            ))
+            .filter(chat_kingdom_reads::uuid.eq(uuid))
+            .filter(chat_kingdom_reads::kingdom_id.eq(kingdom_id))
+            .filter(
+                latest_timestamp
+                    .lt(cursor.created_timestamp)
+                    .or(latest_timestamp.eq(cursor.created_timestamp).and(latest_mid.lt(cursor.mid))),
+            )
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
 use crate::models::chat_groups::ChatGroup;
 use crate::models::user::{FrontDisplayChatUser, User};
 use crate::schema::chat_messages;
-use crate::{get_guid_value, BinaryEncode, BinaryDecode, deserialize_binary, utils::binary_helper::*};
-use anyhow::{Result, Context};
-use byteorder::{LittleEndian, WriteBytesExt};
+use crate::{next_id, BinaryEncode, BinaryDecode, utils::binary_helper::*};
+use crate::utils::common::LengthPrefix;
+use anyhow::{anyhow, Result, Context};
 use chrono::{NaiveDateTime, Utc};
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use std::io::Cursor;
 use serde::{Serialize,Deserialize};
@@ -32,7 +33,7 @@ pub struct ChatMessage {
     pub msg_type: i16,      //1:文本消息,2:系统消息,3:图片，4：位置，5：表情
 }
 
-#[derive(Debug, Clone, Queryable,Serialize,Deserialize)]
+#[derive(Debug, Clone, Queryable,Serialize,Deserialize, BinaryEncode, BinaryDecode)]
 pub struct FrontDisplayChatMessage {
     pub mid: i64,
     pub send_id: i64,
@@ -43,7 +44,7 @@ pub struct FrontDisplayChatMessage {
     pub msg_type: i16,
 }
 
-#[derive(Debug, Clone, Queryable,Serialize,Deserialize)]
+#[derive(Debug, Clone, Queryable,Serialize,Deserialize, BinaryEncode, BinaryDecode)]
 pub struct FrontDisplayKingdomChatMessage {
     pub mid: i64,
     pub send_user: FrontDisplayChatUser,
@@ -54,7 +55,7 @@ pub struct FrontDisplayKingdomChatMessage {
     pub msg_type: i16,
 }
 
-#[derive(Debug, Clone, Queryable,Serialize,Deserialize)]
+#[derive(Debug, Clone, Queryable,Serialize,Deserialize, BinaryEncode, BinaryDecode)]
 pub struct FrontDisplayGroupChatMessage {
     pub mid: i64,
     pub send_user: FrontDisplayChatUser,
@@ -67,7 +68,7 @@ pub struct FrontDisplayGroupChatMessage {
     pub msg_type: i16,
 }
 
-#[derive(Debug, Clone, Queryable,Serialize,Deserialize)]
+#[derive(Debug, Clone, Queryable,Serialize,Deserialize, BinaryEncode, BinaryDecode)]
 pub struct FrontDisplayP2pChatMessage {
     pub mid: i64,
     pub send_user: FrontDisplayChatUser,
@@ -78,13 +79,13 @@ pub struct FrontDisplayP2pChatMessage {
     pub msg_type: i16,
 }
 
-#[derive(Debug, Clone, Queryable,Serialize,Deserialize)]
+#[derive(Debug, Clone, Queryable,Serialize,Deserialize, BinaryEncode, BinaryDecode)]
 pub struct FrontDisplayChatMessageUnreadCount {
     pub unread_count: i16,
     pub kind: i16,
 }
 
-#[derive(Debug, Clone, Queryable,Serialize,Deserialize)]
+#[derive(Debug, Clone, Queryable,Serialize,Deserialize, BinaryEncode, BinaryDecode)]
 pub struct FrontDisplayP2pChatMessageCount {
     pub mid: i64,
     pub content: String,
@@ -93,6 +94,177 @@ pub struct FrontDisplayP2pChatMessageCount {
     pub msg_type: i16,
 }
 
+//position in a history, messages are ordered by (created_timestamp, mid) so ties within a millisecond page stably.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MessageCursor {
+    pub created_timestamp: i64,
+    pub mid: i64,
+}
+
+impl MessageCursor {
+    pub fn of(msg: &ChatMessage) -> Self {
+        MessageCursor {
+            created_timestamp: msg.created_timestamp,
+            mid: msg.mid,
+        }
+    }
+
+    //every message up to timestamp, for read positions reported as a timestamp only.
+    pub fn through(timestamp: i64) -> Self {
+        MessageCursor {
+            created_timestamp: timestamp,
+            mid: i64::MAX,
+        }
+    }
+
+    //opaque to clients, 32 hex digits.
+    pub fn encode(&self) -> String {
+        format!("{:016x}{:016x}", self.created_timestamp as u64, self.mid as u64)
+    }
+
+    //empty->no cursor.
+    pub fn parse(v: &str) -> Result<Option<Self>> {
+        if v.is_empty() {
+            return Ok(None);
+        }
+        if v.len() != 32 || !v.is_ascii() {
+            return Err(anyhow!("invalid cursor:{}", v));
+        }
+
+        let created_timestamp = u64::from_str_radix(&v[..16], 16).map_err(|_| anyhow!("invalid cursor:{}", v))?;
+        let mid = u64::from_str_radix(&v[16..], 16).map_err(|_| anyhow!("invalid cursor:{}", v))?;
+
+        Ok(Some(MessageCursor {
+            created_timestamp: created_timestamp as i64,
+            mid: mid as i64,
+        }))
+    }
+}
+
+//without a cursor after starts from the oldest message and before from the newest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageMode {
+    After,
+    Before,
+    Around,
+}
+
+impl PageMode {
+    //0:after,1:before,2:around.
+    pub fn from_i16(v: i16) -> Result<Self> {
+        match v {
+            0 => Ok(PageMode::After),
+            1 => Ok(PageMode::Before),
+            2 => Ok(PageMode::Around),
+            _ => Err(anyhow!("invalid page mode:{}", v)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PageQuery {
+    pub cursor: Option<MessageCursor>,
+    pub mode: PageMode,
+    pub limit: i64,
+}
+
+impl PageQuery {
+    pub fn new(cursor: &str, mode: i16, limit: i64) -> Result<Self> {
+        let cursor = MessageCursor::parse(cursor)?;
+        let mode = PageMode::from_i16(mode)?;
+        if mode == PageMode::Around && cursor.is_none() {
+            return Err(anyhow!("around needs a cursor."));
+        }
+
+        Ok(PageQuery { cursor, mode, limit })
+    }
+
+    //v1 history, order 0:after the timestamp,1:before it, timestamp 0 for either end.
+    //mids are positive, so the cursor skips every message of that millisecond like the old filter.
+    pub fn from_timestamp(timestamp: i64, order: i16, limit: i64) -> Self {
+        let (mode, mid) = if order == 0 {
+            (PageMode::After, i64::MAX)
+        } else {
+            (PageMode::Before, 0)
+        };
+        let cursor = if timestamp > 0 {
+            Some(MessageCursor { created_timestamp: timestamp, mid })
+        } else {
+            None
+        };
+
+        PageQuery { cursor, mode, limit }
+    }
+
+    //how many messages to take older than the cursor and from the cursor on.
+    pub fn side_limits(&self) -> (i64, i64) {
+        let limit = self.limit.max(0);
+        match self.mode {
+            PageMode::After => (0, limit),
+            PageMode::Before => (limit, 0),
+            PageMode::Around => (limit / 2, limit - limit / 2),
+        }
+    }
+
+    //older comes newest first and newer oldest first, each with up to one row past its limit.
+    //returns the page oldest first and whether either side had more.
+    pub fn assemble(&self, mut older: Vec<ChatMessage>, mut newer: Vec<ChatMessage>) -> (Vec<ChatMessage>, bool) {
+        let (older_limit, newer_limit) = self.side_limits();
+        let has_more = older.len() as i64 > older_limit || newer.len() as i64 > newer_limit;
+
+        older.truncate(older_limit as usize);
+        newer.truncate(newer_limit as usize);
+        older.reverse();
+        older.extend(newer);
+
+        (older, has_more)
+    }
+
+    //cursors of the first and last message, an empty page echoes the requested cursor.
+    pub fn cursors(&self, msgs: &[ChatMessage]) -> (String, String) {
+        let requested = self.cursor.map(|c| c.encode()).unwrap_or_default();
+
+        match (msgs.first(), msgs.last()) {
+            (Some(first), Some(last)) => (MessageCursor::of(first).encode(), MessageCursor::of(last).encode()),
+            _ => (requested.clone(), requested),
+        }
+    }
+}
+
+//history page, oldest message first. has_more is 1 when the requested direction holds more,
+//before_cursor and after_cursor continue the history from either end.
+#[derive(Debug, Clone, Serialize, Deserialize, BinaryEncode, BinaryDecode)]
+pub struct KingdomMessagePage {
+    pub messages: Vec<FrontDisplayKingdomChatMessage>,
+    pub has_more: u8,
+    pub before_cursor: String,
+    pub after_cursor: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, BinaryEncode, BinaryDecode)]
+pub struct GroupMessagePage {
+    pub messages: Vec<FrontDisplayGroupChatMessage>,
+    pub has_more: u8,
+    pub before_cursor: String,
+    pub after_cursor: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, BinaryEncode, BinaryDecode)]
+pub struct P2pMessagePage {
+    pub messages: Vec<FrontDisplayP2pChatMessage>,
+    pub has_more: u8,
+    pub before_cursor: String,
+    pub after_cursor: String,
+}
+
+//server-initiated message pushed to online recipients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PushChatMessage {
+    Kingdom(FrontDisplayKingdomChatMessage),
+    Group(FrontDisplayGroupChatMessage),
+    P2p(FrontDisplayP2pChatMessage),
+}
+
 #[derive(Debug, Insertable)]
 #[table_name = "chat_messages"]
 pub struct NewChatMessage {
@@ -115,7 +287,7 @@ impl ChatMessage {
         msg_type: i16,
     ) -> QueryResult<Self> {
         let data = NewChatMessage {
-            mid: get_guid_value() as i64,
+            mid: next_id(),
             send_id,
             to_id,
             content,
@@ -129,6 +301,61 @@ impl ChatMessage {
             .get_result(conn)
     }
 
+    pub fn get_front_display_kingdom_message(
+        conn: &PgConnection,
+        chat_msg: ChatMessage,
+    ) -> QueryResult<FrontDisplayKingdomChatMessage> {
+        let send_user = User::get_front_display_chat_user_info(conn, chat_msg.send_id)?;
+
+        Ok(FrontDisplayKingdomChatMessage {
+            mid: chat_msg.mid,
+            send_user,
+            to_id: chat_msg.to_id,
+            content: chat_msg.content,
+            created_timestamp: chat_msg.created_timestamp,
+            kind: chat_msg.kind,
+            msg_type: chat_msg.msg_type,
+        })
+    }
+
+    pub fn get_front_display_group_message(
+        conn: &PgConnection,
+        chat_msg: ChatMessage,
+    ) -> QueryResult<FrontDisplayGroupChatMessage> {
+        let send_user = User::get_front_display_chat_user_info(conn, chat_msg.send_id)?;
+        let group_info = ChatGroup::get_chat_group_by_gid(conn, chat_msg.to_id)?;
+
+        Ok(FrontDisplayGroupChatMessage {
+            mid: chat_msg.mid,
+            send_user,
+            gid: group_info.gid,
+            group_name: group_info.group_name,
+            group_thumbnail: group_info.group_thumbnail,
+            content: chat_msg.content,
+            created_timestamp: chat_msg.created_timestamp,
+            kind: chat_msg.kind,
+            msg_type: chat_msg.msg_type,
+        })
+    }
+
+    pub fn get_front_display_p2p_message(
+        conn: &PgConnection,
+        chat_msg: ChatMessage,
+    ) -> QueryResult<FrontDisplayP2pChatMessage> {
+        let send_user = User::get_front_display_chat_user_info(conn, chat_msg.send_id)?;
+        let dst_user = User::get_front_display_chat_user_info(conn, chat_msg.to_id)?;
+
+        Ok(FrontDisplayP2pChatMessage {
+            mid: chat_msg.mid,
+            send_user,
+            dst_user,
+            content: chat_msg.content,
+            created_timestamp: chat_msg.created_timestamp,
+            kind: chat_msg.kind,
+            msg_type: chat_msg.msg_type,
+        })
+    }
+
     pub fn get_kingdom_unread_count(
         conn: &PgConnection,
         kingdom_id: i64,
@@ -148,16 +375,18 @@ impl ChatMessage {
     pub fn get_kingdom_unread_count_and_latest_message(
         conn: &PgConnection,
         to_id: i64,
-        t: i64,
+        cursor: &MessageCursor,
     ) -> Result<(i64, FrontDisplayKingdomChatMessage)> {
-        let unread_count = Self::get_kingdom_unread_count(conn, to_id, t).with_context(|| format!("failed get kingdom unread count."))?;
+        let unread_count = Self::get_kingdom_unread_count_after(conn, to_id, cursor).with_context(|| format!("failed get kingdom unread count."))?;
 
         let latest_msg: ChatMessage = chat_messages::table
             .filter(chat_messages::to_id.eq(to_id))
             .filter(chat_messages::kind.eq(1))
-            .filter(chat_messages::created_timestamp.gt(t))
-            .order(chat_messages::created_timestamp.desc())
+            .order((chat_messages::created_timestamp.desc(), chat_messages::mid.desc()))
             .first(conn).with_context(|| format!("failed get kingdom latest message."))?;
+        if MessageCursor::of(&latest_msg) <= *cursor {
+            return Err(anyhow!("no kingdom message after the read position."));
+        }
 
         let send_user = User::get_front_display_chat_user_info(conn, latest_msg.send_id).with_context(|| format!("fialed get user info."))?;
         let kingdom_chat_message = FrontDisplayKingdomChatMessage {
@@ -173,30 +402,24 @@ impl ChatMessage {
         Ok((unread_count, kingdom_chat_message))
     }
 
-    pub fn get_group_unread_count(conn: &PgConnection, group_id: i64, t: i64) -> QueryResult<i64> {
-        use diesel::dsl::count;
-        let unread_count = chat_messages::table
-            .filter(chat_messages::to_id.eq(group_id))
-            .filter(chat_messages::kind.eq(2))
-            .filter(chat_messages::created_timestamp.gt(t))
-            .select(count(chat_messages::mid))
-            .first(conn)?;
-
-        Ok(unread_count)
-    }
+    //unread messages of uid in the group after cursor and the newest of them.
     pub fn get_group_unread_count_and_latest_message(
         conn: &PgConnection,
         to_id: i64,
-        t: i64,
-    ) -> QueryResult<(i64, FrontDisplayGroupChatMessage)> {
-        let unread_count = Self::get_group_unread_count(conn, to_id, t)?;
+        uid: i64,
+        cursor: &MessageCursor,
+    ) -> Result<(i64, FrontDisplayGroupChatMessage)> {
+        let unread_count = Self::get_group_unread_count_after(conn, to_id, uid, cursor).with_context(|| format!("failed get group unread count."))?;
 
         let latest_msg: ChatMessage = chat_messages::table
             .filter(chat_messages::to_id.eq(to_id))
             .filter(chat_messages::kind.eq(2))
-            .filter(chat_messages::created_timestamp.gt(t))
-            .order(chat_messages::created_timestamp.desc())
-            .first(conn)?;
+            .filter(chat_messages::send_id.ne(uid))
+            .order((chat_messages::created_timestamp.desc(), chat_messages::mid.desc()))
+            .first(conn).with_context(|| format!("failed get group latest message."))?;
+        if MessageCursor::of(&latest_msg) <= *cursor {
+            return Err(anyhow!("no group message after the read position."));
+        }
 
         let group_info = ChatGroup::get_chat_group_by_gid(conn, to_id)?;
         let send_user = User::get_front_display_chat_user_info(conn, latest_msg.send_id)?;
@@ -215,17 +438,22 @@ impl ChatMessage {
         Ok((unread_count, group_chat_message))
     }
 
+    //the latest message send_id sent to to_id after cursor.
     pub fn get_p2p_unread_count_latest_message(
         conn: &PgConnection,
+        send_id: i64,
         to_id: i64,
-        t: i64,
+        cursor: &MessageCursor,
     ) -> QueryResult<FrontDisplayP2pChatMessageCount> {
         let latest_msg: ChatMessage = chat_messages::table
+            .filter(chat_messages::send_id.eq(send_id))
             .filter(chat_messages::to_id.eq(to_id))
             .filter(chat_messages::kind.eq(3))
-            .filter(chat_messages::created_timestamp.gt(t))
-            .order(chat_messages::created_timestamp.desc())
+            .order((chat_messages::created_timestamp.desc(), chat_messages::mid.desc()))
             .first(conn)?;
+        if MessageCursor::of(&latest_msg) <= *cursor {
+            return Err(diesel::result::Error::NotFound);
+        }
 
         let chat_message = FrontDisplayP2pChatMessageCount {
             mid: latest_msg.mid,
@@ -237,33 +465,118 @@ impl ChatMessage {
         Ok(chat_message)
     }
 
-    pub fn get_kingdom_message(
-        conn: &PgConnection,
-        to_id: i64,
-        timestamp: i64,
-        limit: i64,
-        order: i16,
-    ) -> Result<Vec<FrontDisplayKingdomChatMessage>> {
-        let mut query = chat_messages::table
-            .filter(chat_messages::to_id.eq(to_id))
-            .limit(limit)
-            .into_boxed();
-
-        if timestamp > 0 {
-            if order == 0 {
-                query = query.filter(chat_messages::created_timestamp.gt(timestamp));
-            } else if order == 1 {
-                query = query.filter(chat_messages::created_timestamp.lt(timestamp));
+    //one page of history ordered by (created_timestamp, mid) and whether more exist past it.
+    //base is the conversation filter, called once per side of the cursor.
+    pub fn load_page<F>(conn: &PgConnection, base: F, page: &PageQuery) -> QueryResult<(Vec<ChatMessage>, bool)>
+    where
+        F: Fn() -> chat_messages::BoxedQuery<'static, Pg>,
+    {
+        use crate::schema::chat_messages::dsl::{created_timestamp, mid};
+
+        let (older_limit, newer_limit) = page.side_limits();
+
+        let mut older = Vec::new();
+        if older_limit > 0 {
+            let mut query = base();
+            if let Some(c) = page.cursor {
+                query = query.filter(
+                    created_timestamp
+                        .lt(c.created_timestamp)
+                        .or(created_timestamp.eq(c.created_timestamp).and(mid.lt(c.mid))),
+                );
             }
+            older = query
+                .order((created_timestamp.desc(), mid.desc()))
+                .limit(older_limit + 1)
+                .load(conn)?;
         }
 
-        if order == 0 {
-            query = query.order(chat_messages::created_timestamp.asc());
-        } else if order == 1 {
-            query = query.order(chat_messages::created_timestamp.desc());
+        let mut newer = Vec::new();
+        if newer_limit > 0 {
+            let mut query = base();
+            if let Some(c) = page.cursor {
+                //around keeps the cursor message itself.
+                query = if page.mode == PageMode::Around {
+                    query.filter(
+                        created_timestamp
+                            .gt(c.created_timestamp)
+                            .or(created_timestamp.eq(c.created_timestamp).and(mid.ge(c.mid))),
+                    )
+                } else {
+                    query.filter(
+                        created_timestamp
+                            .gt(c.created_timestamp)
+                            .or(created_timestamp.eq(c.created_timestamp).and(mid.gt(c.mid))),
+                    )
+                };
+            }
+            newer = query
+                .order((created_timestamp.asc(), mid.asc()))
+                .limit(newer_limit + 1)
+                .load(conn)?;
         }
 
-        let chat_msgs = query.load::<ChatMessage>(conn)?;
+        Ok(page.assemble(older, newer))
+    }
+
+    //messages of base newer than cursor, what is left unread once the reader is at cursor.
+    pub fn count_after(
+        conn: &PgConnection,
+        base: chat_messages::BoxedQuery<'static, Pg>,
+        cursor: &MessageCursor,
+    ) -> QueryResult<i64> {
+        use crate::schema::chat_messages::dsl::{created_timestamp, mid};
+
+        base.filter(
+            created_timestamp
+                .gt(cursor.created_timestamp)
+                .or(created_timestamp.eq(cursor.created_timestamp).and(mid.gt(cursor.mid))),
+        )
+        .count()
+        .get_result(conn)
+    }
+
+    pub fn get_kingdom_unread_count_after(conn: &PgConnection, kingdom_id: i64, cursor: &MessageCursor) -> QueryResult<i64> {
+        let base = chat_messages::table
+            .filter(chat_messages::to_id.eq(kingdom_id))
+            .filter(chat_messages::kind.eq(1))
+            .into_boxed();
+
+        Self::count_after(conn, base, cursor)
+    }
+
+    //the reader's own messages are never unread.
+    pub fn get_group_unread_count_after(conn: &PgConnection, gid: i64, uid: i64, cursor: &MessageCursor) -> QueryResult<i64> {
+        let base = chat_messages::table
+            .filter(chat_messages::to_id.eq(gid))
+            .filter(chat_messages::kind.eq(2))
+            .filter(chat_messages::send_id.ne(uid))
+            .into_boxed();
+
+        Self::count_after(conn, base, cursor)
+    }
+
+    pub fn get_p2p_unread_count_after(conn: &PgConnection, send_id: i64, to_id: i64, cursor: &MessageCursor) -> QueryResult<i64> {
+        let base = chat_messages::table
+            .filter(chat_messages::send_id.eq(send_id))
+            .filter(chat_messages::to_id.eq(to_id))
+            .filter(chat_messages::kind.eq(3))
+            .into_boxed();
+
+        Self::count_after(conn, base, cursor)
+    }
+
+    pub fn get_kingdom_message(
+        conn: &PgConnection,
+        to_id: i64,
+        page: &PageQuery,
+    ) -> Result<KingdomMessagePage> {
+        let (chat_msgs, has_more) = Self::load_page(
+            conn,
+            || chat_messages::table.filter(chat_messages::to_id.eq(to_id)).into_boxed(),
+            page,
+        )?;
+        let (before_cursor, after_cursor) = page.cursors(&chat_msgs);
 
         let mut datas = Vec::new();
 
@@ -287,91 +600,76 @@ impl ChatMessage {
             datas.push(f_chat_msg);
         }
 
-        Ok(datas)
+        Ok(KingdomMessagePage {
+            messages: datas,
+            has_more: has_more as u8,
+            before_cursor,
+            after_cursor,
+        })
     }
 
     pub fn get_group_message(
         conn: &PgConnection,
         to_id: i64,
-        timestamp: i64,
-        limit: i64,
-        order: i16,
-    ) -> QueryResult<Vec<FrontDisplayGroupChatMessage>> {
-        let mut query = chat_messages::table
-            .filter(chat_messages::to_id.eq(to_id))
-            .limit(limit)
-            .into_boxed();
-
-        if timestamp > 0 {
-            if order == 0 {
-                query = query.filter(chat_messages::created_timestamp.gt(timestamp));
-            } else if order == 1 {
-                query = query.filter(chat_messages::created_timestamp.lt(timestamp));
-            }
-        }
-
-        if order == 0 {
-            query = query.order(chat_messages::created_timestamp.asc());
-        } else if order == 1 {
-            query = query.order(chat_messages::created_timestamp.desc());
-        }
-
-        let chat_msgs = query.load::<ChatMessage>(conn)?;
+        page: &PageQuery,
+    ) -> QueryResult<GroupMessagePage> {
+        let (chat_msgs, has_more) = Self::load_page(
+            conn,
+            || chat_messages::table.filter(chat_messages::to_id.eq(to_id)).into_boxed(),
+            page,
+        )?;
+        let (before_cursor, after_cursor) = page.cursors(&chat_msgs);
 
         let mut datas = Vec::new();
 
-        for chat_msg in chat_msgs.into_iter() {
-            //get send user info
-            let send_user = User::get_front_display_chat_user_info(conn, chat_msg.send_id)?;
+        if !chat_msgs.is_empty() {
             let group_info = ChatGroup::get_chat_group_by_gid(conn, to_id)?;
 
-            let f_chat_msg = FrontDisplayGroupChatMessage {
-                mid: chat_msg.mid,
-                send_user,
-                gid: group_info.gid,
-                group_name: group_info.group_name,
-                group_thumbnail: group_info.group_thumbnail,
-                content: chat_msg.content,
-                created_timestamp: chat_msg.created_timestamp,
-                kind: chat_msg.kind,
-                msg_type: chat_msg.msg_type,
-            };
-
-            datas.push(f_chat_msg);
+            for chat_msg in chat_msgs.into_iter() {
+                //get send user info
+                let send_user = User::get_front_display_chat_user_info(conn, chat_msg.send_id)?;
+
+                let f_chat_msg = FrontDisplayGroupChatMessage {
+                    mid: chat_msg.mid,
+                    send_user,
+                    gid: group_info.gid,
+                    group_name: group_info.group_name.clone(),
+                    group_thumbnail: group_info.group_thumbnail.clone(),
+                    content: chat_msg.content,
+                    created_timestamp: chat_msg.created_timestamp,
+                    kind: chat_msg.kind,
+                    msg_type: chat_msg.msg_type,
+                };
+
+                datas.push(f_chat_msg);
+            }
         }
 
-        Ok(datas)
+        Ok(GroupMessagePage {
+            messages: datas,
+            has_more: has_more as u8,
+            before_cursor,
+            after_cursor,
+        })
     }
 
     pub fn get_p2p_message(
         conn: &PgConnection,
         send_id: i64,
         to_id: i64,
-        timestamp: i64,
-        limit: i64,
-        order: i16,
-    ) -> QueryResult<Vec<FrontDisplayP2pChatMessage>> {
-        let mut query = chat_messages::table
-            .filter(chat_messages::send_id.eq_any(vec![send_id, to_id]))
-            .filter(chat_messages::to_id.eq_any(vec![send_id, to_id]))
-            .limit(limit)
-            .into_boxed();
-
-        if timestamp > 0 {
-            if order == 0 {
-                query = query.filter(chat_messages::created_timestamp.gt(timestamp));
-            } else if order == 1 {
-                query = query.filter(chat_messages::created_timestamp.lt(timestamp));
-            }
-        }
-
-        if order == 0 {
-            query = query.order(chat_messages::created_timestamp.asc());
-        } else if order == 1 {
-            query = query.order(chat_messages::created_timestamp.desc());
-        }
-
-        let chat_msgs = query.load::<ChatMessage>(conn)?;
+        page: &PageQuery,
+    ) -> QueryResult<P2pMessagePage> {
+        let (chat_msgs, has_more) = Self::load_page(
+            conn,
+            || {
+                chat_messages::table
+                    .filter(chat_messages::send_id.eq_any(vec![send_id, to_id]))
+                    .filter(chat_messages::to_id.eq_any(vec![send_id, to_id]))
+                    .into_boxed()
+            },
+            page,
+        )?;
+        let (before_cursor, after_cursor) = page.cursors(&chat_msgs);
 
         let mut datas = Vec::new();
 
@@ -393,292 +691,67 @@ impl ChatMessage {
             datas.push(f_chat_msg);
         }
 
-        Ok(datas)
+        Ok(P2pMessagePage {
+            messages: datas,
+            has_more: has_more as u8,
+            before_cursor,
+            after_cursor,
+        })
     }
 }
 
-impl BinaryEncode for FrontDisplayChatMessage {
-    fn encode(&self) -> Result<Vec<u8>> {
-        let mut encoded = Vec::new();
-
-        binary_write_i64(&mut encoded, self.mid)?;
-        binary_write_i64(&mut encoded, self.send_id)?;
-        binary_write_i64(&mut encoded, self.to_id)?;
-        binary_write_string(&mut encoded, self.content.as_str())?;
-        binary_write_i64(&mut encoded, self.created_timestamp)?;
-        binary_write_i16(&mut encoded, self.kind)?;
-        binary_write_i16(&mut encoded, self.msg_type)?;
-
-        //set item length
-        encoded.encode()
-    }
-}
-
-
-impl<'a> BinaryDecode<'a> for FrontDisplayChatMessage {
-    fn decode(
-        cursor: &mut Cursor<&'a [u8]>,
-        bytes: &'a [u8],
-    ) -> Result<FrontDisplayChatMessage> {
-        let mid = binary_read_i64(cursor)?;
-        let send_id = binary_read_i64(cursor)?;
-        let to_id = binary_read_i64(cursor)?;
-        let content = binary_read_string(cursor, bytes)?;
-        let created_timestamp = binary_read_i64(cursor)?;
-        let kind = binary_read_i16(cursor)?;
-        let msg_type = binary_read_i16(cursor)?;
-
-
-        let data = FrontDisplayChatMessage {
-            mid,
-            send_id,
-            to_id,
-            content,
-            created_timestamp,
-            kind,
-            msg_type,
-        };
-
-        Ok(data)
-    }
-}
-
-impl BinaryEncode for FrontDisplayKingdomChatMessage {
-    fn encode(&self) -> Result<Vec<u8>> {
-        let mut encoded = Vec::new();
-
-        binary_write_i64(&mut encoded, self.mid)?;
-        let send_user = self.send_user.encode()?;
-        encoded.extend(send_user);
-        binary_write_i64(&mut encoded, self.to_id)?;
-        binary_write_string(&mut encoded, self.content.as_str())?;
-        binary_write_i64(&mut encoded, self.created_timestamp)?;
-        binary_write_i16(&mut encoded, self.kind)?;
-        binary_write_i16(&mut encoded, self.msg_type)?;
-
-        //set item length
-        encoded.encode()
-    }
-}
-
-impl<'a> BinaryDecode<'a> for FrontDisplayKingdomChatMessage {
-    fn decode(
-        cursor: &mut Cursor<&'a [u8]>,
-        bytes: &'a [u8],
-    ) -> Result<FrontDisplayKingdomChatMessage> {
-        let mid = binary_read_i64(cursor)?;
-
-        let _user_item_length = binary_read_i16(cursor)?;
-        let send_user: FrontDisplayChatUser = deserialize_binary(cursor, bytes)?;
-        let to_id=binary_read_i64(cursor)?;
-        let content = binary_read_string(cursor, bytes)?;
-        let created_timestamp = binary_read_i64(cursor)?;
-        let kind = binary_read_i16(cursor)?;
-        let msg_type = binary_read_i16(cursor)?;
-
-
-        let data = FrontDisplayKingdomChatMessage {
-            mid,
-            send_user,
-            to_id,
-            content,
-            created_timestamp,
-            kind,
-            msg_type,
-        };
-
-        Ok(data)
+impl PushChatMessage {
+    pub fn mid(&self) -> i64 {
+        match self {
+            PushChatMessage::Kingdom(v) => v.mid,
+            PushChatMessage::Group(v) => v.mid,
+            PushChatMessage::P2p(v) => v.mid,
+        }
     }
-}
-
-impl BinaryEncode for Option<FrontDisplayKingdomChatMessage> {
-    fn encode(&self) -> Result<Vec<u8>> {
-        let mut encoded = Vec::new();
-        if self.is_some() {
-            binary_write_i64(&mut encoded, self.as_ref().unwrap().mid)?;
-            let send_user = self.as_ref().unwrap().send_user.encode()?;
-            encoded.extend(send_user);
-            binary_write_i64(&mut encoded, self.as_ref().unwrap().to_id)?;
-            binary_write_string(&mut encoded, self.as_ref().unwrap().content.as_str())?;
-            binary_write_i64(&mut encoded, self.as_ref().unwrap().created_timestamp)?;
-            binary_write_i16(&mut encoded, self.as_ref().unwrap().kind)?;
-            binary_write_i16(&mut encoded, self.as_ref().unwrap().msg_type)?;
 
+    pub fn kind(&self) -> i16 {
+        match self {
+            PushChatMessage::Kingdom(_) => 1,
+            PushChatMessage::Group(_) => 2,
+            PushChatMessage::P2p(_) => 3,
         }
-        //set item length
-        encoded.encode()
     }
 }
 
-impl BinaryEncode for FrontDisplayGroupChatMessage {
-    fn encode(&self) -> Result<Vec<u8>> {
+impl BinaryEncode for PushChatMessage {
+    fn encode_with(&self, prefix: LengthPrefix) -> Result<Vec<u8>> {
         let mut encoded = Vec::new();
 
-        binary_write_i64(&mut encoded, self.mid)?;
-        let send_user = self.send_user.encode()?;
-        encoded.extend(send_user);
-
-        binary_write_i64(&mut encoded, self.gid)?;
-        binary_write_string(&mut encoded, self.group_name.as_str())?;
-        binary_write_string(&mut encoded, self.group_thumbnail.as_str())?;
-        binary_write_string(&mut encoded, self.content.as_str())?;
-        binary_write_i64(&mut encoded, self.created_timestamp)?;
-        binary_write_i16(&mut encoded, self.kind)?;
-        binary_write_i16(&mut encoded, self.msg_type)?;
-
-        //set item length
-        encoded.encode()
-    }
-}
-
-
-impl<'a> BinaryDecode<'a> for FrontDisplayGroupChatMessage {
-    fn decode(
-        cursor: &mut Cursor<&'a [u8]>,
-        bytes: &'a [u8],
-    ) -> Result<FrontDisplayGroupChatMessage> {
-        let mid = binary_read_i64(cursor)?;
-        let _user_item_length = binary_read_i16(cursor)?;
-        let send_user: FrontDisplayChatUser = deserialize_binary(cursor, bytes)?;
-        let gid=binary_read_i64(cursor)?;
-        let group_name = binary_read_string(cursor, bytes)?;
-        let group_thumbnail = binary_read_string(cursor, bytes)?;
-        let content = binary_read_string(cursor, bytes)?;
-        let created_timestamp = binary_read_i64(cursor)?;
-        let kind = binary_read_i16(cursor)?;
-        let msg_type = binary_read_i16(cursor)?;
-
-
-        let data = FrontDisplayGroupChatMessage {
-            mid,
-            send_user,
-            gid,
-            group_name,
-            group_thumbnail,
-            content,
-            created_timestamp,
-            kind,
-            msg_type,
+        binary_write_i16(&mut encoded, self.kind())?;
+        let msg = match self {
+            PushChatMessage::Kingdom(v) => v.encode_with(prefix)?,
+            PushChatMessage::Group(v) => v.encode_with(prefix)?,
+            PushChatMessage::P2p(v) => v.encode_with(prefix)?,
         };
-
-        Ok(data)
-    }
-}
-
-impl BinaryEncode for FrontDisplayP2pChatMessage {
-    fn encode(&self) -> Result<Vec<u8>> {
-        let mut encoded = Vec::new();
-
-        binary_write_i64(&mut encoded, self.mid)?;
-        let send_user = self.send_user.encode()?;
-        encoded.extend(send_user);
-        let dst_user = self.dst_user.encode()?;
-        encoded.extend(dst_user);
-        binary_write_string(&mut encoded, self.content.as_str())?;
-        binary_write_i64(&mut encoded, self.created_timestamp)?;
-        binary_write_i16(&mut encoded, self.kind)?;
-        binary_write_i16(&mut encoded, self.msg_type)?;
+        encoded.extend(msg);
 
         //set item length
-        encoded.encode()
+        encoded.encode_with(prefix)
     }
 }
 
-impl<'a> BinaryDecode<'a> for FrontDisplayP2pChatMessage {
-    fn decode(
+impl<'a> BinaryDecode<'a> for PushChatMessage {
+    fn decode_with(
         cursor: &mut Cursor<&'a [u8]>,
         bytes: &'a [u8],
-    ) -> Result<FrontDisplayP2pChatMessage> {
-        let mid = binary_read_i64(cursor)?;
-        let _user_item_length = binary_read_i16(cursor)?;
-        let send_user: FrontDisplayChatUser = deserialize_binary(cursor, bytes)?;
-        let _user_item_length = binary_read_i16(cursor)?;
-        let dst_user: FrontDisplayChatUser = deserialize_binary(cursor, bytes)?;
-        let content = binary_read_string(cursor, bytes)?;
-        let created_timestamp = binary_read_i64(cursor)?;
+        prefix: LengthPrefix,
+    ) -> Result<PushChatMessage> {
         let kind = binary_read_i16(cursor)?;
-        let msg_type = binary_read_i16(cursor)?;
+        let _item_length = prefix.read(cursor)?;
 
-
-        let data = FrontDisplayP2pChatMessage {
-            mid,
-            send_user,
-            dst_user,
-            content,
-            created_timestamp,
-            kind,
-            msg_type,
+        let data = match kind {
+            1 => PushChatMessage::Kingdom(BinaryDecode::decode_with(cursor, bytes, prefix)?),
+            2 => PushChatMessage::Group(BinaryDecode::decode_with(cursor, bytes, prefix)?),
+            3 => PushChatMessage::P2p(BinaryDecode::decode_with(cursor, bytes, prefix)?),
+            _ => return Err(anyhow!("invalid push message kind:{}", kind)),
         };
 
         Ok(data)
     }
 }
 
-impl BinaryEncode for FrontDisplayP2pChatMessageCount {
-    fn encode(&self) -> Result<Vec<u8>> {
-        let mut encoded = Vec::new();
-        encoded.write_i64::<LittleEndian>(self.mid)?;
-        let content = self.content.as_bytes();
-        encoded.write_i16::<LittleEndian>(content.len() as i16)?;
-        encoded.extend_from_slice(content);
-        encoded.write_i64::<LittleEndian>(self.created_timestamp)?;
-        encoded.write_i16::<LittleEndian>(self.kind)?;
-        encoded.write_i16::<LittleEndian>(self.msg_type)?;
-
-        //set item length
-        encoded.encode()
-    }
-}
-impl<'a> BinaryDecode<'a> for FrontDisplayP2pChatMessageCount {
-    fn decode(
-        cursor: &mut Cursor<&'a [u8]>,
-        bytes: &'a [u8],
-    ) -> Result<FrontDisplayP2pChatMessageCount> {
-        let mid = binary_read_i64(cursor)?;
-        let content = binary_read_string(cursor, bytes)?;
-        let created_timestamp = binary_read_i64(cursor)?;
-        let kind = binary_read_i16(cursor)?;
-        let msg_type = binary_read_i16(cursor)?;
-
-
-        let data = FrontDisplayP2pChatMessageCount {
-            mid,
-            content,
-            created_timestamp,
-            kind,
-            msg_type,
-        };
-
-        Ok(data)
-    }
-}
-
-impl BinaryEncode for FrontDisplayChatMessageUnreadCount {
-    fn encode(&self) -> Result<Vec<u8>> {
-        let mut encoded = Vec::new();
-
-        binary_write_i16(&mut encoded, self.unread_count)?;
-        binary_write_i16(&mut encoded, self.kind)?;
-
-        //set item length
-        encoded.encode()
-    }
-}
-
-
-impl<'a> BinaryDecode<'a> for FrontDisplayChatMessageUnreadCount {
-    fn decode(
-        cursor: &mut Cursor<&'a [u8]>,
-        _bytes: &'a [u8],
-    ) -> Result<FrontDisplayChatMessageUnreadCount> {
-        let unread_count = binary_read_i16(cursor)?;
-        let kind = binary_read_i16(cursor)?;
-
-        let data = FrontDisplayChatMessageUnreadCount {
-            unread_count,
-            kind
-        };
-
-        Ok(data)
-    }
-}
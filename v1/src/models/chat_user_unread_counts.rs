@@ -1,16 +1,13 @@
 use crate::schema::chat_user_unread_counts;
 use crate::{
-    get_guid_value, models::chat_messages::ChatMessage,
+    next_id, models::chat_messages::ChatMessage, models::chat_messages::MessageCursor,
     models::chat_messages::FrontDisplayP2pChatMessageCount, models::user::FrontDisplayChatUser,
     models::user::User,
-    BinaryEncode, BinaryDecode, deserialize_binary, utils::binary_helper::*
+    BinaryEncode, BinaryDecode,
 };
-use anyhow::Result;
-use byteorder::{LittleEndian, WriteBytesExt};
 use chrono::NaiveDateTime;
 use chrono::Utc;
 use diesel::prelude::*;
-use std::io::Cursor;
 use serde::{Serialize,Deserialize};
 
 #[derive(Debug, Clone, Identifiable, Queryable, Associations)]
@@ -23,9 +20,10 @@ pub struct ChatUserUnreadCount {
     pub unread_count: i16,
     pub modify_time: NaiveDateTime,
     pub created_time: NaiveDateTime,
+    pub latest_mid: i64, //with latest_timestamp the newest message read
 }
 
-#[derive(Debug, Clone, Queryable,Serialize,Deserialize)]
+#[derive(Debug, Clone, Queryable,Serialize,Deserialize, BinaryEncode, BinaryDecode)]
 pub struct FrontDisplayChatUserUnreadCount {
     pub sender: FrontDisplayChatUser,
     pub receiver: FrontDisplayChatUser,
@@ -45,6 +43,10 @@ pub struct NewChatUserUnreadCount {
 }
 
 impl ChatUserUnreadCount {
+    pub fn read_cursor(&self) -> MessageCursor {
+        MessageCursor { created_timestamp: self.latest_timestamp, mid: self.latest_mid }
+    }
+
     pub fn add(
         conn: &PgConnection,
         uuid_s: i64,
@@ -81,7 +83,7 @@ impl ChatUserUnreadCount {
 
         if add {
             let data = NewChatUserUnreadCount {
-                ucid: get_guid_value() as i64,
+                ucid: next_id(),
                 uuid_s,
                 uuid_d,
                 latest_timestamp: 0,
@@ -110,8 +112,9 @@ impl ChatUserUnreadCount {
         for p2p_unread in p2p_unreads.into_iter() {
             let msg = ChatMessage::get_p2p_unread_count_latest_message(
                 conn,
+                p2p_unread.uuid_d,
                 p2p_unread.uuid_s,
-                p2p_unread.latest_timestamp,
+                &p2p_unread.read_cursor(),
             )?;
 
             let sender = User::get_front_display_chat_user_info(conn, p2p_unread.uuid_d)?;
@@ -141,69 +144,58 @@ impl ChatUserUnreadCount {
             .get_result(conn)
     }
 
-    pub fn update_user_unread_count(
+    //the counter row locked until the transaction ends, a concurrent send waits before bumping unread_count.
+    pub fn lock_user_unread_count(
+        conn: &PgConnection,
+        uid_s: i64,
+        uid_d: i64,
+    ) -> QueryResult<ChatUserUnreadCount> {
+        chat_user_unread_counts::table
+            .filter(chat_user_unread_counts::uuid_s.eq(uid_s))
+            .filter(chat_user_unread_counts::uuid_d.eq(uid_d))
+            .for_update()
+            .first(conn)
+    }
+
+    //move the read position to cursor with what is left unread, an older cursor is ignored.
+    pub fn mark_read(
         conn: &PgConnection,
         uid_s: i64,
         uid_d: i64,
+        cursor: &MessageCursor,
+        unread_count: i16,
     ) -> QueryResult<()> {
+        use crate::schema::chat_user_unread_counts::dsl::{latest_mid, latest_timestamp};
+
         let now = Utc::now();
         diesel::update(chat_user_unread_counts::table)
             .set((
-                chat_user_unread_counts::latest_timestamp.eq(now.timestamp_millis()),
-                chat_user_unread_counts::unread_count.eq(0),
+                latest_timestamp.eq(cursor.created_timestamp),
+                latest_mid.eq(cursor.mid),
+                chat_user_unread_counts::unread_count.eq(unread_count),
                 chat_user_unread_counts::modify_time.eq(now.naive_local()),
             ))
             .filter(chat_user_unread_counts::uuid_s.eq(uid_s))
             .filter(chat_user_unread_counts::uuid_d.eq(uid_d))
+            .filter(
+                latest_timestamp
+                    .lt(cursor.created_timestamp)
+                    .or(latest_timestamp.eq(cursor.created_timestamp).and(latest_mid.le(cursor.mid))),
+            )
             .execute(conn)?;
 
         Ok(())
     }
-}
-
-impl BinaryEncode for FrontDisplayChatUserUnreadCount {
-    fn encode(&self) -> Result<Vec<u8>> {
-        let mut encoded = Vec::new();
 
-        let sender = self.sender.encode()?;
-        encoded.extend(sender);
-        let receiver = self.receiver.encode()?;
-        encoded.extend(receiver);
+    //unread p2p messages of uid_s from every peer.
+    pub fn get_unread_total(conn: &PgConnection, uid_s: i64) -> QueryResult<i64> {
+        use diesel::expression::dsl::sum;
 
-        encoded.write_i64::<LittleEndian>(self.latest_timestamp)?;
-        encoded.write_i16::<LittleEndian>(self.unread_count)?;
-        let msg = self.latest_msg.encode()?;
-        encoded.extend(msg);
+        let total: Option<i64> = chat_user_unread_counts::table
+            .filter(chat_user_unread_counts::uuid_s.eq(uid_s))
+            .select(sum(chat_user_unread_counts::unread_count))
+            .first(conn)?;
 
-        //set item length
-        encoded.encode()
+        Ok(total.unwrap_or(0))
     }
 }
-
-
-impl<'a> BinaryDecode<'a> for FrontDisplayChatUserUnreadCount {
-    fn decode(
-        cursor: &mut Cursor<&'a [u8]>,
-        bytes: &'a [u8],
-    ) -> Result<FrontDisplayChatUserUnreadCount> {
-        let _user_item_length = binary_read_i16(cursor)?;
-        let sender: FrontDisplayChatUser = deserialize_binary(cursor, bytes)?;
-        let _user_item_length = binary_read_i16(cursor)?;
-        let receiver: FrontDisplayChatUser = deserialize_binary(cursor, bytes)?;
-        let latest_timestamp = binary_read_i64(cursor)?;
-        let unread_count = binary_read_i16(cursor)?;
-        let _user_item_length = binary_read_i16(cursor)?;
-        let latest_msg: FrontDisplayP2pChatMessageCount = deserialize_binary(cursor, bytes)?;
-
-
-        let data = FrontDisplayChatUserUnreadCount {
-            sender,
-            receiver,
-            latest_timestamp,
-            unread_count,
-            latest_msg
-        };
-
-        Ok(data)
-    }
-}
\ No newline at end of file
@@ -1,6 +1,7 @@
 pub mod blacklist;
 pub mod chat_groups;
 pub mod chat_groups_uids;
+pub mod chat_kingdom_reads;
 pub mod chat_messages;
 pub mod friends;
 pub mod user_link_accounts;
@@ -1,10 +1,7 @@
 use crate::schema::{servers, users};
-use anyhow::Result;
-use byteorder::{LittleEndian, WriteBytesExt};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use crate::{BinaryEncode, BinaryDecode, utils::binary_helper::*};
-use std::io::Cursor;
+use crate::{BinaryEncode, BinaryDecode};
 use serde::{Serialize,Deserialize};
 
 
@@ -38,7 +35,7 @@ pub struct NewUser<'a> {
     pub action_points_latest_timestamp: i64,
 }
 
-#[derive(Clone, Debug, Queryable,Serialize,Deserialize)]
+#[derive(Clone, Debug, Queryable,Serialize,Deserialize, BinaryEncode, BinaryDecode)]
 pub struct FrontDisplayChatUser {
     pub uuid: i64,
     pub uid: i32,
@@ -58,6 +55,18 @@ impl User {
             .first(conn)
     }
 
+    pub fn get_online_uuids_by_server_id(
+        conn: &PgConnection,
+        server_id: i32,
+        uuids: Vec<i64>,
+    ) -> QueryResult<Vec<i64>> {
+        users::table
+            .filter(users::server_id.eq(server_id))
+            .filter(users::uuid.eq_any(uuids))
+            .select(users::uuid)
+            .load(conn)
+    }
+
     pub fn get_front_display_chat_user_info(
         conn: &PgConnection,
         uuid: i64,
@@ -76,44 +85,3 @@ impl User {
     }
 }
 
-impl BinaryEncode for FrontDisplayChatUser {
-    fn encode(&self) -> Result<Vec<u8>> {
-        let mut encoded = Vec::new();
-        encoded.write_i64::<LittleEndian>(self.uuid)?;
-        encoded.write_i32::<LittleEndian>(self.uid)?;
-
-        let name = self.name.as_bytes();
-        encoded.write_i16::<LittleEndian>(name.len() as i16)?;
-        encoded.extend_from_slice(name);
-
-        let avatar = self.avatar.as_bytes();
-        encoded.write_i16::<LittleEndian>(avatar.len() as i16)?;
-        encoded.extend_from_slice(avatar);
-        encoded.write_i32::<LittleEndian>(self.server_id)?;
-        encoded.write_i32::<LittleEndian>(self.action_points)?;
-
-        //set item length
-        encoded.encode()
-    }
-}
-
-impl<'a> BinaryDecode<'a> for FrontDisplayChatUser {
-    fn decode(cursor: &mut Cursor<&'a [u8]>, bytes: &'a [u8]) -> Result<FrontDisplayChatUser> {
-        let uuid = binary_read_i64(cursor)?;
-        let uid = binary_read_i32(cursor)?;
-        let name = binary_read_string(cursor, bytes)?;
-        let avatar = binary_read_string(cursor, bytes)?;
-        let server_id = binary_read_i32(cursor)?;
-        let action_points = binary_read_i32(cursor)?;
-
-        let data = FrontDisplayChatUser {
-            uuid,
-            uid,
-            name,
-            avatar,
-            server_id,
-            action_points,
-        };
-        Ok(data)
-    }
-}
\ No newline at end of file
@@ -1,9 +1,16 @@
 use crate::chat_system::chat;
+use crate::utils::middleware::{
+    AuthMiddleware, LoggingMiddleware, MetricsMiddleware, PanicMiddleware, RateLimitMiddleware,
+    RouterMetrics,
+};
+use crate::utils::common::CURSOR_PAGE_VERSION;
 use crate::RouterRegister;
 use std::sync::Arc;
 // use std::fmt;
 #[derive(Debug,Clone, PartialEq, Hash, Eq, Copy)]
 pub enum RouterCode {
+    //any code this server does not know, e.g. from a client built for a newer protocol.
+    Unknown = 0,
     ConnectionState = 2001,
     SendMessage = 2002,
     PushMessage = 2003,
@@ -12,6 +19,10 @@ pub enum RouterCode {
     GetGroupMessageContent = 2006,
     GetP2pUserMessageContent = 2007,
     GetChannelChatMessageUnreadCount = 2008,
+    Heartbeat = 2009,
+    MarkRead = 2010,
+    //server push only, the badge after a read on another device.
+    UnreadBadge = 2011,
 }
 
 impl RouterCode {
@@ -25,7 +36,10 @@ impl RouterCode {
             2006 => RouterCode::GetGroupMessageContent,
             2007 => RouterCode::GetP2pUserMessageContent,
             2008 => RouterCode::GetChannelChatMessageUnreadCount,
-            _ => RouterCode::ConnectionState,
+            2009 => RouterCode::Heartbeat,
+            2010 => RouterCode::MarkRead,
+            2011 => RouterCode::UnreadBadge,
+            _ => RouterCode::Unknown,
         }
     }
 }
@@ -38,36 +52,59 @@ impl RouterCode {
 //     }
 // }
 
-pub fn build_routers() -> Arc<RouterRegister> {
+pub fn build_routers(metrics: Arc<RouterMetrics>, rate_limit_per_second: u32) -> Arc<RouterRegister> {
     let mut routers = RouterRegister::new();
-    routers.add(RouterCode::ConnectionState, chat::connection_state);
+    routers.layer(PanicMiddleware);
+    routers.layer(LoggingMiddleware);
+    routers.layer(MetricsMiddleware::new(metrics));
+    routers.layer(RateLimitMiddleware::new(rate_limit_per_second));
+    routers.layer(AuthMiddleware);
 
-    routers.add(RouterCode::SendMessage, chat::send_message);
+    routers.add_request(RouterCode::ConnectionState, chat::connection_state);
 
-    routers.add(
+    routers.add_request(RouterCode::SendMessage, chat::send_message);
+
+    routers.add_request(
         RouterCode::PushMessage,
         chat::client_feedback_server_message,
     );
-    routers.add(
+    routers.add_request(
         RouterCode::GetUserUnReadMessageCount,
         chat::get_user_message_unread_count,
     );
-    routers.add(
+    routers.add_request(
         RouterCode::GetKingdomMessageContent,
         chat::get_kingdom_message_content,
     );
-    routers.add(
+    routers.add_request(
         RouterCode::GetGroupMessageContent,
         chat::get_group_message_content,
     );
-    routers.add(
+    routers.add_request(
         RouterCode::GetP2pUserMessageContent,
         chat::get_p2p_user_message_content,
     );
-    routers.add(
+    routers.add_version_request(
+        RouterCode::GetKingdomMessageContent,
+        CURSOR_PAGE_VERSION,
+        chat::get_kingdom_message_page,
+    );
+    routers.add_version_request(
+        RouterCode::GetGroupMessageContent,
+        CURSOR_PAGE_VERSION,
+        chat::get_group_message_page,
+    );
+    routers.add_version_request(
+        RouterCode::GetP2pUserMessageContent,
+        CURSOR_PAGE_VERSION,
+        chat::get_p2p_user_message_page,
+    );
+    routers.add_request(
         RouterCode::GetChannelChatMessageUnreadCount,
         chat::get_chat_channel_unread_count,
     );
+    routers.add(RouterCode::Heartbeat, chat::heartbeat);
+    routers.add_request(RouterCode::MarkRead, chat::mark_read);
 
     Arc::new(routers)
 }
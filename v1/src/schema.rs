@@ -195,6 +195,62 @@ table! {
         ///
         /// (Automatically generated by Diesel.)
         created_time -> Timestamp,
+        /// The `latest_mid` column of the `chat_groups_uids` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        latest_mid -> Int8,
+    }
+}
+
+table! {
+    /// Representation of the `chat_kingdom_reads` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    chat_kingdom_reads (krid) {
+        /// The `krid` column of the `chat_kingdom_reads` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        krid -> Int8,
+        /// The `uuid` column of the `chat_kingdom_reads` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        uuid -> Int8,
+        /// The `kingdom_id` column of the `chat_kingdom_reads` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kingdom_id -> Int8,
+        /// The `latest_timestamp` column of the `chat_kingdom_reads` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        latest_timestamp -> Int8,
+        /// The `latest_mid` column of the `chat_kingdom_reads` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        latest_mid -> Int8,
+        /// The `modify_time` column of the `chat_kingdom_reads` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        modify_time -> Timestamp,
+        /// The `created_time` column of the `chat_kingdom_reads` table.
+        ///
+        /// Its SQL type is `Timestamp`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_time -> Timestamp,
     }
 }
 
@@ -307,6 +363,12 @@ table! {
         ///
         /// (Automatically generated by Diesel.)
         created_time -> Timestamp,
+        /// The `latest_mid` column of the `chat_user_unread_counts` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        latest_mid -> Int8,
     }
 }
 
@@ -2801,6 +2863,7 @@ allow_tables_to_appear_in_same_query!(
     buff_metadatas,
     chat_groups,
     chat_groups_uids,
+    chat_kingdom_reads,
     chat_messages,
     chat_user_unread_counts,
     enemys,
@@ -0,0 +1,501 @@
+use crate::chat_system::presence::connection_closed;
+use crate::utils::common::LengthPrefix;
+use crate::chat_system::push::{redeliver_unacked_pushes, subscribe_chat_messages};
+use crate::{
+    Broker, ChatStore, Clients, Connection, FrameError, Message, MessageCodec, MessageStateCode, PendingPushes,
+    ReplayError, ReplayGuard, RequestFrame, ResponseContext, RouterCode, RouterRegister, Session,
+    SendDedup, SigningKeys, SocketSender, ThreadPool, TokenVerifier,
+};
+use crate::default_log_pre;
+use anyhow::{anyhow, Context, Result};
+use function_name::named;
+use bytes::BytesMut;
+use futures::{future, stream, SinkExt, Stream, StreamExt};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{accept_async, WebSocketStream};
+use tokio_util::codec::{Decoder, FramedRead};
+use tracing::{error, info};
+
+//shared by every connection the server accepts, whatever the transport.
+pub struct ServerContext {
+    pub routers: Arc<RouterRegister>,
+    pub clients: Clients,
+    pub pending_pushes: PendingPushes,
+    pub store: Arc<dyn ChatStore>,
+    pub pool: Arc<ThreadPool>,
+    pub verifier: Arc<dyn TokenVerifier>,
+    pub broker: Arc<dyn Broker>,
+    pub signing_keys: SigningKeys,
+    pub replay_guard: ReplayGuard,
+    pub send_dedup: Arc<dyn SendDedup>,
+    pub max_frame_length: usize,
+    pub idle_timeout: Duration,
+    next_conn_id: AtomicU64,
+}
+
+impl ServerContext {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        routers: Arc<RouterRegister>,
+        clients: Clients,
+        pending_pushes: PendingPushes,
+        store: Arc<dyn ChatStore>,
+        pool: Arc<ThreadPool>,
+        verifier: Arc<dyn TokenVerifier>,
+        broker: Arc<dyn Broker>,
+        signing_keys: SigningKeys,
+        replay_guard: ReplayGuard,
+        send_dedup: Arc<dyn SendDedup>,
+        max_frame_length: usize,
+        idle_timeout: Duration,
+    ) -> Self {
+        ServerContext {
+            routers,
+            clients,
+            pending_pushes,
+            store,
+            pool,
+            verifier,
+            broker,
+            signing_keys,
+            replay_guard,
+            send_dedup,
+            max_frame_length,
+            idle_timeout,
+            next_conn_id: AtomicU64::new(1),
+        }
+    }
+
+    pub fn next_conn_id(&self) -> u64 {
+        self.next_conn_id.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+//where each transport listens, port 0 binds an ephemeral port. every transport is optional.
+#[derive(Clone)]
+pub struct ChatServerConfig {
+    pub tcp_addr: Option<SocketAddr>,
+    pub tls: Option<(SocketAddr, TlsAcceptor)>,
+    pub ws_addr: Option<SocketAddr>,
+    pub push_ack_timeout: Duration,
+    pub push_max_attempts: u32,
+}
+
+//a running server: listeners, push redelivery and the publish subscriber.
+pub struct ChatServer {
+    pub ctx: Arc<ServerContext>,
+    pub tcp_addr: Option<SocketAddr>,
+    pub tls_addr: Option<SocketAddr>,
+    pub ws_addr: Option<SocketAddr>,
+    listeners: Vec<JoinHandle<()>>,
+}
+
+impl ChatServer {
+    #[named]
+    pub async fn start(ctx: Arc<ServerContext>, config: ChatServerConfig) -> Result<ChatServer> {
+        if config.tcp_addr.is_none() && config.tls.is_none() && config.ws_addr.is_none() {
+            return Err(anyhow!("no listener configured."));
+        }
+
+        let mut server = ChatServer {
+            ctx: ctx.clone(),
+            tcp_addr: None,
+            tls_addr: None,
+            ws_addr: None,
+            listeners: Vec::new(),
+        };
+
+        if let Some(addr) = config.tcp_addr {
+            let listener = TcpListener::bind(addr).await?;
+            let addr = listener.local_addr()?;
+            info!("{}\tlisten tcp addr:{}", default_log_pre!("",""), addr);
+            server.tcp_addr = Some(addr);
+            server.listeners.push(tokio::spawn(serve_tcp(ctx.clone(), listener)));
+        }
+
+        if let Some((addr, acceptor)) = config.tls {
+            let listener = TcpListener::bind(addr).await?;
+            let addr = listener.local_addr()?;
+            info!("{}\tlisten tls addr:{}", default_log_pre!("",""), addr);
+            server.tls_addr = Some(addr);
+            server.listeners.push(tokio::spawn(serve_tls(ctx.clone(), listener, acceptor)));
+        }
+
+        if let Some(addr) = config.ws_addr {
+            let listener = TcpListener::bind(addr).await?;
+            let addr = listener.local_addr()?;
+            info!("{}\tlisten websocket addr:{}", default_log_pre!("",""), addr);
+            server.ws_addr = Some(addr);
+            server.listeners.push(tokio::spawn(serve_websocket(ctx.clone(), listener)));
+        }
+
+        tokio::spawn(redeliver_unacked_pushes(
+            ctx.clients.clone(),
+            ctx.pending_pushes.clone(),
+            config.push_ack_timeout,
+            config.push_max_attempts,
+        ));
+
+        subscribe_chat_messages(
+            ctx.broker.clone(),
+            ctx.clients.clone(),
+            ctx.pending_pushes.clone(),
+            ctx.pool.clone(),
+            ctx.store.clone(),
+        );
+
+        Ok(server)
+    }
+
+    //runs until every listener stops.
+    pub async fn wait(self) {
+        futures::future::join_all(self.listeners).await;
+    }
+}
+
+//load the pem certificate chain and private key(pkcs8 or rsa) for the tls listener.
+pub fn load_tls_acceptor(cert_file: &str, key_file: &str) -> Result<TlsAcceptor> {
+    let cert_chain = certs(&mut BufReader::new(
+        File::open(cert_file).with_context(|| format!("failed open tls cert:{}", cert_file))?,
+    ))
+    .map_err(|_| anyhow!("invalid tls cert:{}", cert_file))?;
+
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(
+        File::open(key_file).with_context(|| format!("failed open tls key:{}", key_file))?,
+    ))
+    .map_err(|_| anyhow!("invalid tls key:{}", key_file))?;
+
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut BufReader::new(File::open(key_file)?))
+            .map_err(|_| anyhow!("invalid tls key:{}", key_file))?;
+    }
+
+    if keys.is_empty() {
+        return Err(anyhow!("no private key found in:{}", key_file));
+    }
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(cert_chain, keys.remove(0))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[named]
+pub async fn serve_tcp(ctx: Arc<ServerContext>, mut listener: TcpListener) {
+    let mut incoming = listener.incoming();
+
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Err(e) => {
+                error!("{}\tfailed tcp socket recv message:{:?}\t", default_log_pre!("",""), e);
+            }
+            Ok(sock) => {
+                tokio::spawn(handle_stream_connection(ctx.clone(), sock));
+            }
+        }
+    }
+}
+
+#[named]
+pub async fn serve_tls(ctx: Arc<ServerContext>, mut listener: TcpListener, acceptor: TlsAcceptor) {
+    let mut incoming = listener.incoming();
+
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Err(e) => {
+                error!("{}\tfailed tls socket recv message:{:?}\t", default_log_pre!("",""), e);
+            }
+            Ok(sock) => {
+                let ctx = ctx.clone();
+                let acceptor = acceptor.clone();
+
+                tokio::spawn(async move {
+                    match acceptor.accept(sock).await {
+                        Ok(stream) => handle_stream_connection(ctx, stream).await,
+                        Err(e) => {
+                            error!("{}\tfailed tls handshake:{:?}\t", default_log_pre!("",""), e);
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[named]
+pub async fn serve_websocket(ctx: Arc<ServerContext>, mut listener: TcpListener) {
+    let mut incoming = listener.incoming();
+
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Err(e) => {
+                error!("{}\tfailed websocket recv message:{:?}\t", default_log_pre!("",""), e);
+            }
+            Ok(sock) => {
+                let ctx = ctx.clone();
+
+                tokio::spawn(async move {
+                    match accept_async(sock).await {
+                        Ok(ws) => handle_websocket_connection(ctx, ws).await,
+                        Err(e) => {
+                            error!("{}\tfailed websocket handshake:{:?}\t", default_log_pre!("",""), e);
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
+
+//serve one byte stream connection, plain tcp or tls.
+#[named]
+pub async fn handle_stream_connection<S>(ctx: Arc<ServerContext>, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let conn_id = ctx.next_conn_id();
+    let (recv, mut send) = tokio::io::split(stream);
+    let (sender, mut outbound) = mpsc::unbounded_channel::<Vec<u8>>();
+
+    //writer task, ends once every sender of this connection is dropped.
+    tokio::spawn(async move {
+        while let Some(body) = outbound.recv().await {
+            if let Err(e) = send.write_all(&body).await {
+                error!("{}\tfailed write socket conn_id:{}\terror:{:?}", default_log_pre!("",""), conn_id, e);
+                return;
+            }
+        }
+        let _ = send.shutdown().await;
+    });
+
+    let frames = FramedRead::new(recv, MessageCodec::new(ctx.max_frame_length));
+    process_frames(ctx.clone(), conn_id, frames, sender).await;
+
+    connection_closed(&ctx.pool, ctx.broker.clone(), ctx.clients.clone(), ctx.pending_pushes.clone(), conn_id).await;
+}
+
+//serve one websocket connection, every binary message carries the same frames as the tcp protocol.
+#[named]
+pub async fn handle_websocket_connection<S>(ctx: Arc<ServerContext>, ws: WebSocketStream<S>)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let conn_id = ctx.next_conn_id();
+    let (mut ws_send, ws_recv) = ws.split();
+    let (sender, mut outbound) = mpsc::unbounded_channel::<Vec<u8>>();
+
+    tokio::spawn(async move {
+        while let Some(body) = outbound.recv().await {
+            if let Err(e) = ws_send.send(WsMessage::Binary(body)).await {
+                error!("{}\tfailed write websocket conn_id:{}\terror:{:?}", default_log_pre!("",""), conn_id, e);
+                return;
+            }
+        }
+        let _ = ws_send.close().await;
+    });
+
+    let mut codec = MessageCodec::new(ctx.max_frame_length);
+    let frames = ws_recv
+        .take_while(|msg| future::ready(!matches!(msg, Ok(WsMessage::Close(_)))))
+        .flat_map(move |msg| {
+            let frames = match msg {
+                Ok(msg) => decode_websocket_message(&mut codec, msg),
+                Err(e) => vec![Err(FrameError::Io(io::Error::new(io::ErrorKind::Other, e)))],
+            };
+            stream::iter(frames)
+        });
+
+    process_frames(ctx.clone(), conn_id, Box::pin(frames), sender).await;
+
+    connection_closed(&ctx.pool, ctx.broker.clone(), ctx.clients.clone(), ctx.pending_pushes.clone(), conn_id).await;
+}
+
+fn decode_websocket_message(
+    codec: &mut MessageCodec,
+    msg: WsMessage,
+) -> Vec<Result<RequestFrame, FrameError>> {
+    let data = match msg {
+        WsMessage::Binary(v) => v,
+        WsMessage::Text(_) => {
+            return vec![Err(FrameError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "text websocket message not supported.",
+            )))];
+        }
+        //ping/pong are answered by the websocket layer.
+        _ => return vec![],
+    };
+
+    let mut buf = BytesMut::from(&data[..]);
+    let mut frames = Vec::new();
+
+    loop {
+        match codec.decode(&mut buf) {
+            Ok(Some(frame)) => frames.push(Ok(frame)),
+            Ok(None) => break,
+            Err(e) => {
+                frames.push(Err(e));
+                return frames;
+            }
+        }
+    }
+
+    //a frame may not span websocket messages.
+    if !buf.is_empty() {
+        frames.push(Err(FrameError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "incomplete frame in websocket message.",
+        ))));
+    }
+
+    frames
+}
+
+//read, verify and dispatch request frames until the peer leaves, idles out or misbehaves.
+#[named]
+pub async fn process_frames<F>(ctx: Arc<ServerContext>, conn_id: u64, mut frames: F, sender: SocketSender)
+where
+    F: Stream<Item = Result<RequestFrame, FrameError>> + Unpin,
+{
+    let session = Arc::new(Session::new(conn_id));
+
+    loop {
+        let frame = match timeout(ctx.idle_timeout, frames.next()).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(_) => {
+                info!("{}\tidle timeout conn_id:{}\t", default_log_pre!("",""), conn_id);
+                break;
+            }
+        };
+
+        let frame = match frame {
+            Ok(v) => v,
+            Err(FrameError::TooLarge { code, version, session_id, length, max }) => {
+                error!("{}\tframe too large length:{}\tmax:{}\t", default_log_pre!(code,""), length, max);
+                respond(&sender, code, session_id, version, MessageStateCode::GeneralError, "frame too large.");
+                break;
+            }
+            Err(FrameError::Io(ref e)) if e.kind() == tokio::io::ErrorKind::ConnectionReset => {
+                break;
+            }
+            Err(e) => {
+                error!("{}\tfailed socket recv message:{:?}\t", default_log_pre!("",""), e);
+                break;
+            }
+        };
+
+        let code = frame.code;
+        let session_id = frame.session_id;
+        let version = frame.version;
+
+        //signature valid
+        if let Err(e) = ctx.signing_keys.verify(
+            frame.key_id,
+            &frame.signature,
+            code,
+            version,
+            session_id,
+            frame.timestamp,
+            &frame.body,
+        ) {
+            error!("{}\tinvalid signature formats:{:?}\t", default_log_pre!(code,""), e);
+            respond(&sender, code, session_id, version, MessageStateCode::GeneralError, "invalid signature.");
+            break;
+        }
+
+        //replay protection
+        if let Err(e) = ctx.replay_guard.check(session_id, frame.timestamp, &frame.signature) {
+            error!("{}\trejected replay request:{}\t", default_log_pre!(code,""), e);
+
+            let state = match e {
+                ReplayError::Expired { .. } => MessageStateCode::RequestExpired,
+                ReplayError::Replayed => MessageStateCode::RequestReplayed,
+                ReplayError::CacheFull => MessageStateCode::GeneralError,
+            };
+            respond(&sender, code, session_id, version, state, e.to_string().as_str());
+            continue;
+        }
+
+        let code_enum = RouterCode::from_u16(code);
+
+        //unknown codes get an explicit answer instead of being routed anywhere
+        if code_enum == RouterCode::Unknown {
+            error!("{}\tunknown router code conn_id:{}\t", default_log_pre!(code,""), conn_id);
+            respond(&sender, code, session_id, version, MessageStateCode::NotFound, "unknown route.");
+            continue;
+        }
+
+        let msg = Message {
+            code: code_enum,
+            version,
+            session_id,
+            body_len: frame.body.len() as u32,
+            body: frame.body,
+        };
+
+        let conn = Connection {
+            socket: sender.clone(),
+            store: ctx.store.clone(),
+            pool: ctx.pool.clone(),
+            pending_pushes: ctx.pending_pushes.clone(),
+            session: session.clone(),
+            verifier: ctx.verifier.clone(),
+            broker: ctx.broker.clone(),
+            send_dedup: ctx.send_dedup.clone(),
+            msg,
+            claimed_uid: None,
+        };
+
+        match ctx.routers.call(code_enum, version) {
+            Ok(f) => {
+                let resp = match f(ctx.clients.clone(), conn).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("{}\tfailed method exec:{:?}", default_log_pre!(code,""), e);
+                        respond(&sender, code, session_id, version, MessageStateCode::GeneralError, &e.to_string());
+                        break;
+                    }
+                };
+
+                if sender.send(resp).is_err() {
+                    break;
+                }
+            }
+
+            Err(e) => {
+                error!("{}\trouter code not found:{:?}.\t", default_log_pre!(code,""), e);
+                respond(&sender, code, session_id, version, MessageStateCode::NotFound, "unsupported route version.");
+                continue;
+            }
+        }
+    }
+}
+
+#[named]
+fn respond(sender: &SocketSender, code: u16, session_id: u64, version: u8, state: MessageStateCode, msg: &str) {
+    let prefix = LengthPrefix::for_version(version);
+    let resp = match ResponseContext::get_bincode_with(code, session_id, state, msg, "", prefix) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}\tfialed encode response:{:?}\t", default_log_pre!(code,""), e);
+            return;
+        }
+    };
+
+    let _ = sender.send(resp);
+}
@@ -0,0 +1,39 @@
+use super::redis_db::RedisPool;
+use anyhow::{anyhow, Result};
+use redis::Commands;
+use std::sync::Arc;
+
+pub const LOGIN_TOKEN_REDIS_KEY_PREFIX: &str = "chat_login_token_"; //format->(uid),value->token issued by game server
+
+//validates the login token presented on connection_state.
+pub trait TokenVerifier: Send + Sync {
+    fn verify(&self, uid: u64, token: &str) -> Result<()>;
+}
+
+//token written to redis by the game server at login.
+pub struct RedisTokenVerifier {
+    redis: Arc<RedisPool>,
+}
+
+impl RedisTokenVerifier {
+    pub fn new(redis: Arc<RedisPool>) -> Self {
+        RedisTokenVerifier { redis }
+    }
+}
+
+impl TokenVerifier for RedisTokenVerifier {
+    fn verify(&self, uid: u64, token: &str) -> Result<()> {
+        if token.is_empty() {
+            return Err(anyhow!("invalid token."));
+        }
+
+        let mut redis_conn = self.redis.get()?;
+        let key = format!("{}{}", LOGIN_TOKEN_REDIS_KEY_PREFIX, uid);
+        let stored: Option<String> = redis_conn.get(&key)?;
+
+        match stored {
+            Some(v) if v == token => Ok(()),
+            _ => Err(anyhow!("invalid token.")),
+        }
+    }
+}
@@ -1,7 +1,8 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::Cursor;
 use std::str::from_utf8;
+use super::common::LengthPrefix;
 
 pub fn binary_read_i64(cursor: &mut Cursor<&[u8]>) -> Result<i64> {
     let v = cursor.read_i64::<LittleEndian>()?;
@@ -30,8 +31,13 @@ pub fn binary_read_f32(cursor: &mut Cursor<&[u8]>) -> Result<f32> {
 
 pub fn binary_read_string(cursor: &mut Cursor<&[u8]>, bytes: &[u8]) -> Result<String> {
     let length = binary_read_i16(cursor)?;
+    if length < 0 {
+        return Err(anyhow!("invalid string length:{}", length));
+    }
     let length = cursor.position() + length as u64;
-    let data = &bytes[cursor.position() as usize..length as usize];
+    let data = bytes
+        .get(cursor.position() as usize..length as usize)
+        .ok_or_else(|| anyhow!("string length out of range."))?;
     cursor.set_position(length);
 
     let v = from_utf8(data)?.to_string();
@@ -69,9 +75,7 @@ pub fn binary_write_i8(encoded: &mut Vec<u8>, v: i8) -> Result<()> {
     let v = encoded.write_i8(v)?;
     Ok(v)
 }
+//errors instead of wrapping when the string does not fit the i16 length.
 pub fn binary_write_string(encoded: &mut Vec<u8>, v: &str) -> Result<()> {
-    let bytes = v.as_bytes();
-    binary_write_i16(encoded,bytes.len() as i16)?;
-    encoded.extend_from_slice(bytes);
-    Ok(())
+    LengthPrefix::I16.write_string(encoded, v)
 }
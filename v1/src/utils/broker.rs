@@ -0,0 +1,144 @@
+use super::redis_db::{
+    publish_chat_message_redis, subscribe_chat_message_redis, ChatPublishMessage, RedisPool,
+    ONLINE_USERS_SETS_REDIS_KEY, ONLINE_USER_CONNECTIONS_REDIS_KEY_PREFIX, USER_OFFLINE_CHANNEL_REDIS_KEY,
+};
+use anyhow::Result;
+use redis::Script;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+
+//online presence and the fan out of committed messages to every chat node.
+//a user counts as online while any node holds one of its connections.
+pub trait Broker: Send + Sync {
+    //a connection of the user logged in on some node.
+    fn user_online(&self, uid: u64) -> Result<()>;
+
+    //a connection of the user closed, once the cluster wide count reaches 0 the user is dropped
+    //from the online set and the offline is announced. returns whether the user went offline.
+    fn user_offline(&self, uid: u64) -> Result<bool>;
+
+    fn publish(&self, msg: &ChatPublishMessage) -> Result<()>;
+
+    //deliver every published message to sender, may block until the receiver side is gone.
+    fn subscribe(&self, sender: &UnboundedSender<ChatPublishMessage>) -> Result<()>;
+}
+
+//the counter and the online set change in one script so a login racing the last close is not lost.
+const USER_ONLINE_SCRIPT: &str = r"
+redis.call('INCR', KEYS[2])
+redis.call('SADD', KEYS[1], ARGV[1])
+return 1
+";
+
+const USER_OFFLINE_SCRIPT: &str = r"
+local n = redis.call('DECR', KEYS[2])
+if n > 0 then
+    return 0
+end
+redis.call('DEL', KEYS[2])
+redis.call('SREM', KEYS[1], ARGV[1])
+redis.call('PUBLISH', KEYS[3], ARGV[1])
+return 1
+";
+
+//shared by every node through the redis online set, connection counters and publish channel.
+pub struct RedisBroker {
+    redis: Arc<RedisPool>,
+}
+
+impl RedisBroker {
+    pub fn new(redis: Arc<RedisPool>) -> Self {
+        RedisBroker { redis }
+    }
+}
+
+impl Broker for RedisBroker {
+    fn user_online(&self, uid: u64) -> Result<()> {
+        let mut redis_conn = self.redis.get()?;
+        let _: i64 = Script::new(USER_ONLINE_SCRIPT)
+            .key(ONLINE_USERS_SETS_REDIS_KEY)
+            .key(format!("{}{}", ONLINE_USER_CONNECTIONS_REDIS_KEY_PREFIX, uid))
+            .arg(uid)
+            .invoke(&mut *redis_conn)?;
+
+        Ok(())
+    }
+
+    fn user_offline(&self, uid: u64) -> Result<bool> {
+        let mut redis_conn = self.redis.get()?;
+        let offline: i64 = Script::new(USER_OFFLINE_SCRIPT)
+            .key(ONLINE_USERS_SETS_REDIS_KEY)
+            .key(format!("{}{}", ONLINE_USER_CONNECTIONS_REDIS_KEY_PREFIX, uid))
+            .key(USER_OFFLINE_CHANNEL_REDIS_KEY)
+            .arg(uid)
+            .invoke(&mut *redis_conn)?;
+
+        Ok(offline == 1)
+    }
+
+    fn publish(&self, msg: &ChatPublishMessage) -> Result<()> {
+        let mut redis_conn = self.redis.get()?;
+        Ok(publish_chat_message_redis(&mut redis_conn, msg)?)
+    }
+
+    //the subscriber holds its connection for good, so it opens its own instead of draining the pool.
+    fn subscribe(&self, sender: &UnboundedSender<ChatPublishMessage>) -> Result<()> {
+        Ok(subscribe_chat_message_redis(sender)?)
+    }
+}
+
+//single process broker for tests and local development, nothing leaves the process.
+#[derive(Default)]
+pub struct LocalBroker {
+    //open connections per uid over every node sharing this broker, a uid leaves at 0.
+    online: Mutex<HashMap<u64, u64>>,
+    subscribers: Mutex<Vec<UnboundedSender<ChatPublishMessage>>>,
+}
+
+impl LocalBroker {
+    pub fn new() -> Self {
+        LocalBroker::default()
+    }
+
+    pub fn is_online(&self, uid: u64) -> bool {
+        self.online.lock().unwrap().contains_key(&uid)
+    }
+}
+
+impl Broker for LocalBroker {
+    fn user_online(&self, uid: u64) -> Result<()> {
+        *self.online.lock().unwrap().entry(uid).or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn user_offline(&self, uid: u64) -> Result<bool> {
+        let mut online = self.online.lock().unwrap();
+        let n = match online.get_mut(&uid) {
+            Some(n) => n,
+            None => return Ok(false),
+        };
+
+        *n = n.saturating_sub(1);
+        if *n > 0 {
+            return Ok(false);
+        }
+
+        online.remove(&uid);
+        Ok(true)
+    }
+
+    fn publish(&self, msg: &ChatPublishMessage) -> Result<()> {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(msg.clone()).is_ok());
+
+        Ok(())
+    }
+
+    fn subscribe(&self, sender: &UnboundedSender<ChatPublishMessage>) -> Result<()> {
+        self.subscribers.lock().unwrap().push(sender.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use super::signature::SIGNATURE_LENGTH;
+use bytes::{BufMut, BytesMut};
+use byteorder::{ByteOrder, LittleEndian};
+use flate2::read::ZlibDecoder;
+use std::fmt;
+use std::io::{self, Read};
+use tokio_util::codec::{Decoder, Encoder};
+
+//code(2) + version(1) + key_id(2) + session_id(8) + signature(32) + timestamp(8) + body length(4)
+pub const FRAME_HEADER_LENGTH: usize = 57;
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 1024 * 1024;
+//code(2) + session_id(8) + state(2) + body length(4) + zlib length(4)
+pub const RESPONSE_HEADER_LENGTH: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct RequestFrame {
+    pub code: u16,
+    pub version: u8,
+    pub key_id: u16,
+    pub session_id: u64,
+    pub signature: [u8; SIGNATURE_LENGTH],
+    pub timestamp: u64,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    TooLarge {
+        code: u16,
+        version: u8,
+        session_id: u64,
+        length: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "frame io error:{}", e),
+            FrameError::TooLarge { length, max, .. } => {
+                write!(f, "frame body length {} exceeds max {}", length, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+//splits the tcp byte stream into request frames, buffering partial reads.
+#[derive(Debug, Clone)]
+pub struct MessageCodec {
+    max_frame_length: usize,
+}
+
+impl MessageCodec {
+    pub fn new(max_frame_length: usize) -> Self {
+        MessageCodec { max_frame_length }
+    }
+
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        MessageCodec::new(DEFAULT_MAX_FRAME_LENGTH)
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = RequestFrame;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<RequestFrame>, FrameError> {
+        if src.len() < FRAME_HEADER_LENGTH {
+            src.reserve(FRAME_HEADER_LENGTH - src.len());
+            return Ok(None);
+        }
+
+        let body_length = LittleEndian::read_u32(&src[53..57]) as usize;
+
+        if body_length > self.max_frame_length {
+            return Err(FrameError::TooLarge {
+                code: LittleEndian::read_u16(&src[0..2]),
+                version: src[2],
+                session_id: LittleEndian::read_u64(&src[5..13]),
+                length: body_length,
+                max: self.max_frame_length,
+            });
+        }
+
+        let frame_length = FRAME_HEADER_LENGTH + body_length;
+
+        if src.len() < frame_length {
+            src.reserve(frame_length - src.len());
+            return Ok(None);
+        }
+
+        let header = src.split_to(FRAME_HEADER_LENGTH);
+        let body = src.split_to(body_length);
+
+        let mut signature = [0; SIGNATURE_LENGTH];
+        signature.copy_from_slice(&header[13..45]);
+
+        let frame = RequestFrame {
+            code: LittleEndian::read_u16(&header[0..2]),
+            version: header[2],
+            key_id: LittleEndian::read_u16(&header[3..5]),
+            session_id: LittleEndian::read_u64(&header[5..13]),
+            signature,
+            timestamp: LittleEndian::read_u64(&header[45..53]),
+            body: body.to_vec(),
+        };
+
+        Ok(Some(frame))
+    }
+}
+
+impl Encoder<RequestFrame> for MessageCodec {
+    type Error = FrameError;
+
+    fn encode(&mut self, item: RequestFrame, dst: &mut BytesMut) -> Result<(), FrameError> {
+        if item.body.len() > self.max_frame_length {
+            return Err(FrameError::TooLarge {
+                code: item.code,
+                version: item.version,
+                session_id: item.session_id,
+                length: item.body.len(),
+                max: self.max_frame_length,
+            });
+        }
+
+        dst.reserve(FRAME_HEADER_LENGTH + item.body.len());
+        dst.put_u16_le(item.code);
+        dst.put_u8(item.version);
+        dst.put_u16_le(item.key_id);
+        dst.put_u64_le(item.session_id);
+        dst.put_slice(&item.signature);
+        dst.put_u64_le(item.timestamp);
+        dst.put_u32_le(item.body.len() as u32);
+        dst.extend_from_slice(&item.body);
+
+        Ok(())
+    }
+}
+
+//a server response or push, body already inflated: msg string then the encoded data.
+#[derive(Debug, Clone)]
+pub struct ResponseFrame {
+    pub code: u16,
+    pub session_id: u64,
+    pub state: u16,
+    pub body: Vec<u8>,
+}
+
+//client side of the protocol, splits the byte stream into response frames.
+#[derive(Debug, Clone)]
+pub struct ResponseCodec {
+    max_frame_length: usize,
+}
+
+impl ResponseCodec {
+    pub fn new(max_frame_length: usize) -> Self {
+        ResponseCodec { max_frame_length }
+    }
+}
+
+impl Default for ResponseCodec {
+    fn default() -> Self {
+        ResponseCodec::new(DEFAULT_MAX_FRAME_LENGTH)
+    }
+}
+
+impl Decoder for ResponseCodec {
+    type Item = ResponseFrame;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<ResponseFrame>, FrameError> {
+        if src.len() < RESPONSE_HEADER_LENGTH {
+            src.reserve(RESPONSE_HEADER_LENGTH - src.len());
+            return Ok(None);
+        }
+
+        let body_length = LittleEndian::read_u32(&src[12..16]) as usize;
+        let zlib_length = LittleEndian::read_u32(&src[16..20]) as usize;
+
+        if body_length > self.max_frame_length || zlib_length > self.max_frame_length {
+            return Err(FrameError::TooLarge {
+                code: LittleEndian::read_u16(&src[0..2]),
+                version: 0,
+                session_id: LittleEndian::read_u64(&src[2..10]),
+                length: body_length.max(zlib_length),
+                max: self.max_frame_length,
+            });
+        }
+
+        let frame_length = RESPONSE_HEADER_LENGTH + zlib_length;
+
+        if src.len() < frame_length {
+            src.reserve(frame_length - src.len());
+            return Ok(None);
+        }
+
+        let header = src.split_to(RESPONSE_HEADER_LENGTH);
+        let zlib_body = src.split_to(zlib_length);
+
+        //inflate at most one byte past the announced length, a zlib bomb fails the length check below.
+        let mut body = Vec::with_capacity(body_length);
+        if body_length > 0 {
+            ZlibDecoder::new(&zlib_body[..])
+                .take(body_length as u64 + 1)
+                .read_to_end(&mut body)?;
+        }
+
+        if body.len() != body_length {
+            return Err(FrameError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "response body length mismatch.",
+            )));
+        }
+
+        Ok(Some(ResponseFrame {
+            code: LittleEndian::read_u16(&header[0..2]),
+            session_id: LittleEndian::read_u64(&header[2..10]),
+            state: LittleEndian::read_u16(&header[10..12]),
+            body,
+        }))
+    }
+}
@@ -1,23 +1,122 @@
-use anyhow::Result;
-use byteorder::{LittleEndian, WriteBytesExt};
+use anyhow::{anyhow, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
 use std::io::Cursor;
 use crate::utils::binary_helper::*;
 
+//requests at this Message.version or above use u32 lengths for strings, lists and items.
+pub const LARGE_PAYLOAD_VERSION: u8 = 2;
+
+//history routes at this Message.version take a cursor and mode and answer with a page.
+pub const CURSOR_PAGE_VERSION: u8 = 3;
+
+//width of every string, list and item length in a body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthPrefix {
+    I16,
+    U32,
+}
+
+//a length the i16 format cannot carry, never truncated.
+#[derive(Debug)]
+pub struct LengthOverflow {
+    pub length: usize,
+}
+
+impl fmt::Display for LengthOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "payload length {} exceeds {}, use protocol version {}.",
+            self.length,
+            i16::MAX,
+            LARGE_PAYLOAD_VERSION
+        )
+    }
+}
+
+impl std::error::Error for LengthOverflow {}
+
+impl LengthPrefix {
+    pub fn for_version(version: u8) -> Self {
+        if version >= LARGE_PAYLOAD_VERSION {
+            LengthPrefix::U32
+        } else {
+            LengthPrefix::I16
+        }
+    }
+
+    pub fn width(self) -> usize {
+        match self {
+            LengthPrefix::I16 => 2,
+            LengthPrefix::U32 => 4,
+        }
+    }
+
+    pub fn write(self, encoded: &mut Vec<u8>, length: usize) -> Result<()> {
+        match self {
+            LengthPrefix::I16 => {
+                if length > i16::MAX as usize {
+                    return Err(LengthOverflow { length }.into());
+                }
+                encoded.write_i16::<LittleEndian>(length as i16)?;
+            }
+            LengthPrefix::U32 => {
+                if length > u32::MAX as usize {
+                    return Err(anyhow!("payload length {} exceeds {}.", length, u32::MAX));
+                }
+                encoded.write_u32::<LittleEndian>(length as u32)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read(self, cursor: &mut Cursor<&[u8]>) -> Result<usize> {
+        match self {
+            LengthPrefix::I16 => {
+                let length = binary_read_i16(cursor)?;
+                if length < 0 {
+                    return Err(anyhow!("invalid length:{}", length));
+                }
+                Ok(length as usize)
+            }
+            LengthPrefix::U32 => Ok(cursor.read_u32::<LittleEndian>()? as usize),
+        }
+    }
+
+    pub fn write_string(self, encoded: &mut Vec<u8>, v: &str) -> Result<()> {
+        self.write(encoded, v.len())?;
+        encoded.extend_from_slice(v.as_bytes());
+        Ok(())
+    }
+
+    pub fn read_string(self, cursor: &mut Cursor<&[u8]>, bytes: &[u8]) -> Result<String> {
+        let length = self.read(cursor)?;
+        let start = cursor.position() as usize;
+        let data = bytes
+            .get(start..start + length)
+            .ok_or_else(|| anyhow!("string length out of range."))?;
+        cursor.set_position((start + length) as u64);
+
+        Ok(std::str::from_utf8(data)?.to_string())
+    }
+}
+
+//encode() keeps the i16 format old clients understand.
 pub trait BinaryEncode {
-    fn encode(&self) -> Result<Vec<u8>>;
+    fn encode_with(&self, prefix: LengthPrefix) -> Result<Vec<u8>>;
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        self.encode_with(LengthPrefix::I16)
+    }
 }
 
 //empty str->"".
 impl BinaryEncode for &str {
-    fn encode(&self) -> Result<Vec<u8>> {
+    fn encode_with(&self, prefix: LengthPrefix) -> Result<Vec<u8>> {
         let mut encoded = Vec::new();
-
-        let length = self.len();
-        encoded.write_i16::<LittleEndian>(length as i16)?;
-
-        if length > 0 {
-            encoded.extend_from_slice(&*self.as_bytes());
-        }
+        prefix.write_string(&mut encoded, self)?;
 
         Ok(encoded)
     }
@@ -25,29 +124,36 @@ impl BinaryEncode for &str {
 
 
 pub trait BinaryDecode<'a> {
-    fn decode(cursor: &mut Cursor<&'a [u8]>, bytes: &'a [u8]) -> Result<Self>
+    fn decode_with(cursor: &mut Cursor<&'a [u8]>, bytes: &'a [u8], prefix: LengthPrefix) -> Result<Self>
         where
             Self: std::marker::Sized;
+
+    fn decode(cursor: &mut Cursor<&'a [u8]>, bytes: &'a [u8]) -> Result<Self>
+        where
+            Self: std::marker::Sized,
+    {
+        Self::decode_with(cursor, bytes, LengthPrefix::I16)
+    }
 }
 
 impl<'a, T: BinaryDecode<'a>> BinaryDecode<'a> for Vec<T> {
-    fn decode(cursor: &mut Cursor<&'a [u8]>, bytes: &'a [u8]) -> Result<Self> {
-        let array_length = binary_read_i16(cursor)?;
+    fn decode_with(cursor: &mut Cursor<&'a [u8]>, bytes: &'a [u8], prefix: LengthPrefix) -> Result<Self> {
+        let array_length = prefix.read(cursor)?;
 
         let mut datas = Vec::new();
 
         if array_length > 0 {
             let mut len = 0;
             loop {
-                len += 2;
+                len += prefix.width();
 
                 if len >= array_length {
                     break;
                 }
 
-                let item_length = binary_read_i16(cursor)?;
+                let item_length = prefix.read(cursor)?;
 
-                let data = T::decode(cursor, &bytes)?;
+                let data = T::decode_with(cursor, bytes, prefix)?;
 
                 datas.push(data);
 
@@ -67,12 +173,12 @@ pub fn deserialize_binary<'a, T: BinaryDecode<'a> + BinaryEncode>(
 }
 
 impl BinaryEncode for Vec<u8> {
-    fn encode(&self) -> Result<Vec<u8>> {
+    fn encode_with(&self, prefix: LengthPrefix) -> Result<Vec<u8>> {
         let encoded_length = self.len();
 
         let mut data = Vec::with_capacity(encoded_length + 4);
 
-        data.write_i16::<LittleEndian>(encoded_length as i16)?;
+        prefix.write(&mut data, encoded_length)?;
 
         data.extend(self);
 
@@ -81,29 +187,186 @@ impl BinaryEncode for Vec<u8> {
 }
 
 impl<T: BinaryEncode> BinaryEncode for Vec<T> {
-    fn encode(&self) -> Result<Vec<u8>> {
+    fn encode_with(&self, prefix: LengthPrefix) -> Result<Vec<u8>> {
         let mut encoded = Vec::new();
 
-        self.into_iter()
-            .map(|e| {
-                if let Ok(v) = e.encode() {
-                    if !v.is_empty() {
-                        encoded.extend_from_slice(&v)
-                    }
-                }
-            })
-            .count();
+        for e in self.iter() {
+            encoded.extend(e.encode_with(prefix)?);
+        }
 
         let mut res = Vec::with_capacity(encoded.len() + 4);
 
-        res.write_i16::<LittleEndian>(encoded.len() as i16)?;
+        prefix.write(&mut res, encoded.len())?;
 
-        let encoded_length = encoded.len() as i32;
+        res.extend_from_slice(&encoded);
 
-        if encoded_length > 0 {
-            res.extend_from_slice(&encoded);
+        Ok(res)
+    }
+}
+
+//fixed width and string fields, written inline without an item length.
+pub trait BinaryScalar: Sized {
+    fn write_scalar(&self, encoded: &mut Vec<u8>, prefix: LengthPrefix) -> Result<()>;
+    fn read_scalar(cursor: &mut Cursor<&[u8]>, bytes: &[u8], prefix: LengthPrefix) -> Result<Self>;
+}
+
+macro_rules! impl_binary_scalar {
+    ($ty:ty, $write:ident, $read:ident) => {
+        impl BinaryScalar for $ty {
+            fn write_scalar(&self, encoded: &mut Vec<u8>, _prefix: LengthPrefix) -> Result<()> {
+                encoded.$write::<LittleEndian>(*self)?;
+                Ok(())
+            }
+
+            fn read_scalar(cursor: &mut Cursor<&[u8]>, _bytes: &[u8], _prefix: LengthPrefix) -> Result<Self> {
+                Ok(cursor.$read::<LittleEndian>()?)
+            }
         }
+    };
+}
 
-        Ok(res)
+impl_binary_scalar!(i16, write_i16, read_i16);
+impl_binary_scalar!(i32, write_i32, read_i32);
+impl_binary_scalar!(i64, write_i64, read_i64);
+impl_binary_scalar!(u16, write_u16, read_u16);
+impl_binary_scalar!(u32, write_u32, read_u32);
+impl_binary_scalar!(u64, write_u64, read_u64);
+impl_binary_scalar!(f32, write_f32, read_f32);
+impl_binary_scalar!(f64, write_f64, read_f64);
+
+impl BinaryScalar for i8 {
+    fn write_scalar(&self, encoded: &mut Vec<u8>, _prefix: LengthPrefix) -> Result<()> {
+        binary_write_i8(encoded, *self)
+    }
+
+    fn read_scalar(cursor: &mut Cursor<&[u8]>, _bytes: &[u8], _prefix: LengthPrefix) -> Result<Self> {
+        binary_read_i8(cursor)
+    }
+}
+
+impl BinaryScalar for u8 {
+    fn write_scalar(&self, encoded: &mut Vec<u8>, _prefix: LengthPrefix) -> Result<()> {
+        encoded.write_u8(*self)?;
+        Ok(())
+    }
+
+    fn read_scalar(cursor: &mut Cursor<&[u8]>, _bytes: &[u8], _prefix: LengthPrefix) -> Result<Self> {
+        Ok(cursor.read_u8()?)
+    }
+}
+
+//0 or 1.
+impl BinaryScalar for bool {
+    fn write_scalar(&self, encoded: &mut Vec<u8>, _prefix: LengthPrefix) -> Result<()> {
+        encoded.write_u8(*self as u8)?;
+        Ok(())
     }
+
+    fn read_scalar(cursor: &mut Cursor<&[u8]>, _bytes: &[u8], _prefix: LengthPrefix) -> Result<Self> {
+        match cursor.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(anyhow!("invalid bool:{}", v)),
+        }
+    }
+}
+
+impl BinaryScalar for String {
+    fn write_scalar(&self, encoded: &mut Vec<u8>, prefix: LengthPrefix) -> Result<()> {
+        prefix.write_string(encoded, self.as_str())
+    }
+
+    fn read_scalar(cursor: &mut Cursor<&[u8]>, bytes: &[u8], prefix: LengthPrefix) -> Result<Self> {
+        prefix.read_string(cursor, bytes)
+    }
+}
+
+//nested struct field: skip its item length, then decode the fields.
+pub fn decode_item<'a, T: BinaryDecode<'a>>(
+    cursor: &mut Cursor<&'a [u8]>,
+    bytes: &'a [u8],
+    prefix: LengthPrefix,
+) -> Result<T> {
+    let _item_length = prefix.read(cursor)?;
+    T::decode_with(cursor, bytes, prefix)
+}
+
+//None->empty item.
+pub fn encode_option_item<T: BinaryEncode>(v: &Option<T>, prefix: LengthPrefix) -> Result<Vec<u8>> {
+    match v {
+        Some(v) => v.encode_with(prefix),
+        None => Vec::<u8>::new().encode_with(prefix),
+    }
+}
+
+pub fn decode_option_item<'a, T: BinaryDecode<'a>>(
+    cursor: &mut Cursor<&'a [u8]>,
+    bytes: &'a [u8],
+    prefix: LengthPrefix,
+) -> Result<Option<T>> {
+    let item_length = prefix.read(cursor)?;
+
+    if item_length > 0 {
+        Ok(Some(T::decode_with(cursor, bytes, prefix)?))
+    } else {
+        Ok(None)
+    }
+}
+
+//Option of a scalar is an item like an Option of a struct, None->empty item.
+pub fn encode_option_scalar<T: BinaryScalar>(v: &Option<T>, prefix: LengthPrefix) -> Result<Vec<u8>> {
+    let mut item = Vec::new();
+    if let Some(v) = v {
+        v.write_scalar(&mut item, prefix)?;
+    }
+
+    item.encode_with(prefix)
+}
+
+pub fn decode_option_scalar<T: BinaryScalar>(
+    cursor: &mut Cursor<&[u8]>,
+    bytes: &[u8],
+    prefix: LengthPrefix,
+) -> Result<Option<T>> {
+    let item_length = prefix.read(cursor)?;
+    if item_length == 0 {
+        return Ok(None);
+    }
+
+    let start = cursor.position() as usize;
+    let v = T::read_scalar(cursor, bytes, prefix)?;
+    if cursor.position() as usize != start + item_length {
+        return Err(anyhow!("invalid item length:{}", item_length));
+    }
+
+    Ok(Some(v))
+}
+
+//Vec of a scalar: the list length, then every value inline without an item length.
+pub fn encode_scalar_list<T: BinaryScalar>(v: &[T], prefix: LengthPrefix) -> Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    for e in v.iter() {
+        e.write_scalar(&mut encoded, prefix)?;
+    }
+
+    encoded.encode_with(prefix)
+}
+
+pub fn decode_scalar_list<T: BinaryScalar>(
+    cursor: &mut Cursor<&[u8]>,
+    bytes: &[u8],
+    prefix: LengthPrefix,
+) -> Result<Vec<T>> {
+    let array_length = prefix.read(cursor)?;
+    let end = cursor.position() as usize + array_length;
+
+    let mut datas = Vec::new();
+    while (cursor.position() as usize) < end {
+        datas.push(T::read_scalar(cursor, bytes, prefix)?);
+    }
+    if cursor.position() as usize != end {
+        return Err(anyhow!("invalid list length:{}", array_length));
+    }
+
+    Ok(datas)
 }
@@ -1,41 +1,109 @@
-use super::db::{DbConnPool, DieselPool};
+use super::auth::TokenVerifier;
+use super::broker::Broker;
+use super::dedup::SendDedup;
 use super::message::{Message, MessageStateCode};
-use crate::BinaryEncode;
-use anyhow::Result;
+use super::session::Session;
+use super::common::{LengthOverflow, LengthPrefix};
+use super::store::ChatStore;
+use super::thread_pool::ThreadPool;
+use crate::{BinaryEncode, PendingPushes, SocketSender};
+use anyhow::{anyhow, Result};
 use byteorder::{LittleEndian, WriteBytesExt};
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use std::io::Write;
 use std::sync::Arc;
-use tokio::net::tcp::OwnedWriteHalf;
-use tokio::sync::Mutex;
 
 pub struct Connection {
-    pub socket: Arc<Mutex<OwnedWriteHalf>>,
-    pub master_db: Arc<DieselPool>,
-    pub slave_db: Arc<DieselPool>,
+    pub socket: SocketSender,
+    pub store: Arc<dyn ChatStore>,
+    pub pool: Arc<ThreadPool>,
+    pub pending_pushes: PendingPushes,
+    pub session: Arc<Session>,
+    pub verifier: Arc<dyn TokenVerifier>,
+    pub broker: Arc<dyn Broker>,
+    pub send_dedup: Arc<dyn SendDedup>,
     pub msg: Message,
+    //uid the request body claims, set by RouterRegister::call before the middlewares run.
+    pub claimed_uid: Option<u64>,
 }
 
 impl Connection {
+    //lengths follow the request's Message.version, a body too large for it is answered with PayloadTooLarge.
     pub fn get_bin_code<T>(&self, state: MessageStateCode, msg: &str, body: T) -> Result<Vec<u8>>
         where
             T: BinaryEncode + std::fmt::Debug,
     {
-        ResponseContext::get_bincode(self.msg.code as u16, self.msg.session_id, state, msg, body)
+        let code = self.msg.code as u16;
+        let prefix = self.length_prefix();
+
+        match ResponseContext::get_bincode_with(code, self.msg.session_id, state, msg, body, prefix) {
+            Err(e) if e.downcast_ref::<LengthOverflow>().is_some() => ResponseContext::get_bincode_with(
+                code,
+                self.msg.session_id,
+                MessageStateCode::PayloadTooLarge,
+                e.to_string().as_str(),
+                "",
+                prefix,
+            ),
+            resp => resp,
+        }
+    }
+
+    pub fn length_prefix(&self) -> LengthPrefix {
+        LengthPrefix::for_version(self.msg.version)
     }
 
     pub fn get_general_error(&self, msg: &str) -> Result<Vec<u8>> {
         self.get_bin_code(MessageStateCode::GeneralError, msg, "")
     }
 
-    pub fn db_conn(&self, master: bool) -> DbConnPool {
-        if master {
-            self.master_db.get().unwrap()
-        } else {
-            self.slave_db.get().unwrap()
+    //requests must claim the uid bound to the session at connection_state.
+    pub fn authorized_uid(&self, claimed_uid: u64) -> Result<u64> {
+        match self.session.uid() {
+            Some(uid) if uid == claimed_uid => Ok(uid),
+            Some(_) => Err(anyhow!("identity mismatch.")),
+            None => Err(anyhow!("unauthenticated.")),
         }
     }
+
+    //storage, broker and token checks block, so they run on the bounded pool instead of the reactor.
+    pub async fn with_store<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&dyn ChatStore) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let store = self.store.clone();
+        self.pool.run(move || f(store.as_ref())).await?
+    }
+
+    pub async fn with_broker<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&dyn Broker) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let broker = self.broker.clone();
+        self.pool.run(move || f(broker.as_ref())).await?
+    }
+
+    pub async fn with_send_dedup<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&dyn SendDedup) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let send_dedup = self.send_dedup.clone();
+        self.pool.run(move || f(send_dedup.as_ref())).await?
+    }
+
+    pub async fn verify_token(&self, uid: u64, token: String) -> Result<()> {
+        let verifier = self.verifier.clone();
+        self.pool.run(move || verifier.verify(uid, &token)).await?
+    }
+}
+
+//state of an encoded response, see ResponseContext::get_bincode_with.
+pub fn response_state(resp: &[u8]) -> Option<u16> {
+    resp.get(10..12).map(|v| u16::from_le_bytes([v[0], v[1]]))
 }
 
 #[derive(Debug)]
@@ -51,16 +119,25 @@ impl<'a, T: BinaryEncode + std::fmt::Debug> ResponseContext<'a, T> {
         state: MessageStateCode,
         msg: &str,
         body: T,
+    ) -> Result<Vec<u8>> {
+        Self::get_bincode_with(code, session_id, state, msg, body, LengthPrefix::I16)
+    }
+
+    pub fn get_bincode_with(
+        code: u16,
+        session_id: u64,
+        state: MessageStateCode,
+        msg: &str,
+        body: T,
+        prefix: LengthPrefix,
     ) -> Result<Vec<u8>> {
         let mut gz = ZlibEncoder::new(Vec::new(), Compression::default());
         let mut resp = vec![];
 
         let mut res_body = Vec::new();
-        let msg_bytes = msg.as_bytes();
-        res_body.write_i16::<LittleEndian>(msg_bytes.len() as i16)?;
-        res_body.extend_from_slice(msg_bytes);
+        prefix.write_string(&mut res_body, msg)?;
 
-        res_body.extend(body.encode()?);
+        res_body.extend(body.encode_with(prefix)?);
 
         resp.write_u16::<LittleEndian>(code)?;
         resp.write_u64::<LittleEndian>(session_id)?;
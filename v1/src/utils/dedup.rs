@@ -0,0 +1,176 @@
+use super::redis_db::RedisPool;
+use crate::models::chat_messages::FrontDisplayP2pChatMessageCount;
+use anyhow::Result;
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_SEND_DEDUP_WINDOW_SECS: u64 = 600;
+pub const DEFAULT_SEND_DEDUP_CAPACITY: usize = 100_000;
+pub const SEND_DEDUP_REDIS_KEY_PREFIX: &str = "chat_send_dedup_"; //format->(uid:client_msg_id),value->empty while in flight then the stored message json
+
+type DedupKey = (u64, String);
+
+#[derive(Debug, Clone)]
+pub enum SendState {
+    //first time seen, the caller stores the message then calls finish or abort.
+    New,
+    //the first send is still being stored.
+    InFlight,
+    //already stored, answer with the original result.
+    Done(FrontDisplayP2pChatMessageCount),
+}
+
+//remembers client message ids per sender inside a window, a retried send gets the stored message back.
+//begin marks the id in flight, finish stores the result and abort forgets the id so a retry may store it.
+pub trait SendDedup: Send + Sync {
+    fn begin(&self, uid: u64, client_msg_id: &str) -> Result<SendState>;
+
+    fn finish(&self, uid: u64, client_msg_id: &str, stored: FrontDisplayP2pChatMessageCount) -> Result<()>;
+
+    fn abort(&self, uid: u64, client_msg_id: &str) -> Result<()>;
+}
+
+#[derive(Default)]
+struct SentMessages {
+    //first seen timestamp and the stored result once there is one.
+    entries: HashMap<DedupKey, (u64, Option<FrontDisplayP2pChatMessageCount>)>,
+    order: VecDeque<(u64, DedupKey)>,
+}
+
+//one process only, for tests and single node setups.
+pub struct MemorySendDedup {
+    window_secs: u64,
+    capacity: usize,
+    sent: Mutex<SentMessages>,
+}
+
+impl MemorySendDedup {
+    pub fn new(window_secs: u64, capacity: usize) -> Self {
+        MemorySendDedup {
+            window_secs,
+            capacity,
+            sent: Mutex::new(SentMessages::default()),
+        }
+    }
+
+    pub fn begin_at(&self, now: u64, uid: u64, client_msg_id: &str) -> SendState {
+        let key = (uid, client_msg_id.to_string());
+        let mut sent = self.sent.lock().unwrap();
+
+        let oldest = now.saturating_sub(self.window_secs);
+        while let Some(front) = sent.order.front() {
+            if front.0 >= oldest && sent.order.len() < self.capacity {
+                break;
+            }
+            let (seen_at, front) = sent.order.pop_front().unwrap();
+            //an aborted id sent again has a newer entry, keep it.
+            if sent.entries.get(&front).map(|v| v.0) == Some(seen_at) {
+                sent.entries.remove(&front);
+            }
+        }
+
+        if let Some((_, stored)) = sent.entries.get(&key) {
+            return match stored {
+                Some(v) => SendState::Done(v.clone()),
+                None => SendState::InFlight,
+            };
+        }
+
+        sent.entries.insert(key.clone(), (now, None));
+        sent.order.push_back((now, key));
+
+        SendState::New
+    }
+}
+
+impl SendDedup for MemorySendDedup {
+    fn begin(&self, uid: u64, client_msg_id: &str) -> Result<SendState> {
+        Ok(self.begin_at(Utc::now().timestamp() as u64, uid, client_msg_id))
+    }
+
+    fn finish(&self, uid: u64, client_msg_id: &str, stored: FrontDisplayP2pChatMessageCount) -> Result<()> {
+        let key = (uid, client_msg_id.to_string());
+
+        if let Some(entry) = self.sent.lock().unwrap().entries.get_mut(&key) {
+            entry.1 = Some(stored);
+        }
+        Ok(())
+    }
+
+    fn abort(&self, uid: u64, client_msg_id: &str) -> Result<()> {
+        let key = (uid, client_msg_id.to_string());
+        self.sent.lock().unwrap().entries.remove(&key);
+        Ok(())
+    }
+}
+
+//shared by every node through redis, a retry balanced to another node still finds the first send.
+//redis expires an id window_secs after its first send or its stored result.
+pub struct RedisSendDedup {
+    redis: Arc<RedisPool>,
+    window_secs: u64,
+}
+
+impl RedisSendDedup {
+    pub fn new(redis: Arc<RedisPool>, window_secs: u64) -> Self {
+        RedisSendDedup { redis, window_secs }
+    }
+
+    fn key(uid: u64, client_msg_id: &str) -> String {
+        format!("{}{}:{}", SEND_DEDUP_REDIS_KEY_PREFIX, uid, client_msg_id)
+    }
+}
+
+impl SendDedup for RedisSendDedup {
+    fn begin(&self, uid: u64, client_msg_id: &str) -> Result<SendState> {
+        let mut redis_conn = self.redis.get()?;
+        let key = RedisSendDedup::key(uid, client_msg_id);
+
+        //an id aborted between the SET and the GET is free again, try once more.
+        for _ in 0..2 {
+            let created: Option<String> = redis::cmd("SET")
+                .arg(&key)
+                .arg("")
+                .arg("NX")
+                .arg("EX")
+                .arg(self.window_secs)
+                .query(&mut *redis_conn)?;
+            if created.is_some() {
+                return Ok(SendState::New);
+            }
+
+            let stored: Option<String> = redis::cmd("GET").arg(&key).query(&mut *redis_conn)?;
+            match stored {
+                Some(v) if v.is_empty() => return Ok(SendState::InFlight),
+                Some(v) => return Ok(SendState::Done(serde_json::from_str(&v)?)),
+                None => continue,
+            }
+        }
+
+        Ok(SendState::InFlight)
+    }
+
+    //XX keeps an id that expired or was aborted meanwhile from coming back.
+    fn finish(&self, uid: u64, client_msg_id: &str, stored: FrontDisplayP2pChatMessageCount) -> Result<()> {
+        let mut redis_conn = self.redis.get()?;
+        let _: Option<String> = redis::cmd("SET")
+            .arg(RedisSendDedup::key(uid, client_msg_id))
+            .arg(serde_json::to_string(&stored)?)
+            .arg("XX")
+            .arg("EX")
+            .arg(self.window_secs)
+            .query(&mut *redis_conn)?;
+
+        Ok(())
+    }
+
+    fn abort(&self, uid: u64, client_msg_id: &str) -> Result<()> {
+        let mut redis_conn = self.redis.get()?;
+        let _: i64 = redis::cmd("DEL")
+            .arg(RedisSendDedup::key(uid, client_msg_id))
+            .query(&mut *redis_conn)?;
+
+        Ok(())
+    }
+}
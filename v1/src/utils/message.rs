@@ -19,5 +19,8 @@ pub enum MessageStateCode {
     Ok = 200,
     NotFound = 403,
     NoContent = 204,
+    RequestExpired = 408,
+    RequestReplayed = 409,
+    PayloadTooLarge = 413,
     GeneralError = 503,
 }
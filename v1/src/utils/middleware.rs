@@ -0,0 +1,258 @@
+use super::connection::response_state;
+use super::router::{BoxFn, ExecFuture};
+use crate::{Clients, Connection, MessageStateCode, ResponseContext, RouterCode};
+use crate::default_log_pre;
+use function_name::named;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub const DEFAULT_RATE_LIMIT_PER_SECOND: u32 = 50;
+pub const DEFAULT_METRICS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+//one layer around a router handler, call next to continue the chain.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, clients: Clients, conn: Connection, next: BoxFn) -> ExecFuture;
+}
+
+//turns a panicking handler into a general error response instead of killing the connection task.
+pub struct PanicMiddleware;
+
+impl Middleware for PanicMiddleware {
+    #[named]
+    fn handle(&self, clients: Clients, conn: Connection, next: BoxFn) -> ExecFuture {
+        let code = conn.msg.code as u16;
+        let session_id = conn.msg.session_id;
+        let prefix = conn.length_prefix();
+
+        Box::pin(async move {
+            match AssertUnwindSafe(async move { next(clients, conn).await })
+                .catch_unwind()
+                .await
+            {
+                Ok(resp) => resp,
+                Err(_) => {
+                    error!("{}\thandler panicked session_id:{}\t", default_log_pre!(code,""), session_id);
+                    ResponseContext::get_bincode_with(
+                        code,
+                        session_id,
+                        MessageStateCode::GeneralError,
+                        "internal error.",
+                        "",
+                        prefix,
+                    )
+                }
+            }
+        })
+    }
+}
+
+//logs every request with its outcome and elapsed time.
+pub struct LoggingMiddleware;
+
+impl Middleware for LoggingMiddleware {
+    #[named]
+    fn handle(&self, clients: Clients, conn: Connection, next: BoxFn) -> ExecFuture {
+        let code = conn.msg.code as u16;
+        let version = conn.msg.version;
+        let conn_id = conn.session.conn_id;
+        let uid = conn.session.uid().unwrap_or(0);
+
+        Box::pin(async move {
+            let start = Instant::now();
+            let resp = next(clients, conn).await;
+            let elapsed = start.elapsed().as_millis();
+
+            match &resp {
+                Ok(_) => info!(
+                    "{}\trequest done conn_id:{}\tversion:{}\telapsed_ms:{}",
+                    default_log_pre!(code,uid), conn_id, version, elapsed
+                ),
+                Err(e) => error!(
+                    "{}\trequest failed conn_id:{}\tversion:{}\telapsed_ms:{}\terror:{:?}",
+                    default_log_pre!(code,uid), conn_id, version, elapsed, e
+                ),
+            }
+
+            resp
+        })
+    }
+}
+
+//only login and heartbeat are served before the session is authenticated,
+//after it a request may only claim the uid bound to the session.
+pub struct AuthMiddleware;
+
+impl Middleware for AuthMiddleware {
+    #[named]
+    fn handle(&self, clients: Clients, conn: Connection, next: BoxFn) -> ExecFuture {
+        let code = conn.msg.code;
+
+        if conn.session.uid().is_none()
+            && code != RouterCode::ConnectionState
+            && code != RouterCode::Heartbeat
+        {
+            error!("{}\tunauthenticated request conn_id:{}\t", default_log_pre!(code as u16,""), conn.session.conn_id);
+            let resp = conn.get_general_error("unauthenticated.");
+            return Box::pin(async move { resp });
+        }
+
+        if let Some(claimed) = conn.claimed_uid {
+            if let Err(e) = conn.authorized_uid(claimed) {
+                error!(
+                    "{}\trejected identity conn_id:{}\treason:{}.",
+                    default_log_pre!(code as u16,claimed), conn.session.conn_id, e
+                );
+                let resp = conn.get_general_error(e.to_string().as_str());
+                return Box::pin(async move { resp });
+            }
+        }
+
+        next(clients, conn)
+    }
+}
+
+//fixed one second window per connection.
+pub struct RateLimitMiddleware {
+    per_second: u32,
+    windows: Mutex<HashMap<u64, (Instant, u32)>>,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(per_second: u32) -> Self {
+        RateLimitMiddleware {
+            per_second,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn allow(&self, conn_id: u64) -> bool {
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut windows = self.windows.lock().unwrap();
+
+        //drop windows of idle or closed connections once the map grows.
+        if windows.len() > 10_000 {
+            windows.retain(|_, (start, _)| now.duration_since(*start) < second);
+        }
+
+        let window = windows.entry(conn_id).or_insert((now, 0));
+        if now.duration_since(window.0) >= second {
+            *window = (now, 0);
+        }
+
+        window.1 += 1;
+        window.1 <= self.per_second
+    }
+}
+
+impl Middleware for RateLimitMiddleware {
+    #[named]
+    fn handle(&self, clients: Clients, conn: Connection, next: BoxFn) -> ExecFuture {
+        if !self.allow(conn.session.conn_id) {
+            error!(
+                "{}\trate limited conn_id:{}\t",
+                default_log_pre!(conn.msg.code as u16,conn.session.uid().unwrap_or(0)),
+                conn.session.conn_id
+            );
+            let resp = conn.get_general_error("too many requests.");
+            return Box::pin(async move { resp });
+        }
+
+        next(clients, conn)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RouteMetric {
+    pub requests: AtomicU64,
+    pub errors: AtomicU64,
+    pub total_micros: AtomicU64,
+}
+
+//request counters and latency per router code, a response with a non Ok state counts as an error.
+#[derive(Debug, Default)]
+pub struct RouterMetrics {
+    routes: Mutex<HashMap<RouterCode, Arc<RouteMetric>>>,
+}
+
+impl RouterMetrics {
+    pub fn route(&self, code: RouterCode) -> Arc<RouteMetric> {
+        self.routes
+            .lock()
+            .unwrap()
+            .entry(code)
+            .or_insert_with(|| Arc::new(RouteMetric::default()))
+            .clone()
+    }
+
+    //(code, requests, errors, average latency in micros)
+    pub fn snapshot(&self) -> Vec<(RouterCode, u64, u64, u64)> {
+        let routes = self.routes.lock().unwrap();
+
+        routes
+            .iter()
+            .map(|(code, m)| {
+                let requests = m.requests.load(Ordering::Relaxed);
+                let total = m.total_micros.load(Ordering::Relaxed);
+                let avg = if requests == 0 { 0 } else { total / requests };
+                (*code, requests, m.errors.load(Ordering::Relaxed), avg)
+            })
+            .collect()
+    }
+}
+
+pub struct MetricsMiddleware {
+    metrics: Arc<RouterMetrics>,
+}
+
+impl MetricsMiddleware {
+    pub fn new(metrics: Arc<RouterMetrics>) -> Self {
+        MetricsMiddleware { metrics }
+    }
+}
+
+impl Middleware for MetricsMiddleware {
+    fn handle(&self, clients: Clients, conn: Connection, next: BoxFn) -> ExecFuture {
+        let metric = self.metrics.route(conn.msg.code);
+
+        Box::pin(async move {
+            let start = Instant::now();
+            let resp = next(clients, conn).await;
+
+            metric.requests.fetch_add(1, Ordering::Relaxed);
+            metric
+                .total_micros
+                .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+            let ok = match &resp {
+                Ok(v) => response_state(v) == Some(MessageStateCode::Ok as u16),
+                Err(_) => false,
+            };
+            if !ok {
+                metric.errors.fetch_add(1, Ordering::Relaxed);
+            }
+
+            resp
+        })
+    }
+}
+
+#[named]
+pub async fn log_router_metrics(metrics: Arc<RouterMetrics>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+
+        for (code, requests, errors, avg) in metrics.snapshot() {
+            info!(
+                "{}\trouter metrics requests:{}\terrors:{}\tavg_micros:{}",
+                default_log_pre!(code as u16,""), requests, errors, avg
+            );
+        }
+    }
+}
@@ -1,9 +1,19 @@
+pub mod auth;
+pub mod broker;
+pub mod codec;
 pub mod common;
 pub mod connection;
 pub mod db;
-pub mod helper;
+pub mod dedup;
 pub mod message;
+pub mod middleware;
 pub mod redis_db;
+pub mod replay;
+pub mod request;
 pub mod router;
+pub mod session;
+pub mod signature;
+pub mod snowflake;
+pub mod store;
 pub mod thread_pool;
 pub mod binary_helper;
\ No newline at end of file
@@ -1,57 +1,224 @@
-use guid_create::GUID;
-use murmur3::murmur3_x64_128;
+use r2d2::ManageConnection;
+use super::snowflake::next_id;
 use redis::Commands;
-use redis::{Connection, RedisResult};
+use redis::{Client, Connection, RedisError, RedisResult};
 use std::env;
-use std::io::Cursor;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
 use tracing::error;
 
-pub fn get_connection() -> RedisResult<Arc<Mutex<Connection>>> {
-    let addr = env::var("REDIS_URL").expect("REDIS_URL not found.");
-    let client = redis::Client::open(addr)?;
+pub type RedisPool = r2d2::Pool<RedisConnectionManager>;
+pub type RedisConnPool = r2d2::PooledConnection<RedisConnectionManager>;
+
+pub const DEFAULT_REDIS_POOL_SIZE: u32 = 16;
+pub const DEFAULT_REDIS_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+pub const DEFAULT_REDIS_IO_TIMEOUT: Duration = Duration::from_secs(3);
+pub const DEFAULT_REDIS_MAX_LIFETIME: Duration = Duration::from_secs(30 * 60);
+
+//opens pooled connections, a connection that fails a command or a checkout ping is dropped and reopened.
+pub struct RedisConnectionManager {
+    client: Client,
+    //bounds the tcp connect, an unreachable host must not hold a pool thread for the os timeout.
+    connect_timeout: Duration,
+    io_timeout: Option<Duration>,
+}
+
+impl RedisConnectionManager {
+    pub fn new(url: &str, connect_timeout: Duration, io_timeout: Option<Duration>) -> RedisResult<Self> {
+        Ok(RedisConnectionManager {
+            client: Client::open(url)?,
+            connect_timeout,
+            io_timeout,
+        })
+    }
+}
+
+impl ManageConnection for RedisConnectionManager {
+    type Connection = Connection;
+    type Error = RedisError;
+
+    fn connect(&self) -> RedisResult<Connection> {
+        let conn = self.client.get_connection_with_timeout(self.connect_timeout)?;
+        conn.set_read_timeout(self.io_timeout)?;
+        conn.set_write_timeout(self.io_timeout)?;
+
+        Ok(conn)
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> RedisResult<()> {
+        redis::cmd("PING").query(conn)
+    }
+
+    fn has_broken(&self, conn: &mut Connection) -> bool {
+        !conn.is_open()
+    }
+}
+
+//pool size, timeouts and reconnect behaviour of the shared redis pool.
+#[derive(Debug, Clone)]
+pub struct RedisPoolConfig {
+    pub url: String,
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connect_timeout: Duration,
+    pub io_timeout: Option<Duration>,
+    //ping every connection on checkout so a redis restart is noticed before a command fails.
+    pub test_on_check_out: bool,
+    pub max_lifetime: Option<Duration>,
+}
+
+impl RedisPoolConfig {
+    pub fn new(url: &str) -> Self {
+        RedisPoolConfig {
+            url: url.to_string(),
+            max_size: DEFAULT_REDIS_POOL_SIZE,
+            min_idle: None,
+            connect_timeout: DEFAULT_REDIS_CONNECT_TIMEOUT,
+            io_timeout: Some(DEFAULT_REDIS_IO_TIMEOUT),
+            test_on_check_out: true,
+            max_lifetime: Some(DEFAULT_REDIS_MAX_LIFETIME),
+        }
+    }
+
+    //REDIS_URL plus the optional REDIS_POOL_SIZE, REDIS_CONNECT_TIMEOUT_MS, REDIS_IO_TIMEOUT_MS,
+    //REDIS_TEST_ON_CHECK_OUT and REDIS_MAX_LIFETIME_SECS.
+    pub fn from_env() -> Self {
+        let addr = env::var("REDIS_URL").expect("REDIS_URL not found.");
+        let mut config = RedisPoolConfig::new(&addr);
+
+        if let Some(v) = env_parse::<u32>("REDIS_POOL_SIZE").filter(|v| *v > 0) {
+            config.max_size = v;
+        }
+        if let Some(v) = env_parse::<u64>("REDIS_CONNECT_TIMEOUT_MS") {
+            config.connect_timeout = Duration::from_millis(v);
+        }
+        if let Some(v) = env_parse::<u64>("REDIS_IO_TIMEOUT_MS") {
+            config.io_timeout = if v == 0 { None } else { Some(Duration::from_millis(v)) };
+        }
+        if let Some(v) = env_parse::<bool>("REDIS_TEST_ON_CHECK_OUT") {
+            config.test_on_check_out = v;
+        }
+        if let Some(v) = env_parse::<u64>("REDIS_MAX_LIFETIME_SECS") {
+            config.max_lifetime = Some(Duration::from_secs(v));
+        }
 
-    let conn = client.get_connection()?;
+        config
+    }
 
-    Ok(Arc::new(Mutex::new(conn)))
+    //does not connect yet, connections are opened on demand and reopened after redis comes back.
+    pub fn build(&self) -> RedisResult<RedisPool> {
+        let manager = RedisConnectionManager::new(&self.url, self.connect_timeout, self.io_timeout)?;
+
+        Ok(r2d2::Pool::builder()
+            .max_size(self.max_size)
+            .min_idle(self.min_idle)
+            .connection_timeout(self.connect_timeout)
+            .test_on_check_out(self.test_on_check_out)
+            .max_lifetime(self.max_lifetime)
+            .build_unchecked(manager))
+    }
 }
 
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|v| v.parse::<T>().ok())
+}
+
+pub fn get_redis_pool() -> Arc<RedisPool> {
+    Arc::new(
+        RedisPoolConfig::from_env()
+            .build()
+            .expect("failed init redis pool."),
+    )
+}
+
+//a dedicated connection outside the pool, for subscribers that hold it for their whole life.
 pub fn get_redis_connection_by_url() -> RedisResult<Connection> {
     let addr = env::var("REDIS_URL").expect("REDIS_URL not found.");
-    let client = redis::Client::open(addr)?;
+    let client = Client::open(addr)?;
 
-    let conn = client.get_connection();
-
-    conn
+    client.get_connection()
 }
 
 pub const ONLINE_USERS_SETS_REDIS_KEY: &str = "online_users";
+pub const ONLINE_USER_CONNECTIONS_REDIS_KEY_PREFIX: &str = "online_user_connections_"; //open connections of a uid over every node
 pub const CHAT_KINGDOM_MESSAGE_REDIS_KEY_PREFIX: &str = "chat_kingdom_message_";
 pub const CHAT_GROUP_MESSAGE_REDIS_KEY_PREFIX: &str = "chat_group_message_";
 pub const CHAT_USER_MESSAGE_REDIS_KEY_PREFIX: &str = "chat_user_message_"; //p2p->format(from_uid:to_uid)
 pub const CHAT_PUBLISH_CHANNEL_REDIS_KEY: &str = "chat_publish_channel"; //format->(tid:$:value:$:from_uid:$:to_uid:$:content)
+pub const CHAT_PUBLISH_SEPARATOR: &str = ":$:";
+pub const USER_OFFLINE_CHANNEL_REDIS_KEY: &str = "user_offline_channel"; //format->(uid)
 
-pub struct RedisDbConn {
-    pub conn: Connection,
+//committed message fanned out to every chat node.
+//value is the message mid, to_uid the kingdom server number / gid / peer uid, content the json push message.
+#[derive(Debug, Clone)]
+pub struct ChatPublishMessage {
+    pub tid: u8,
+    pub mid: i64,
+    pub from_uid: u64,
+    pub to_uid: u64,
+    pub content: String,
 }
 
-impl RedisDbConn {
-    pub fn new() -> Self {
-        let addr = env::var("REDIS_URL").expect("REDIS_URL not found.");
-        let client = redis::Client::open(addr).unwrap();
+impl ChatPublishMessage {
+    pub fn to_payload(&self) -> String {
+        format!(
+            "{}{sep}{}{sep}{}{sep}{}{sep}{}",
+            self.tid,
+            self.mid,
+            self.from_uid,
+            self.to_uid,
+            self.content,
+            sep = CHAT_PUBLISH_SEPARATOR
+        )
+    }
 
-        let conn = client.get_connection().unwrap();
+    pub fn from_payload(payload: &str) -> Option<Self> {
+        let mut fields = payload.splitn(5, CHAT_PUBLISH_SEPARATOR);
 
-        RedisDbConn { conn }
+        Some(ChatPublishMessage {
+            tid: fields.next()?.parse().ok()?,
+            mid: fields.next()?.parse().ok()?,
+            from_uid: fields.next()?.parse().ok()?,
+            to_uid: fields.next()?.parse().ok()?,
+            content: fields.next()?.to_string(),
+        })
     }
 }
 
-pub fn store_chat_message_redis(tid: u8, from_uid: u64, dst_id: u64, msg: &[u8]) {
-    //store message to redis
+pub fn publish_chat_message_redis(redis_conn: &mut Connection, msg: &ChatPublishMessage) -> RedisResult<()> {
+    redis_conn.publish::<&str, String, i64>(CHAT_PUBLISH_CHANNEL_REDIS_KEY, msg.to_payload())?;
+
+    Ok(())
+}
+
+//blocks reading the publish channel, returns Ok once the receiver side is gone.
+pub fn subscribe_chat_message_redis(sender: &UnboundedSender<ChatPublishMessage>) -> RedisResult<()> {
+    let mut redis_conn = get_redis_connection_by_url()?;
+    let mut pubsub = redis_conn.as_pubsub();
 
-    let mut redis_conn = get_redis_connection_by_url().unwrap();
+    pubsub.subscribe(CHAT_PUBLISH_CHANNEL_REDIS_KEY)?;
+
+    loop {
+        let msg = pubsub.get_message()?;
+        let payload: String = msg.get_payload()?;
+
+        let data = match ChatPublishMessage::from_payload(&payload) {
+            Some(v) => v,
+            None => {
+                error!("invalid chat publish payload:{}", payload);
+                continue;
+            }
+        };
+
+        if sender.send(data).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+pub fn store_chat_message_redis(redis_conn: &mut Connection, tid: u8, from_uid: u64, dst_id: u64, msg: &[u8]) {
+    //store message to redis
 
     let start = SystemTime::now();
     let since_the_epoch = start
@@ -59,12 +226,7 @@ pub fn store_chat_message_redis(tid: u8, from_uid: u64, dst_id: u64, msg: &[u8])
         .expect("Time went backwards")
         .as_secs();
 
-    //create message id
-    let guid = GUID::rand();
-    let guid = guid.to_string();
-    let guid_str = guid.replace("-", "");
-    let hash_result = murmur3_x64_128(&mut Cursor::new(guid_str), 0).unwrap();
-    let msg_id = (hash_result >> 65) as u64;
+    let msg_id = next_id();
 
     match tid {
         1 => {
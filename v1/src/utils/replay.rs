@@ -0,0 +1,134 @@
+use super::signature::SIGNATURE_LENGTH;
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+
+pub const DEFAULT_REPLAY_WINDOW_SECS: u64 = 300;
+pub const DEFAULT_REPLAY_CACHE_CAPACITY: usize = 100_000;
+pub const DEFAULT_REPLAY_SESSION_CAPACITY: usize = 1_024;
+
+//(timestamp, signature) of a frame inside one session.
+type ReplayKey = (u64, [u8; SIGNATURE_LENGTH]);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayError {
+    Expired { timestamp: u64, now: u64 },
+    Replayed,
+    //every remembered frame is still inside the window, forgetting one would let it replay.
+    CacheFull,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Expired { timestamp, now } => {
+                write!(f, "request timestamp {} outside window, server time {}", timestamp, now)
+            }
+            ReplayError::Replayed => write!(f, "request already received"),
+            ReplayError::CacheFull => write!(f, "too many requests inside the window"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+#[derive(Default)]
+struct SeenRequests {
+    sessions: HashMap<u64, HashSet<ReplayKey>>,
+    total: usize,
+}
+
+impl SeenRequests {
+    //forget the keys of one session older than oldest, they can not pass the timestamp check again.
+    fn prune_session(&mut self, session_id: u64, oldest: u64) {
+        if let Some(keys) = self.sessions.get_mut(&session_id) {
+            let before = keys.len();
+            keys.retain(|key| key.0 >= oldest);
+            self.total -= before - keys.len();
+            if keys.is_empty() {
+                self.sessions.remove(&session_id);
+            }
+        }
+    }
+
+    fn prune(&mut self, oldest: u64) {
+        for keys in self.sessions.values_mut() {
+            keys.retain(|key| key.0 >= oldest);
+        }
+        self.sessions.retain(|_, keys| !keys.is_empty());
+        self.total = self.sessions.values().map(|keys| keys.len()).sum();
+    }
+}
+
+//rejects signed frames whose timestamp is stale and frames already seen inside the window.
+//keys are only forgotten once they leave the window, a full cache rejects new frames instead.
+pub struct ReplayGuard {
+    window_secs: u64,
+    capacity: usize,
+    session_capacity: usize,
+    seen: Mutex<SeenRequests>,
+}
+
+impl ReplayGuard {
+    //capacity bounds every remembered frame, session_capacity the frames of one session id.
+    pub fn new(window_secs: u64, capacity: usize, session_capacity: usize) -> Self {
+        ReplayGuard {
+            window_secs,
+            capacity,
+            session_capacity,
+            seen: Mutex::new(SeenRequests::default()),
+        }
+    }
+
+    pub fn check(
+        &self,
+        session_id: u64,
+        timestamp: u64,
+        signature: &[u8; SIGNATURE_LENGTH],
+    ) -> Result<(), ReplayError> {
+        self.check_at(Utc::now().timestamp() as u64, session_id, timestamp, signature)
+    }
+
+    pub fn check_at(
+        &self,
+        now: u64,
+        session_id: u64,
+        timestamp: u64,
+        signature: &[u8; SIGNATURE_LENGTH],
+    ) -> Result<(), ReplayError> {
+        let diff = if now > timestamp { now - timestamp } else { timestamp - now };
+        if diff > self.window_secs {
+            return Err(ReplayError::Expired { timestamp, now });
+        }
+
+        let key = (timestamp, *signature);
+        let oldest = now.saturating_sub(self.window_secs);
+        let mut seen = self.seen.lock().unwrap();
+
+        let session_len = match seen.sessions.get(&session_id) {
+            Some(keys) if keys.contains(&key) => return Err(ReplayError::Replayed),
+            Some(keys) => keys.len(),
+            None => 0,
+        };
+
+        if session_len >= self.session_capacity {
+            seen.prune_session(session_id, oldest);
+            if seen.sessions.get(&session_id).map_or(0, |keys| keys.len()) >= self.session_capacity {
+                return Err(ReplayError::CacheFull);
+            }
+        }
+
+        if seen.total >= self.capacity {
+            seen.prune(oldest);
+            if seen.total >= self.capacity {
+                return Err(ReplayError::CacheFull);
+            }
+        }
+
+        seen.sessions.entry(session_id).or_default().insert(key);
+        seen.total += 1;
+
+        Ok(())
+    }
+}
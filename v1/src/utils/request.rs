@@ -0,0 +1,39 @@
+use super::common::{BinaryDecode, BinaryEncode, LengthPrefix};
+use anyhow::{anyhow, Result};
+use std::io::Cursor;
+
+//a route's request body, decoded and validated before its handler runs.
+pub trait RequestBody: for<'a> BinaryDecode<'a> + Sized {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    //the uid the request acts for, AuthMiddleware rejects it unless the session is bound to it.
+    fn claimed_uid(&self) -> Option<u64> {
+        None
+    }
+
+    fn parse(body: &[u8], prefix: LengthPrefix) -> Result<Self> {
+        let mut cursor = Cursor::new(body);
+        let req = Self::decode_with(&mut cursor, body, prefix)?;
+        req.validate()?;
+
+        Ok(req)
+    }
+}
+
+//client side: a request body is the struct's fields without the item length in front.
+pub fn encode_body<T: BinaryEncode>(req: &T, prefix: LengthPrefix) -> Result<Vec<u8>> {
+    let mut encoded = req.encode_with(prefix)?;
+    encoded.drain(..prefix.width());
+
+    Ok(encoded)
+}
+
+pub fn ensure(cond: bool, msg: &str) -> Result<()> {
+    if cond {
+        Ok(())
+    } else {
+        Err(anyhow!("{}", msg))
+    }
+}
@@ -1,20 +1,37 @@
+use super::middleware::Middleware;
+use super::common::LengthPrefix;
+use super::request::RequestBody;
 use crate::{Clients, Connection, RouterCode};
+use crate::default_log_pre;
 use anyhow::{anyhow, Result};
+use function_name::named;
 use std::collections::HashMap;
 use std::future::Future;
+use std::io::Cursor;
 use std::pin::Pin;
+use std::sync::Arc;
+use tracing::error;
 
-type ExecFuture = Pin<Box<dyn Future<Output = ResponseResult> + Send + Sync + 'static>>;
-type BoxFn = Box<dyn Fn(Clients, Connection) -> ExecFuture + Send + Sync + 'static>;
+pub type ExecFuture = Pin<Box<dyn Future<Output = ResponseResult> + Send + Sync + 'static>>;
+pub type BoxFn = Arc<dyn Fn(Clients, Connection) -> ExecFuture + Send + Sync + 'static>;
 
+//reads the uid a request body claims to act for, see RequestBody::claimed_uid.
+type ClaimFn = fn(&[u8], LengthPrefix) -> Option<u64>;
+
+//handlers are keyed by code and an optional request version,
+//a versioned handler wins over the default one registered for the same code.
 pub struct RouterRegister {
-    route: HashMap<RouterCode, BoxFn>,
+    route: HashMap<(RouterCode, Option<u8>), BoxFn>,
+    claims: HashMap<(RouterCode, Option<u8>), ClaimFn>,
+    middlewares: Vec<Arc<dyn Middleware>>,
 }
 
 impl RouterRegister {
     pub fn new() -> Self {
         RouterRegister {
             route: HashMap::new(),
+            claims: HashMap::new(),
+            middlewares: Vec::new(),
         }
     }
 
@@ -24,21 +41,110 @@ impl RouterRegister {
         R: Future<Output = ResponseResult> + Send + Sync + 'static,
     {
         self.route.insert(
-            code,
-            Box::new(move |clients, conn| Box::pin(callback(clients, conn))),
+            (code, None),
+            Arc::new(move |clients, conn| Box::pin(callback(clients, conn))),
+        );
+    }
+
+    //register a handler only for requests carrying this Message.version.
+    pub fn add_version<F, R>(&mut self, code: RouterCode, version: u8, callback: F)
+    where
+        F: Fn(Clients, Connection) -> R + Send + Sync + 'static,
+        R: Future<Output = ResponseResult> + Send + Sync + 'static,
+    {
+        self.route.insert(
+            (code, Some(version)),
+            Arc::new(move |clients, conn| Box::pin(callback(clients, conn))),
         );
     }
 
-    pub fn call(&self, code: RouterCode) -> Result<&BoxFn> {
-        let f = match self.route.get(&code) {
-            Some(h) => h,
+    //the body is decoded into Q and validated first, malformed bodies never reach the handler.
+    pub fn add_request<Q, F, R>(&mut self, code: RouterCode, callback: F)
+    where
+        Q: RequestBody + Send + Sync + 'static,
+        F: Fn(Clients, Connection, Q) -> R + Send + Sync + 'static,
+        R: Future<Output = ResponseResult> + Send + Sync + 'static,
+    {
+        self.route.insert((code, None), request_handler(callback));
+        self.claims.insert((code, None), claimed_uid::<Q>);
+    }
+
+    pub fn add_version_request<Q, F, R>(&mut self, code: RouterCode, version: u8, callback: F)
+    where
+        Q: RequestBody + Send + Sync + 'static,
+        F: Fn(Clients, Connection, Q) -> R + Send + Sync + 'static,
+        R: Future<Output = ResponseResult> + Send + Sync + 'static,
+    {
+        self.route.insert((code, Some(version)), request_handler(callback));
+        self.claims.insert((code, Some(version)), claimed_uid::<Q>);
+    }
+
+    //middlewares wrap every handler, the first one added is the outermost.
+    pub fn layer<M: Middleware>(&mut self, middleware: M) {
+        self.middlewares.push(Arc::new(middleware));
+    }
+
+    pub fn call(&self, code: RouterCode, version: u8) -> Result<BoxFn> {
+        let key = if self.route.contains_key(&(code, Some(version))) {
+            (code, Some(version))
+        } else {
+            (code, None)
+        };
+        let f = match self.route.get(&key) {
+            Some(h) => h.clone(),
             None => {
-                return Err(anyhow!("No code found for {:?}", code));
+                return Err(anyhow!("No code found for {:?} version {}", code, version));
             }
         };
 
-        Ok(f)
+        let f = self.middlewares.iter().rev().fold(f, |next, middleware| {
+            let middleware = middleware.clone();
+            Arc::new(move |clients, conn| middleware.handle(clients, conn, next.clone()))
+        });
+
+        //the claim is read before the chain runs, so middlewares can check it.
+        let claim = self.claims.get(&key).copied();
+        Ok(Arc::new(move |clients, mut conn: Connection| {
+            conn.claimed_uid = claim.and_then(|c| c(&conn.msg.body, conn.length_prefix()));
+            f(clients, conn)
+        }))
+    }
+}
+
+impl Default for RouterRegister {
+    fn default() -> Self {
+        RouterRegister::new()
     }
 }
 
+//a body that does not decode claims nothing, its handler rejects it.
+fn claimed_uid<Q: RequestBody>(body: &[u8], prefix: LengthPrefix) -> Option<u64> {
+    let mut cursor = Cursor::new(body);
+    Q::decode_with(&mut cursor, body, prefix).ok()?.claimed_uid()
+}
+
+fn request_handler<Q, F, R>(callback: F) -> BoxFn
+where
+    Q: RequestBody + Send + Sync + 'static,
+    F: Fn(Clients, Connection, Q) -> R + Send + Sync + 'static,
+    R: Future<Output = ResponseResult> + Send + Sync + 'static,
+{
+    Arc::new(move |clients, conn| match Q::parse(&conn.msg.body, conn.length_prefix()) {
+        Ok(req) => Box::pin(callback(clients, conn, req)),
+        Err(e) => reject_request(conn, e),
+    })
+}
+
+#[named]
+fn reject_request(conn: Connection, e: anyhow::Error) -> ExecFuture {
+    error!(
+        "{}\tinvalid request body conn_id:{}\treason:{}.",
+        default_log_pre!(conn.msg.code as u16,conn.session.uid().unwrap_or(0)),
+        conn.session.conn_id,
+        e
+    );
+    let resp = conn.get_general_error(format!("invalid request body:{}", e).as_str());
+    Box::pin(async move { resp })
+}
+
 pub type ResponseResult = Result<Vec<u8>>;
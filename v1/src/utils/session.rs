@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+//per-socket state shared by every message received on the same connection.
+#[derive(Debug)]
+pub struct Session {
+    pub conn_id: u64,
+    uid: AtomicU64,
+}
+
+impl Session {
+    pub fn new(conn_id: u64) -> Self {
+        Session {
+            conn_id,
+            uid: AtomicU64::new(0),
+        }
+    }
+
+    //authenticated uid, None until connection_state succeeds.
+    pub fn uid(&self) -> Option<u64> {
+        match self.uid.load(Ordering::SeqCst) {
+            0 => None,
+            uid => Some(uid),
+        }
+    }
+
+    //bind the verified uid, a socket can not switch to another identity.
+    pub fn bind(&self, uid: u64) -> Result<()> {
+        match self.uid.compare_exchange(0, uid, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => Ok(()),
+            Err(current) if current == uid => Ok(()),
+            Err(_) => Err(anyhow!("session already bound to another user.")),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Context, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::env;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_LENGTH: usize = 32;
+
+//one signing secret issued to a client application.
+//a rotated out key keeps validating until expires_at(unix seconds), so old builds keep working during the window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningKey {
+    pub key_id: u16,
+    pub secret: String,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SigningKeys {
+    keys: HashMap<u16, SigningKey>,
+}
+
+impl SigningKeys {
+    pub fn new(keys: Vec<SigningKey>) -> Self {
+        SigningKeys {
+            keys: keys.into_iter().map(|k| (k.key_id, k)).collect(),
+        }
+    }
+
+    //json array of SigningKey from the file named by CHAT_SIGN_KEYS_FILE.
+    pub fn from_env() -> Result<Self> {
+        let path = env::var("CHAT_SIGN_KEYS_FILE").context("must set CHAT_SIGN_KEYS_FILE env.")?;
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed read signing keys file:{}", path))?;
+        let keys: Vec<SigningKey> = serde_json::from_str(&content)
+            .with_context(|| format!("failed parse signing keys file:{}", path))?;
+
+        if keys.is_empty() {
+            return Err(anyhow!("no signing keys configured."));
+        }
+
+        Ok(SigningKeys::new(keys))
+    }
+
+    pub fn get(&self, key_id: u16) -> Result<&SigningKey> {
+        let key = self
+            .keys
+            .get(&key_id)
+            .ok_or_else(|| anyhow!("unknown signing key:{}", key_id))?;
+
+        if let Some(expires_at) = key.expires_at {
+            if Utc::now().timestamp() > expires_at {
+                return Err(anyhow!("signing key expired:{}", key_id));
+            }
+        }
+
+        Ok(key)
+    }
+
+    pub fn sign(
+        &self,
+        key_id: u16,
+        code: u16,
+        version: u8,
+        session_id: u64,
+        timestamp: u64,
+        body: &[u8],
+    ) -> Result<[u8; SIGNATURE_LENGTH]> {
+        let key = self.get(key_id)?;
+        let mac = signature_mac(key, code, version, session_id, timestamp, body)?;
+
+        let mut signature = [0; SIGNATURE_LENGTH];
+        signature.copy_from_slice(&mac.finalize().into_bytes());
+
+        Ok(signature)
+    }
+
+    //constant time check of the hmac-sha256 over code, version, key id, session id, timestamp and body.
+    //version picks the body's length prefix, so it must not change without breaking the signature.
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify(
+        &self,
+        key_id: u16,
+        signature: &[u8],
+        code: u16,
+        version: u8,
+        session_id: u64,
+        timestamp: u64,
+        body: &[u8],
+    ) -> Result<()> {
+        let key = self.get(key_id)?;
+        let mac = signature_mac(key, code, version, session_id, timestamp, body)?;
+
+        mac.verify(signature)
+            .map_err(|_| anyhow!("invalid signature."))
+    }
+}
+
+fn signature_mac(
+    key: &SigningKey,
+    code: u16,
+    version: u8,
+    session_id: u64,
+    timestamp: u64,
+    body: &[u8],
+) -> Result<HmacSha256> {
+    let mut mac = HmacSha256::new_varkey(key.secret.as_bytes())
+        .map_err(|_| anyhow!("invalid signing key:{}", key.key_id))?;
+
+    let mut header = Vec::with_capacity(21);
+    header.write_u16::<LittleEndian>(code)?;
+    header.write_u8(version)?;
+    header.write_u16::<LittleEndian>(key.key_id)?;
+    header.write_u64::<LittleEndian>(session_id)?;
+    header.write_u64::<LittleEndian>(timestamp)?;
+
+    mac.update(&header);
+    mac.update(body);
+
+    Ok(mac)
+}
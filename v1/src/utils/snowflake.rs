@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+//layout, high to low: 41 bits milliseconds since ID_EPOCH_MILLIS, 10 bits worker id, 12 bits sequence.
+pub const ID_EPOCH_MILLIS: i64 = 1_577_836_800_000; //2020-01-01T00:00:00Z
+pub const WORKER_ID_BITS: u32 = 10;
+pub const SEQUENCE_BITS: u32 = 12;
+pub const MAX_WORKER_ID: u16 = (1 << WORKER_ID_BITS) - 1;
+
+const SEQUENCE_MASK: u64 = (1 << SEQUENCE_BITS) - 1;
+const TIMESTAMP_SHIFT: u32 = WORKER_ID_BITS + SEQUENCE_BITS;
+
+//time ordered unique ids, unique across nodes as long as every node runs with its own worker id.
+pub struct Snowflake {
+    worker_id: AtomicU64,
+    //last issued (timestamp << SEQUENCE_BITS | sequence).
+    state: AtomicU64,
+}
+
+impl Snowflake {
+    pub const fn new() -> Self {
+        Snowflake {
+            worker_id: AtomicU64::new(0),
+            state: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_worker_id(worker_id: u16) -> Result<Self> {
+        let generator = Snowflake::new();
+        generator.set_worker_id(worker_id)?;
+
+        Ok(generator)
+    }
+
+    pub fn set_worker_id(&self, worker_id: u16) -> Result<()> {
+        if worker_id > MAX_WORKER_ID {
+            return Err(anyhow!("worker id {} out of range 0..={}.", worker_id, MAX_WORKER_ID));
+        }
+        self.worker_id.store(worker_id as u64, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    pub fn worker_id(&self) -> u16 {
+        self.worker_id.load(Ordering::SeqCst) as u16
+    }
+
+    //never goes backwards: a clock step back reuses the last millisecond, a full sequence borrows the next one.
+    pub fn next_id(&self) -> i64 {
+        let now = (Utc::now().timestamp_millis() - ID_EPOCH_MILLIS).max(0) as u64;
+        let mut last = self.state.load(Ordering::SeqCst);
+
+        loop {
+            let last_timestamp = last >> SEQUENCE_BITS;
+            let next = if now > last_timestamp {
+                now << SEQUENCE_BITS
+            } else {
+                //the sequence overflows into the timestamp bits.
+                last + 1
+            };
+
+            match self
+                .state
+                .compare_exchange_weak(last, next, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => {
+                    let timestamp = next >> SEQUENCE_BITS;
+                    let sequence = next & SEQUENCE_MASK;
+                    return ((timestamp << TIMESTAMP_SHIFT)
+                        | (self.worker_id.load(Ordering::Relaxed) << SEQUENCE_BITS)
+                        | sequence) as i64;
+                }
+                Err(v) => last = v,
+            }
+        }
+    }
+}
+
+impl Default for Snowflake {
+    fn default() -> Self {
+        Snowflake::new()
+    }
+}
+
+static ID_GENERATOR: Snowflake = Snowflake::new();
+
+//set once at startup, before any id is issued.
+pub fn set_worker_id(worker_id: u16) -> Result<()> {
+    ID_GENERATOR.set_worker_id(worker_id)
+}
+
+//worker id of this node, tells nodes apart.
+pub fn worker_id() -> u16 {
+    ID_GENERATOR.worker_id()
+}
+
+//next id of this node, used for mid and every other generated primary key.
+pub fn next_id() -> i64 {
+    ID_GENERATOR.next_id()
+}
+
+//unix milliseconds the id was issued at.
+pub fn id_timestamp_millis(id: i64) -> i64 {
+    (id >> TIMESTAMP_SHIFT) + ID_EPOCH_MILLIS
+}
+
+pub fn id_worker_id(id: i64) -> u16 {
+    ((id as u64 >> SEQUENCE_BITS) & MAX_WORKER_ID as u64) as u16
+}
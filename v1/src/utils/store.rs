@@ -0,0 +1,861 @@
+use super::db::{DbConnPool, DieselPool};
+use crate::diesel::{Connection, OptionalExtension};
+use crate::next_id;
+use crate::models::{
+    blacklist::Blacklist,
+    chat_groups::ChatGroup,
+    chat_groups_uids::ChatGroupsUid,
+    chat_kingdom_reads::ChatKingdomRead,
+    chat_messages::{
+        ChatMessage, FrontDisplayGroupChatMessage, FrontDisplayKingdomChatMessage,
+        FrontDisplayP2pChatMessage, FrontDisplayP2pChatMessageCount, GroupMessagePage,
+        KingdomMessagePage, MessageCursor, P2pMessagePage, PageMode, PageQuery,
+    },
+    chat_user_unread_counts::{ChatUserUnreadCount, FrontDisplayChatUserUnreadCount},
+    servers::Server,
+    user::{FrontDisplayChatUser, User},
+};
+use anyhow::{anyhow, Error, Result};
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+//chat persistence: messages, unread counters, groups, blacklist and user lookups.
+//kind is the channel of a message, 1:kingdom,2:group,3:p2p.
+pub trait ChatStore: Send + Sync {
+    //store a message, group and p2p messages bump the recipients unread counters in the same write.
+    fn add_message(
+        &self,
+        send_id: i64,
+        to_id: i64,
+        content: String,
+        kind: i16,
+        msg_type: i16,
+    ) -> Result<ChatMessage>;
+
+    fn kingdom_display_message(&self, msg: ChatMessage) -> Result<FrontDisplayKingdomChatMessage>;
+
+    fn group_display_message(&self, msg: ChatMessage) -> Result<FrontDisplayGroupChatMessage>;
+
+    fn p2p_display_message(&self, msg: ChatMessage) -> Result<FrontDisplayP2pChatMessage>;
+
+    //history pages ordered by (created_timestamp, mid), see PageQuery.
+    fn kingdom_messages(&self, kingdom_id: i64, page: PageQuery) -> Result<KingdomMessagePage>;
+
+    fn group_messages(&self, gid: i64, page: PageQuery) -> Result<GroupMessagePage>;
+
+    fn p2p_messages(&self, send_id: i64, to_id: i64, page: PageQuery) -> Result<P2pMessagePage>;
+
+    //kingdom messages newer than cursor.
+    fn kingdom_unread_count_after(&self, kingdom_id: i64, cursor: MessageCursor) -> Result<i64>;
+
+    //fails when nothing newer than cursor exists.
+    fn kingdom_unread_count_and_latest_message(
+        &self,
+        kingdom_id: i64,
+        cursor: MessageCursor,
+    ) -> Result<(i64, FrontDisplayKingdomChatMessage)>;
+
+    //group messages after the read position of uid, its own messages are never unread.
+    fn group_unread_count(&self, gid: i64, uid: i64) -> Result<i64>;
+
+    //same count after cursor, fails when nothing newer than cursor exists.
+    fn group_unread_count_and_latest_message(
+        &self,
+        gid: i64,
+        uid: i64,
+        cursor: MessageCursor,
+    ) -> Result<(i64, FrontDisplayGroupChatMessage)>;
+
+    //every peer that left uid unread p2p messages.
+    fn user_unread_counts(&self, uid: i64) -> Result<Vec<FrontDisplayChatUserUnreadCount>>;
+
+    fn user_unread_count(&self, uid: i64, send_uid: i64) -> Result<i16>;
+
+    //groups of uid holding unread messages.
+    fn unread_groups(&self, uid: i64) -> Result<Vec<ChatGroupsUid>>;
+
+    fn group_member_uids(&self, gid: i64) -> Result<Vec<i64>>;
+
+    //uid read the p2p messages of send_uid up to cursor, returns what is left unread.
+    //a position older than the stored one is ignored and the stored count returned.
+    fn mark_p2p_read(&self, uid: i64, send_uid: i64, cursor: MessageCursor) -> Result<i64>;
+
+    //same as mark_p2p_read for a group, fails when uid is not a member.
+    fn mark_group_read(&self, gid: i64, uid: i64, cursor: MessageCursor) -> Result<i64>;
+
+    //kingdom messages up to this cursor are read by uid, (0, 0) before the first read.
+    fn kingdom_read_cursor(&self, uid: i64, kingdom_id: i64) -> Result<MessageCursor>;
+
+    //uid read the kingdom up to cursor, an older cursor is ignored.
+    fn mark_kingdom_read(&self, uid: i64, kingdom_id: i64, cursor: MessageCursor) -> Result<()>;
+
+    //unread group and p2p messages of uid, the badge total.
+    fn user_unread_total(&self, uid: i64) -> Result<i64>;
+
+    //whether uid blacklisted black_uid.
+    fn is_blacklisted(&self, uid: i64, black_uid: i64) -> Result<bool>;
+
+    //kingdom of the server the user plays on.
+    fn kingdom_id(&self, uid: i64) -> Result<i64>;
+
+    fn server_kingdom_id(&self, server_number: i32) -> Result<i64>;
+
+    //the subset of uids playing on server_number.
+    fn server_uids(&self, server_number: i32, uids: Vec<i64>) -> Result<Vec<i64>>;
+
+    fn chat_user(&self, uid: i64) -> Result<FrontDisplayChatUser>;
+}
+
+//unread counters are smallint columns.
+fn clamp_count(count: i64) -> i16 {
+    count.min(i16::MAX as i64) as i16
+}
+
+//writes go to the master pool, reads to the slave pool.
+pub struct PgStore {
+    master_db: Arc<DieselPool>,
+    slave_db: Arc<DieselPool>,
+}
+
+impl PgStore {
+    pub fn new(master_db: Arc<DieselPool>, slave_db: Arc<DieselPool>) -> Self {
+        PgStore { master_db, slave_db }
+    }
+
+    fn master(&self) -> Result<DbConnPool> {
+        Ok(self.master_db.get()?)
+    }
+
+    fn slave(&self) -> Result<DbConnPool> {
+        Ok(self.slave_db.get()?)
+    }
+}
+
+impl ChatStore for PgStore {
+    fn add_message(
+        &self,
+        send_id: i64,
+        to_id: i64,
+        content: String,
+        kind: i16,
+        msg_type: i16,
+    ) -> Result<ChatMessage> {
+        let conn = self.master()?;
+
+        conn.transaction::<ChatMessage, Error, _>(|| {
+            let msg = ChatMessage::add(&conn, send_id, to_id, content, kind, msg_type)?;
+
+            match kind {
+                2 => ChatGroupsUid::update_unread_count(&conn, to_id, send_id)?,
+                3 => ChatUserUnreadCount::add(&conn, to_id, send_id, 1)?,
+                _ => {}
+            }
+
+            Ok(msg)
+        })
+    }
+
+    fn kingdom_display_message(&self, msg: ChatMessage) -> Result<FrontDisplayKingdomChatMessage> {
+        let conn = self.slave()?;
+        Ok(ChatMessage::get_front_display_kingdom_message(&conn, msg)?)
+    }
+
+    fn group_display_message(&self, msg: ChatMessage) -> Result<FrontDisplayGroupChatMessage> {
+        let conn = self.master()?;
+        Ok(ChatMessage::get_front_display_group_message(&conn, msg)?)
+    }
+
+    fn p2p_display_message(&self, msg: ChatMessage) -> Result<FrontDisplayP2pChatMessage> {
+        let conn = self.master()?;
+        Ok(ChatMessage::get_front_display_p2p_message(&conn, msg)?)
+    }
+
+    fn kingdom_messages(&self, kingdom_id: i64, page: PageQuery) -> Result<KingdomMessagePage> {
+        let conn = self.slave()?;
+        ChatMessage::get_kingdom_message(&conn, kingdom_id, &page)
+    }
+
+    fn group_messages(&self, gid: i64, page: PageQuery) -> Result<GroupMessagePage> {
+        let conn = self.slave()?;
+        Ok(ChatMessage::get_group_message(&conn, gid, &page)?)
+    }
+
+    fn p2p_messages(&self, send_id: i64, to_id: i64, page: PageQuery) -> Result<P2pMessagePage> {
+        let conn = self.slave()?;
+        Ok(ChatMessage::get_p2p_message(&conn, send_id, to_id, &page)?)
+    }
+
+    fn kingdom_unread_count_after(&self, kingdom_id: i64, cursor: MessageCursor) -> Result<i64> {
+        let conn = self.slave()?;
+        Ok(ChatMessage::get_kingdom_unread_count_after(&conn, kingdom_id, &cursor)?)
+    }
+
+    fn kingdom_unread_count_and_latest_message(
+        &self,
+        kingdom_id: i64,
+        cursor: MessageCursor,
+    ) -> Result<(i64, FrontDisplayKingdomChatMessage)> {
+        let conn = self.slave()?;
+        ChatMessage::get_kingdom_unread_count_and_latest_message(&conn, kingdom_id, &cursor)
+    }
+
+    //read from master, a position marked just before must be seen.
+    fn group_unread_count(&self, gid: i64, uid: i64) -> Result<i64> {
+        let conn = self.master()?;
+        let member = ChatGroupsUid::get_group_user_info(&conn, gid, uid)?;
+        Ok(ChatMessage::get_group_unread_count_after(&conn, gid, uid, &member.read_cursor())?)
+    }
+
+    fn group_unread_count_and_latest_message(
+        &self,
+        gid: i64,
+        uid: i64,
+        cursor: MessageCursor,
+    ) -> Result<(i64, FrontDisplayGroupChatMessage)> {
+        let conn = self.slave()?;
+        ChatMessage::get_group_unread_count_and_latest_message(&conn, gid, uid, &cursor)
+    }
+
+    fn user_unread_counts(&self, uid: i64) -> Result<Vec<FrontDisplayChatUserUnreadCount>> {
+        let conn = self.slave()?;
+        Ok(ChatUserUnreadCount::get_user_unread_message_count(&conn, uid)?)
+    }
+
+    fn user_unread_count(&self, uid: i64, send_uid: i64) -> Result<i16> {
+        let conn = self.slave()?;
+        Ok(ChatUserUnreadCount::get_user_unread_count(&conn, uid, send_uid)?)
+    }
+
+    fn unread_groups(&self, uid: i64) -> Result<Vec<ChatGroupsUid>> {
+        let conn = self.slave()?;
+        Ok(ChatGroupsUid::get_gids_by_uid(&conn, uid)?)
+    }
+
+    fn group_member_uids(&self, gid: i64) -> Result<Vec<i64>> {
+        let conn = self.slave()?;
+        Ok(ChatGroupsUid::get_groups_users_by_gid(&conn, gid)?
+            .into_iter()
+            .map(|(_gid, uid, _server_id)| uid)
+            .collect())
+    }
+
+    fn mark_p2p_read(&self, uid: i64, send_uid: i64, cursor: MessageCursor) -> Result<i64> {
+        let conn = self.master()?;
+
+        conn.transaction::<i64, Error, _>(|| {
+            //no row yet, nothing was ever unread
+            if ChatUserUnreadCount::lock_user_unread_count(&conn, uid, send_uid).optional()?.is_none() {
+                return Ok(0);
+            }
+
+            //counted under the row lock so an unread bump of a concurrent send is not overwritten
+            let unread_count = ChatMessage::get_p2p_unread_count_after(&conn, send_uid, uid, &cursor)?;
+            ChatUserUnreadCount::mark_read(&conn, uid, send_uid, &cursor, clamp_count(unread_count))?;
+
+            Ok(ChatUserUnreadCount::get_user_unread_count(&conn, uid, send_uid)? as i64)
+        })
+    }
+
+    fn mark_group_read(&self, gid: i64, uid: i64, cursor: MessageCursor) -> Result<i64> {
+        let conn = self.master()?;
+
+        conn.transaction::<i64, Error, _>(|| {
+            ChatGroupsUid::lock_group_user(&conn, gid, uid)
+                .optional()?
+                .ok_or_else(|| anyhow!("not a member of group:{}", gid))?;
+
+            //same as mark_p2p_read
+            let unread_count = ChatMessage::get_group_unread_count_after(&conn, gid, uid, &cursor)?;
+            ChatGroupsUid::mark_read(&conn, gid, uid, &cursor, clamp_count(unread_count))?;
+
+            Ok(ChatGroupsUid::get_group_user_info(&conn, gid, uid)?.unread_count as i64)
+        })
+    }
+
+    //read from master, a position reported just before must be seen.
+    fn kingdom_read_cursor(&self, uid: i64, kingdom_id: i64) -> Result<MessageCursor> {
+        let conn = self.master()?;
+        Ok(ChatKingdomRead::get_read_cursor(&conn, uid, kingdom_id)?)
+    }
+
+    fn mark_kingdom_read(&self, uid: i64, kingdom_id: i64, cursor: MessageCursor) -> Result<()> {
+        let conn = self.master()?;
+        Ok(ChatKingdomRead::mark_read(&conn, uid, kingdom_id, &cursor)?)
+    }
+
+    fn user_unread_total(&self, uid: i64) -> Result<i64> {
+        let conn = self.master()?;
+        Ok(ChatGroupsUid::get_unread_total(&conn, uid)? + ChatUserUnreadCount::get_unread_total(&conn, uid)?)
+    }
+
+    fn is_blacklisted(&self, uid: i64, black_uid: i64) -> Result<bool> {
+        let conn = self.slave()?;
+        Ok(Blacklist::find_user_black_list_exists(&conn, uid, black_uid)?)
+    }
+
+    fn kingdom_id(&self, uid: i64) -> Result<i64> {
+        let conn = self.slave()?;
+        Ok(User::get_kingdom_id(&conn, uid)?)
+    }
+
+    fn server_kingdom_id(&self, server_number: i32) -> Result<i64> {
+        let conn = self.slave()?;
+        Ok(Server::get_server_id(&conn, server_number)?)
+    }
+
+    fn server_uids(&self, server_number: i32, uids: Vec<i64>) -> Result<Vec<i64>> {
+        let conn = self.slave()?;
+        Ok(User::get_online_uuids_by_server_id(&conn, server_number, uids)?)
+    }
+
+    fn chat_user(&self, uid: i64) -> Result<FrontDisplayChatUser> {
+        let conn = self.slave()?;
+        Ok(User::get_front_display_chat_user_info(&conn, uid)?)
+    }
+}
+
+#[derive(Default)]
+struct MemoryData {
+    users: HashMap<i64, FrontDisplayChatUser>,
+    servers: HashMap<i32, i64>,
+    groups: HashMap<i64, ChatGroup>,
+    group_members: Vec<ChatGroupsUid>,
+    blacklists: HashSet<(i64, i64)>,
+    messages: Vec<ChatMessage>,
+    user_unread_counts: Vec<ChatUserUnreadCount>,
+    //(uid, kingdom_id) -> read cursor
+    kingdom_reads: HashMap<(i64, i64), MessageCursor>,
+}
+
+//single process store for tests and local development, follows the postgres queries and is lost on exit.
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<MemoryData>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    //server_number is what users and kingdom messages refer to, sid is the kingdom id.
+    pub fn add_server(&self, server_number: i32, sid: i64) {
+        self.data.lock().unwrap().servers.insert(server_number, sid);
+    }
+
+    pub fn add_user(&self, user: FrontDisplayChatUser) {
+        self.data.lock().unwrap().users.insert(user.uuid, user);
+    }
+
+    pub fn add_group(&self, gid: i64, group_name: &str, owner: i64, members: &[i64]) {
+        let now = Utc::now().naive_local();
+        let mut data = self.data.lock().unwrap();
+
+        data.groups.insert(
+            gid,
+            ChatGroup {
+                gid,
+                group_name: group_name.to_string(),
+                group_thumbnail: String::new(),
+                uuid: owner,
+                person_count: members.len() as i16,
+                modify_time: now,
+                created_time: now,
+            },
+        );
+
+        for uid in members.iter() {
+            data.group_members.push(ChatGroupsUid {
+                guid: next_id(),
+                gid,
+                uuid: *uid,
+                latest_timestamp: 0,
+                unread_count: 0,
+                modify_time: now,
+                created_time: now,
+                latest_mid: 0,
+            });
+        }
+    }
+
+    pub fn add_blacklist(&self, uid: i64, black_uid: i64) {
+        self.data.lock().unwrap().blacklists.insert((uid, black_uid));
+    }
+}
+
+impl MemoryData {
+    fn chat_user(&self, uid: i64) -> Result<FrontDisplayChatUser> {
+        self.users
+            .get(&uid)
+            .cloned()
+            .ok_or_else(|| anyhow!("user not found:{}", uid))
+    }
+
+    fn group(&self, gid: i64) -> Result<&ChatGroup> {
+        self.groups.get(&gid).ok_or_else(|| anyhow!("group not found:{}", gid))
+    }
+
+    //same ordering and bounds as ChatMessage::load_page.
+    fn page<F>(&self, filter: F, page: &PageQuery) -> (Vec<ChatMessage>, bool)
+    where
+        F: Fn(&ChatMessage) -> bool,
+    {
+        let mut msgs = self.messages.iter().filter(|m| filter(m)).cloned().collect::<Vec<_>>();
+        msgs.sort_by_key(MessageCursor::of);
+
+        //one row past the limit tells whether more exist, a side without a limit is not read.
+        let (older_limit, newer_limit) = page.side_limits();
+        let take = |limit: i64| if limit > 0 { limit as usize + 1 } else { 0 };
+        let older = msgs
+            .iter()
+            .rev()
+            .filter(|m| match page.cursor {
+                Some(c) => MessageCursor::of(m) < c,
+                None => true,
+            })
+            .take(take(older_limit))
+            .cloned()
+            .collect::<Vec<_>>();
+        let newer = msgs
+            .iter()
+            .filter(|m| match page.cursor {
+                Some(c) if page.mode == PageMode::Around => MessageCursor::of(m) >= c,
+                Some(c) => MessageCursor::of(m) > c,
+                None => true,
+            })
+            .take(take(newer_limit))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        page.assemble(older, newer)
+    }
+
+    fn count_after<F>(&self, filter: F, cursor: &MessageCursor) -> i64
+    where
+        F: Fn(&ChatMessage) -> bool,
+    {
+        self.messages
+            .iter()
+            .filter(|m| filter(m) && MessageCursor::of(m) > *cursor)
+            .count() as i64
+    }
+
+    //the newest message of filter after cursor.
+    fn latest_after<F>(&self, filter: F, cursor: &MessageCursor) -> Option<ChatMessage>
+    where
+        F: Fn(&ChatMessage) -> bool,
+    {
+        self.messages
+            .iter()
+            .filter(|m| filter(m) && MessageCursor::of(m) > *cursor)
+            .max_by_key(|m| MessageCursor::of(m))
+            .cloned()
+    }
+
+    fn kingdom_display(&self, msg: ChatMessage) -> Result<FrontDisplayKingdomChatMessage> {
+        Ok(FrontDisplayKingdomChatMessage {
+            mid: msg.mid,
+            send_user: self.chat_user(msg.send_id)?,
+            to_id: msg.to_id,
+            content: msg.content,
+            created_timestamp: msg.created_timestamp,
+            kind: msg.kind,
+            msg_type: msg.msg_type,
+        })
+    }
+
+    fn group_display(&self, msg: ChatMessage) -> Result<FrontDisplayGroupChatMessage> {
+        let group = self.group(msg.to_id)?;
+
+        Ok(FrontDisplayGroupChatMessage {
+            mid: msg.mid,
+            send_user: self.chat_user(msg.send_id)?,
+            gid: group.gid,
+            group_name: group.group_name.clone(),
+            group_thumbnail: group.group_thumbnail.clone(),
+            content: msg.content,
+            created_timestamp: msg.created_timestamp,
+            kind: msg.kind,
+            msg_type: msg.msg_type,
+        })
+    }
+
+    fn p2p_display(&self, msg: ChatMessage) -> Result<FrontDisplayP2pChatMessage> {
+        Ok(FrontDisplayP2pChatMessage {
+            mid: msg.mid,
+            send_user: self.chat_user(msg.send_id)?,
+            dst_user: self.chat_user(msg.to_id)?,
+            content: msg.content,
+            created_timestamp: msg.created_timestamp,
+            kind: msg.kind,
+            msg_type: msg.msg_type,
+        })
+    }
+}
+
+impl ChatStore for MemoryStore {
+    fn add_message(
+        &self,
+        send_id: i64,
+        to_id: i64,
+        content: String,
+        kind: i16,
+        msg_type: i16,
+    ) -> Result<ChatMessage> {
+        let now = Utc::now();
+        let mut data = self.data.lock().unwrap();
+
+        let msg = ChatMessage {
+            mid: next_id(),
+            send_id,
+            to_id,
+            content,
+            created_timestamp: now.timestamp_millis(),
+            kind,
+            modify_time: now.naive_local(),
+            created_time: now.naive_local(),
+            msg_type,
+        };
+
+        match kind {
+            2 => {
+                for member in data.group_members.iter_mut() {
+                    if member.gid == to_id && member.uuid != send_id {
+                        member.unread_count += 1;
+                    }
+                }
+            }
+            3 => match data
+                .user_unread_counts
+                .iter_mut()
+                .find(|c| c.uuid_s == to_id && c.uuid_d == send_id)
+            {
+                Some(c) => {
+                    c.unread_count += 1;
+                    c.modify_time = now.naive_local();
+                }
+                None => data.user_unread_counts.push(ChatUserUnreadCount {
+                    ucid: next_id(),
+                    uuid_s: to_id,
+                    uuid_d: send_id,
+                    latest_timestamp: 0,
+                    unread_count: 1,
+                    modify_time: now.naive_local(),
+                    created_time: now.naive_local(),
+                    latest_mid: 0,
+                }),
+            },
+            _ => {}
+        }
+
+        data.messages.push(msg.clone());
+
+        Ok(msg)
+    }
+
+    fn kingdom_display_message(&self, msg: ChatMessage) -> Result<FrontDisplayKingdomChatMessage> {
+        self.data.lock().unwrap().kingdom_display(msg)
+    }
+
+    fn group_display_message(&self, msg: ChatMessage) -> Result<FrontDisplayGroupChatMessage> {
+        self.data.lock().unwrap().group_display(msg)
+    }
+
+    fn p2p_display_message(&self, msg: ChatMessage) -> Result<FrontDisplayP2pChatMessage> {
+        self.data.lock().unwrap().p2p_display(msg)
+    }
+
+    fn kingdom_messages(&self, kingdom_id: i64, page: PageQuery) -> Result<KingdomMessagePage> {
+        let data = self.data.lock().unwrap();
+        let (msgs, has_more) = data.page(|m| m.to_id == kingdom_id, &page);
+        let (before_cursor, after_cursor) = page.cursors(&msgs);
+
+        //like postgres, messages whose sender is unknown are skipped.
+        Ok(KingdomMessagePage {
+            messages: msgs.into_iter().filter_map(|m| data.kingdom_display(m).ok()).collect(),
+            has_more: has_more as u8,
+            before_cursor,
+            after_cursor,
+        })
+    }
+
+    fn group_messages(&self, gid: i64, page: PageQuery) -> Result<GroupMessagePage> {
+        let data = self.data.lock().unwrap();
+        let (msgs, has_more) = data.page(|m| m.to_id == gid, &page);
+        let (before_cursor, after_cursor) = page.cursors(&msgs);
+
+        Ok(GroupMessagePage {
+            messages: msgs.into_iter().map(|m| data.group_display(m)).collect::<Result<_>>()?,
+            has_more: has_more as u8,
+            before_cursor,
+            after_cursor,
+        })
+    }
+
+    fn p2p_messages(&self, send_id: i64, to_id: i64, page: PageQuery) -> Result<P2pMessagePage> {
+        let data = self.data.lock().unwrap();
+        let peers = [send_id, to_id];
+        let (msgs, has_more) = data.page(|m| peers.contains(&m.send_id) && peers.contains(&m.to_id), &page);
+        let (before_cursor, after_cursor) = page.cursors(&msgs);
+
+        Ok(P2pMessagePage {
+            messages: msgs.into_iter().map(|m| data.p2p_display(m)).collect::<Result<_>>()?,
+            has_more: has_more as u8,
+            before_cursor,
+            after_cursor,
+        })
+    }
+
+    fn kingdom_unread_count_after(&self, kingdom_id: i64, cursor: MessageCursor) -> Result<i64> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .count_after(|m| m.to_id == kingdom_id && m.kind == 1, &cursor))
+    }
+
+    fn kingdom_unread_count_and_latest_message(
+        &self,
+        kingdom_id: i64,
+        cursor: MessageCursor,
+    ) -> Result<(i64, FrontDisplayKingdomChatMessage)> {
+        let data = self.data.lock().unwrap();
+        let unread = data.count_after(|m| m.to_id == kingdom_id && m.kind == 1, &cursor);
+
+        let latest = data
+            .messages
+            .iter()
+            .filter(|m| m.to_id == kingdom_id && m.kind == 1 && MessageCursor::of(m) > cursor)
+            .max_by_key(|m| MessageCursor::of(m))
+            .cloned();
+        let latest = match latest {
+            Some(v) => v,
+            None => return Err(anyhow!("failed get kingdom latest message.")),
+        };
+
+        Ok((unread, data.kingdom_display(latest)?))
+    }
+
+    fn group_unread_count(&self, gid: i64, uid: i64) -> Result<i64> {
+        let data = self.data.lock().unwrap();
+        let cursor = data
+            .group_members
+            .iter()
+            .find(|m| m.gid == gid && m.uuid == uid)
+            .map(|m| m.read_cursor())
+            .ok_or_else(|| anyhow!("not a member of group:{}", gid))?;
+
+        Ok(data.count_after(|m| m.to_id == gid && m.kind == 2 && m.send_id != uid, &cursor))
+    }
+
+    fn group_unread_count_and_latest_message(
+        &self,
+        gid: i64,
+        uid: i64,
+        cursor: MessageCursor,
+    ) -> Result<(i64, FrontDisplayGroupChatMessage)> {
+        let data = self.data.lock().unwrap();
+        let filter = |m: &ChatMessage| m.to_id == gid && m.kind == 2 && m.send_id != uid;
+
+        let latest = match data.latest_after(filter, &cursor) {
+            Some(v) => v,
+            None => return Err(anyhow!("no group message after the read position.")),
+        };
+
+        Ok((data.count_after(filter, &cursor), data.group_display(latest)?))
+    }
+
+    fn user_unread_counts(&self, uid: i64) -> Result<Vec<FrontDisplayChatUserUnreadCount>> {
+        let data = self.data.lock().unwrap();
+        let mut res = Vec::new();
+
+        for count in data
+            .user_unread_counts
+            .iter()
+            .filter(|c| c.uuid_s == uid && c.unread_count > 0)
+        {
+            //the latest p2p message count.uuid_d sent after the last read.
+            let latest = data.latest_after(
+                |m| m.send_id == count.uuid_d && m.to_id == count.uuid_s && m.kind == 3,
+                &count.read_cursor(),
+            );
+            let latest = match latest {
+                Some(v) => v,
+                None => return Err(anyhow!("failed get p2p latest message.")),
+            };
+
+            res.push(FrontDisplayChatUserUnreadCount {
+                sender: data.chat_user(count.uuid_d)?,
+                receiver: data.chat_user(count.uuid_s)?,
+                latest_timestamp: count.latest_timestamp,
+                unread_count: count.unread_count,
+                latest_msg: FrontDisplayP2pChatMessageCount {
+                    mid: latest.mid,
+                    content: latest.content,
+                    created_timestamp: latest.created_timestamp,
+                    kind: latest.kind,
+                    msg_type: latest.msg_type,
+                },
+            });
+        }
+
+        Ok(res)
+    }
+
+    fn user_unread_count(&self, uid: i64, send_uid: i64) -> Result<i16> {
+        self.data
+            .lock()
+            .unwrap()
+            .user_unread_counts
+            .iter()
+            .find(|c| c.uuid_s == uid && c.uuid_d == send_uid)
+            .map(|c| c.unread_count)
+            .ok_or_else(|| anyhow!("unread count not found."))
+    }
+
+    fn unread_groups(&self, uid: i64) -> Result<Vec<ChatGroupsUid>> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .group_members
+            .iter()
+            .filter(|m| m.uuid == uid && m.unread_count > 0)
+            .cloned()
+            .collect())
+    }
+
+    fn group_member_uids(&self, gid: i64) -> Result<Vec<i64>> {
+        let data = self.data.lock().unwrap();
+
+        Ok(data
+            .group_members
+            .iter()
+            .filter(|m| m.gid == gid && data.users.contains_key(&m.uuid))
+            .map(|m| m.uuid)
+            .collect())
+    }
+
+    fn mark_p2p_read(&self, uid: i64, send_uid: i64, cursor: MessageCursor) -> Result<i64> {
+        let now = Utc::now();
+        let mut data = self.data.lock().unwrap();
+        let unread_count = data.count_after(|m| m.send_id == send_uid && m.to_id == uid && m.kind == 3, &cursor);
+
+        match data
+            .user_unread_counts
+            .iter_mut()
+            .find(|c| c.uuid_s == uid && c.uuid_d == send_uid)
+        {
+            Some(count) => {
+                if count.read_cursor() <= cursor {
+                    count.latest_timestamp = cursor.created_timestamp;
+                    count.latest_mid = cursor.mid;
+                    count.unread_count = clamp_count(unread_count);
+                    count.modify_time = now.naive_local();
+                }
+                Ok(count.unread_count as i64)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn mark_group_read(&self, gid: i64, uid: i64, cursor: MessageCursor) -> Result<i64> {
+        let now = Utc::now();
+        let mut data = self.data.lock().unwrap();
+        let unread_count = data.count_after(|m| m.to_id == gid && m.kind == 2 && m.send_id != uid, &cursor);
+
+        let member = data
+            .group_members
+            .iter_mut()
+            .find(|m| m.gid == gid && m.uuid == uid)
+            .ok_or_else(|| anyhow!("not a member of group:{}", gid))?;
+
+        if member.read_cursor() <= cursor {
+            member.latest_timestamp = cursor.created_timestamp;
+            member.latest_mid = cursor.mid;
+            member.unread_count = clamp_count(unread_count);
+            member.modify_time = now.naive_local();
+        }
+
+        Ok(member.unread_count as i64)
+    }
+
+    fn kingdom_read_cursor(&self, uid: i64, kingdom_id: i64) -> Result<MessageCursor> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .kingdom_reads
+            .get(&(uid, kingdom_id))
+            .copied()
+            .unwrap_or(MessageCursor { created_timestamp: 0, mid: 0 }))
+    }
+
+    fn mark_kingdom_read(&self, uid: i64, kingdom_id: i64, cursor: MessageCursor) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+        let latest = data
+            .kingdom_reads
+            .entry((uid, kingdom_id))
+            .or_insert(MessageCursor { created_timestamp: 0, mid: 0 });
+        *latest = (*latest).max(cursor);
+
+        Ok(())
+    }
+
+    fn user_unread_total(&self, uid: i64) -> Result<i64> {
+        let data = self.data.lock().unwrap();
+
+        let groups = data
+            .group_members
+            .iter()
+            .filter(|m| m.uuid == uid)
+            .map(|m| m.unread_count as i64)
+            .sum::<i64>();
+        let p2ps = data
+            .user_unread_counts
+            .iter()
+            .filter(|c| c.uuid_s == uid)
+            .map(|c| c.unread_count as i64)
+            .sum::<i64>();
+
+        Ok(groups + p2ps)
+    }
+
+    fn is_blacklisted(&self, uid: i64, black_uid: i64) -> Result<bool> {
+        Ok(self.data.lock().unwrap().blacklists.contains(&(uid, black_uid)))
+    }
+
+    fn kingdom_id(&self, uid: i64) -> Result<i64> {
+        let data = self.data.lock().unwrap();
+        let server_id = data.chat_user(uid)?.server_id;
+
+        data.servers
+            .get(&server_id)
+            .copied()
+            .ok_or_else(|| anyhow!("server not found:{}", server_id))
+    }
+
+    fn server_kingdom_id(&self, server_number: i32) -> Result<i64> {
+        self.data
+            .lock()
+            .unwrap()
+            .servers
+            .get(&server_number)
+            .copied()
+            .ok_or_else(|| anyhow!("server not found:{}", server_number))
+    }
+
+    fn server_uids(&self, server_number: i32, uids: Vec<i64>) -> Result<Vec<i64>> {
+        let data = self.data.lock().unwrap();
+
+        Ok(uids
+            .into_iter()
+            .filter(|uid| data.users.get(uid).map(|u| u.server_id) == Some(server_number))
+            .collect())
+    }
+
+    fn chat_user(&self, uid: i64) -> Result<FrontDisplayChatUser> {
+        self.data.lock().unwrap().chat_user(uid)
+    }
+}
@@ -1,15 +1,51 @@
+use crate::default_log_pre;
+use anyhow::{anyhow, Result};
+use function_name::named;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Semaphore};
+use tracing::info;
 
+pub const DEFAULT_BLOCKING_THREADS: usize = 16;
+pub const DEFAULT_BLOCKING_QUEUE_CAPACITY: usize = 1024;
+
+//bounded executor for blocking storage and redis work, keeps the async reactor free.
+//at most size jobs run and queue_capacity more wait, further callers wait for a slot.
 pub struct ThreadPool {
     _workers: Vec<Worker>,
-    sender: mpsc::Sender<Job>,
+    sender: Mutex<mpsc::Sender<Job>>,
+    slots: Arc<Semaphore>,
+    metrics: Arc<ThreadPoolMetrics>,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+//queued counts jobs submitted but not started yet, including callers waiting for a slot.
+#[derive(Debug, Default)]
+pub struct ThreadPoolMetrics {
+    pub queued: AtomicU64,
+    pub running: AtomicU64,
+    pub completed: AtomicU64,
+    pub panicked: AtomicU64,
+    pub total_wait_micros: AtomicU64,
+    pub total_run_micros: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadPoolSnapshot {
+    pub queued: u64,
+    pub running: u64,
+    pub completed: u64,
+    pub panicked: u64,
+    pub avg_wait_micros: u64,
+    pub avg_run_micros: u64,
+}
+
 impl ThreadPool {
-    pub fn new(size: usize) -> Self {
+    pub fn new(size: usize, queue_capacity: usize) -> Self {
         assert!(size > 0);
 
         let mut workers = Vec::with_capacity(size);
@@ -23,17 +59,79 @@ impl ThreadPool {
 
         ThreadPool {
             _workers: workers,
-            sender,
+            sender: Mutex::new(sender),
+            slots: Arc::new(Semaphore::new(size + queue_capacity)),
+            metrics: Arc::new(ThreadPoolMetrics::default()),
         }
     }
 
-    pub fn excute<F>(&self, f: F)
+    //run f on a worker thread, waits for a queue slot first when the pool is saturated.
+    pub async fn run<F, T>(&self, f: F) -> Result<T>
     where
-        F: FnOnce() + Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
     {
-        let job = Box::new(f);
+        let metrics = self.metrics.clone();
+        let submitted = Instant::now();
+        metrics.queued.fetch_add(1, Ordering::Relaxed);
+
+        let permit = self.slots.clone().acquire_owned().await;
+        let (tx, rx) = oneshot::channel();
+
+        let job = Box::new(move || {
+            let started = Instant::now();
+            metrics.queued.fetch_sub(1, Ordering::Relaxed);
+            metrics.running.fetch_add(1, Ordering::Relaxed);
+            metrics
+                .total_wait_micros
+                .fetch_add(started.duration_since(submitted).as_micros() as u64, Ordering::Relaxed);
+
+            let res = catch_unwind(AssertUnwindSafe(f));
+
+            metrics.running.fetch_sub(1, Ordering::Relaxed);
+            metrics.completed.fetch_add(1, Ordering::Relaxed);
+            metrics
+                .total_run_micros
+                .fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+            drop(permit);
+
+            match res {
+                Ok(v) => {
+                    let _ = tx.send(v);
+                }
+                Err(_) => {
+                    metrics.panicked.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+
+        if self.sender.lock().unwrap().send(job).is_err() {
+            self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(anyhow!("thread pool stopped."));
+        }
 
-        self.sender.send(job).unwrap();
+        rx.await.map_err(|_| anyhow!("blocking job panicked."))
+    }
+
+    pub fn metrics(&self) -> ThreadPoolSnapshot {
+        let m = &self.metrics;
+        let completed = m.completed.load(Ordering::Relaxed);
+        let avg = |total: &AtomicU64| total.load(Ordering::Relaxed).checked_div(completed).unwrap_or(0);
+
+        ThreadPoolSnapshot {
+            queued: m.queued.load(Ordering::Relaxed),
+            running: m.running.load(Ordering::Relaxed),
+            completed,
+            panicked: m.panicked.load(Ordering::Relaxed),
+            avg_wait_micros: avg(&m.total_wait_micros),
+            avg_run_micros: avg(&m.total_run_micros),
+        }
+    }
+}
+
+impl Default for ThreadPool {
+    fn default() -> Self {
+        ThreadPool::new(DEFAULT_BLOCKING_THREADS, DEFAULT_BLOCKING_QUEUE_CAPACITY)
     }
 }
 
@@ -45,7 +143,11 @@ struct Worker {
 impl Worker {
     fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
         let thread = thread::spawn(move || loop {
-            let job = receiver.lock().unwrap().recv().unwrap();
+            //the pool was dropped.
+            let job = match receiver.lock().unwrap().recv() {
+                Ok(v) => v,
+                Err(_) => return,
+            };
             job();
         });
 
@@ -55,3 +157,18 @@ impl Worker {
         }
     }
 }
+
+#[named]
+pub async fn log_thread_pool_metrics(pool: Arc<ThreadPool>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+
+        let m = pool.metrics();
+        info!(
+            "{}\tthread pool metrics queued:{}\trunning:{}\tcompleted:{}\tpanicked:{}\tavg_wait_micros:{}\tavg_run_micros:{}",
+            default_log_pre!("", ""), m.queued, m.running, m.completed, m.panicked, m.avg_wait_micros, m.avg_run_micros
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io::Cursor;
+use v1::models::chat_messages::FrontDisplayP2pChatMessageCount;
+use v1::models::user::FrontDisplayChatUser;
+use v1::utils::common::{decode_item, LengthOverflow, LengthPrefix};
+use v1::{
+    BinaryDecode, BinaryEncode, ChatMessageUnReadCount, FrontDisplayChatUserUnreadCount,
+    GroupUnReadCountMsg, KingdomUnReadCountMsg,
+};
+
+#[derive(Debug, PartialEq, BinaryEncode, BinaryDecode)]
+struct Scalars {
+    muted: bool,
+    last_seen: Option<i64>,
+    nickname: Option<String>,
+    tags: Vec<String>,
+    members: Vec<i64>,
+    level: u8,
+}
+
+fn scalars() -> Scalars {
+    Scalars {
+        muted: true,
+        last_seen: Some(1_600_000_000_000),
+        nickname: Some("kay".to_string()),
+        tags: vec!["a".to_string(), "".to_string(), "guild".to_string()],
+        members: vec![1001, -2, 0],
+        level: 7,
+    }
+}
+
+fn user() -> FrontDisplayChatUser {
+    FrontDisplayChatUser {
+        uuid: 17,
+        uid: 9,
+        name: "kay".to_string(),
+        avatar: "".to_string(),
+        server_id: 3,
+        action_points: 40,
+    }
+}
+
+//the layout the hand-written impl produced.
+fn user_bytes() -> Vec<u8> {
+    let mut fields = Vec::new();
+    fields.write_i64::<LittleEndian>(17).unwrap();
+    fields.write_i32::<LittleEndian>(9).unwrap();
+    fields.write_i16::<LittleEndian>(3).unwrap();
+    fields.extend_from_slice(b"kay");
+    fields.write_i16::<LittleEndian>(0).unwrap();
+    fields.write_i32::<LittleEndian>(3).unwrap();
+    fields.write_i32::<LittleEndian>(40).unwrap();
+
+    let mut item = Vec::new();
+    item.write_i16::<LittleEndian>(fields.len() as i16).unwrap();
+    item.extend(fields);
+    item
+}
+
+#[test]
+fn derived_encode_matches_manual_layout() {
+    assert_eq!(user().encode().unwrap(), user_bytes());
+
+    let none = KingdomUnReadCountMsg {
+        unread_count: 5,
+        latest_message: None,
+    };
+    let mut fields = Vec::new();
+    fields.write_i32::<LittleEndian>(5).unwrap();
+    fields.write_i16::<LittleEndian>(0).unwrap();
+    let mut expected = Vec::new();
+    expected.write_i16::<LittleEndian>(fields.len() as i16).unwrap();
+    expected.extend(fields);
+
+    assert_eq!(none.encode().unwrap(), expected);
+}
+
+#[test]
+fn derived_decode_round_trip() {
+    let bytes = user_bytes();
+    let mut cursor = Cursor::new(bytes.as_slice());
+    let decoded: FrontDisplayChatUser = decode_item(&mut cursor, &bytes, LengthPrefix::I16).unwrap();
+
+    assert_eq!(decoded.uuid, 17);
+    assert_eq!(decoded.name, "kay");
+    assert_eq!(decoded.avatar, "");
+    assert_eq!(decoded.action_points, 40);
+    assert_eq!(cursor.position() as usize, bytes.len());
+
+    let kingdom = KingdomUnReadCountMsg {
+        unread_count: 2,
+        latest_message: None,
+    };
+    let groups: Vec<GroupUnReadCountMsg> = vec![];
+    let mut bytes = kingdom.encode().unwrap();
+    bytes.extend(groups.encode().unwrap());
+
+    let mut cursor = Cursor::new(bytes.as_slice());
+    let decoded: KingdomUnReadCountMsg = decode_item(&mut cursor, &bytes, LengthPrefix::I16).unwrap();
+    let decoded_groups: Vec<GroupUnReadCountMsg> = BinaryDecode::decode(&mut cursor, &bytes).unwrap();
+
+    assert_eq!(decoded.unread_count, 2);
+    assert!(decoded.latest_message.is_none());
+    assert!(decoded_groups.is_empty());
+}
+
+#[test]
+fn large_payload_needs_u32_lengths() {
+    let mut large = user();
+    large.name = "n".repeat(40_000);
+
+    let err = large.encode().unwrap_err();
+    assert!(err.downcast_ref::<LengthOverflow>().is_some());
+
+    let bytes = large.encode_with(LengthPrefix::U32).unwrap();
+    let mut cursor = Cursor::new(bytes.as_slice());
+    let decoded: FrontDisplayChatUser = decode_item(&mut cursor, &bytes, LengthPrefix::U32).unwrap();
+
+    assert_eq!(decoded.name.len(), 40_000);
+    assert_eq!(decoded.action_points, 40);
+    assert_eq!(cursor.position() as usize, bytes.len());
+}
+
+#[test]
+fn derived_scalar_fields_round_trip() {
+    let values = [
+        scalars(),
+        Scalars {
+            muted: false,
+            last_seen: None,
+            nickname: None,
+            tags: vec![],
+            members: vec![],
+            level: 0,
+        },
+    ];
+
+    for prefix in [LengthPrefix::I16, LengthPrefix::U32].iter() {
+        for value in values.iter() {
+            let bytes = value.encode_with(*prefix).unwrap();
+            let mut cursor = Cursor::new(bytes.as_slice());
+            let decoded: Scalars = decode_item(&mut cursor, &bytes, *prefix).unwrap();
+
+            assert_eq!(&decoded, value);
+            assert_eq!(cursor.position() as usize, bytes.len());
+        }
+    }
+}
+
+#[test]
+fn derived_bool_rejects_other_bytes() {
+    let mut bytes = scalars().encode().unwrap();
+    //first field byte after the item length
+    bytes[2] = 2;
+
+    let mut cursor = Cursor::new(bytes.as_slice());
+    let decoded: anyhow::Result<Scalars> = decode_item(&mut cursor, &bytes, LengthPrefix::I16);
+    assert!(decoded.is_err());
+}
+
+#[test]
+fn unread_count_response_round_trip() {
+    let counts = ChatMessageUnReadCount {
+        kingdom: KingdomUnReadCountMsg {
+            unread_count: 3,
+            latest_message: None,
+        },
+        groups: vec![],
+        p2ps: vec![FrontDisplayChatUserUnreadCount {
+            sender: user(),
+            receiver: user(),
+            latest_timestamp: 1_600_000_000_000,
+            unread_count: 4,
+            latest_msg: FrontDisplayP2pChatMessageCount {
+                mid: 11,
+                content: "hi".to_string(),
+                created_timestamp: 1_600_000_000_000,
+                kind: 3,
+                msg_type: 1,
+            },
+        }],
+    };
+
+    let bytes = counts.encode().unwrap();
+    let mut cursor = Cursor::new(bytes.as_slice());
+    let decoded: ChatMessageUnReadCount = decode_item(&mut cursor, &bytes, LengthPrefix::I16).unwrap();
+
+    assert_eq!(decoded.kingdom.unread_count, 3);
+    assert!(decoded.groups.is_empty());
+    assert_eq!(decoded.p2ps.len(), 1);
+    assert_eq!(decoded.p2ps[0].sender.name, "kay");
+    assert_eq!(decoded.p2ps[0].unread_count, 4);
+    assert_eq!(decoded.p2ps[0].latest_msg.content, "hi");
+    assert_eq!(cursor.position() as usize, bytes.len());
+}
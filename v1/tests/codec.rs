@@ -0,0 +1,131 @@
+use byteorder::{ByteOrder, LittleEndian};
+use bytes::BytesMut;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+use tokio_util::codec::{Decoder, Encoder};
+use v1::{FrameError, MessageCodec, MessageStateCode, RequestFrame, ResponseCodec, ResponseContext};
+
+fn frame(code: u16, body: &[u8]) -> RequestFrame {
+    RequestFrame {
+        code,
+        version: 1,
+        key_id: 1,
+        session_id: 782348283,
+        signature: [7; 32],
+        timestamp: 1599561154,
+        body: body.to_vec(),
+    }
+}
+
+fn encode(codec: &mut MessageCodec, frame: RequestFrame) -> BytesMut {
+    let mut buf = BytesMut::new();
+    codec.encode(frame, &mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn decode_split_frame() {
+    let mut codec = MessageCodec::default();
+    let bytes = encode(&mut codec, frame(2002, b"hello"));
+
+    let mut buf = BytesMut::new();
+    buf.extend_from_slice(&bytes[..10]);
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+
+    buf.extend_from_slice(&bytes[10..bytes.len() - 1]);
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+
+    buf.extend_from_slice(&bytes[bytes.len() - 1..]);
+    let decoded = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(decoded.code, 2002);
+    assert_eq!(decoded.key_id, 1);
+    assert_eq!(decoded.session_id, 782348283);
+    assert_eq!(decoded.signature, [7; 32]);
+    assert_eq!(decoded.timestamp, 1599561154);
+    assert_eq!(decoded.body, b"hello".to_vec());
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn decode_coalesced_frames() {
+    let mut codec = MessageCodec::default();
+    let mut buf = encode(&mut codec, frame(2001, b"first"));
+    buf.extend_from_slice(&encode(&mut codec, frame(2004, b"")));
+
+    let first = codec.decode(&mut buf).unwrap().unwrap();
+    let second = codec.decode(&mut buf).unwrap().unwrap();
+
+    assert_eq!(first.code, 2001);
+    assert_eq!(first.body, b"first".to_vec());
+    assert_eq!(second.code, 2004);
+    assert!(second.body.is_empty());
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+}
+
+#[test]
+fn decode_rejects_oversized_frame() {
+    let mut buf = encode(&mut MessageCodec::default(), frame(2002, &[0; 64]));
+    let mut codec = MessageCodec::new(32);
+
+    match codec.decode(&mut buf) {
+        Err(FrameError::TooLarge { code, length, max, .. }) => {
+            assert_eq!(code, 2002);
+            assert_eq!(length, 64);
+            assert_eq!(max, 32);
+        }
+        other => panic!("unexpected decode result:{:?}", other),
+    }
+}
+
+#[test]
+fn decode_response_frames() {
+    let mut buf = BytesMut::new();
+    buf.extend_from_slice(
+        &ResponseContext::get_bincode(2009, 782348283, MessageStateCode::Ok, "pong", "").unwrap(),
+    );
+    buf.extend_from_slice(
+        &ResponseContext::get_bincode(2003, 7, MessageStateCode::NotFound, "unknown route.", "").unwrap(),
+    );
+    let mut codec = ResponseCodec::default();
+
+    let mut partial = buf.split_to(10);
+    assert!(codec.decode(&mut partial).unwrap().is_none());
+    partial.unsplit(buf);
+
+    let first = codec.decode(&mut partial).unwrap().unwrap();
+    let second = codec.decode(&mut partial).unwrap().unwrap();
+
+    assert_eq!(first.code, 2009);
+    assert_eq!(first.session_id, 782348283);
+    assert_eq!(first.state, MessageStateCode::Ok as u16);
+    //msg length, "pong", then the empty body string
+    assert_eq!(first.body, vec![4, 0, b'p', b'o', b'n', b'g', 0, 0]);
+    assert_eq!(second.code, 2003);
+    assert_eq!(second.state, MessageStateCode::NotFound as u16);
+    assert!(partial.is_empty());
+}
+
+#[test]
+fn response_inflating_past_its_length_rejected() {
+    //4 bytes announced, a megabyte of zeros inside
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&vec![0; 1 << 20]).unwrap();
+    let zlib_body = encoder.finish().unwrap();
+
+    let mut header = [0u8; 20];
+    LittleEndian::write_u16(&mut header[0..2], 2009);
+    LittleEndian::write_u64(&mut header[2..10], 7);
+    LittleEndian::write_u16(&mut header[10..12], MessageStateCode::Ok as u16);
+    LittleEndian::write_u32(&mut header[12..16], 4);
+    LittleEndian::write_u32(&mut header[16..20], zlib_body.len() as u32);
+
+    let mut buf = BytesMut::new();
+    buf.extend_from_slice(&header);
+    buf.extend_from_slice(&zlib_body);
+
+    match ResponseCodec::default().decode(&mut buf) {
+        Err(FrameError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+        other => panic!("unexpected decode {:?}", other.map(|f| f.map(|f| f.body.len()))),
+    }
+}
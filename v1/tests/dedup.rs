@@ -0,0 +1,89 @@
+use v1::models::chat_messages::FrontDisplayP2pChatMessageCount;
+use std::sync::Arc;
+use std::time::Duration;
+use v1::{MemorySendDedup, RedisPoolConfig, RedisSendDedup, SendDedup, SendState};
+
+fn stored(mid: i64) -> FrontDisplayP2pChatMessageCount {
+    FrontDisplayP2pChatMessageCount {
+        mid,
+        content: "hello".to_string(),
+        created_timestamp: 1599731395000,
+        kind: 3,
+        msg_type: 1,
+    }
+}
+
+#[test]
+fn repeated_id_returns_stored_message() {
+    let dedup = MemorySendDedup::new(600, 100);
+    let now = 1_600_000_000;
+
+    assert!(matches!(dedup.begin_at(now, 7, "c-1"), SendState::New));
+    assert!(matches!(dedup.begin_at(now, 7, "c-1"), SendState::InFlight));
+
+    dedup.finish(7, "c-1", stored(42)).unwrap();
+    match dedup.begin_at(now + 1, 7, "c-1") {
+        SendState::Done(v) => assert_eq!(v.mid, 42),
+        v => panic!("unexpected state:{:?}", v),
+    }
+
+    //ids are per sender
+    assert!(matches!(dedup.begin_at(now, 8, "c-1"), SendState::New));
+}
+
+#[test]
+fn aborted_send_can_be_retried() {
+    let dedup = MemorySendDedup::new(600, 100);
+    let now = 1_600_000_000;
+
+    assert!(matches!(dedup.begin_at(now, 7, "c-1"), SendState::New));
+    dedup.abort(7, "c-1").unwrap();
+    assert!(matches!(dedup.begin_at(now, 7, "c-1"), SendState::New));
+}
+
+#[test]
+fn ids_forgotten_outside_window_or_capacity() {
+    let dedup = MemorySendDedup::new(600, 2);
+    let now = 1_600_000_000;
+
+    dedup.begin_at(now, 7, "c-1");
+    dedup.finish(7, "c-1", stored(42)).unwrap();
+    assert!(matches!(dedup.begin_at(now + 601, 7, "c-1"), SendState::New));
+
+    dedup.begin_at(now + 601, 7, "c-2");
+    dedup.begin_at(now + 601, 7, "c-3");
+    //c-1 was evicted to make room
+    assert!(matches!(dedup.begin_at(now + 601, 7, "c-1"), SendState::New));
+}
+
+#[test]
+fn redis_dedup_reports_unreachable_redis() {
+    let mut config = RedisPoolConfig::new("redis://127.0.0.1:1/");
+    config.connect_timeout = Duration::from_millis(200);
+    let dedup = RedisSendDedup::new(Arc::new(config.build().unwrap()), 600);
+
+    //the caller sends without dedup instead of failing the send
+    assert!(dedup.begin(7, "c-1").is_err());
+}
+
+#[test]
+#[ignore = "needs a live redis, set REDIS_URL"]
+fn redis_dedup_shared_by_nodes() {
+    let pool = Arc::new(RedisPoolConfig::from_env().build().unwrap());
+    let node_a = RedisSendDedup::new(pool.clone(), 60);
+    let node_b = RedisSendDedup::new(pool, 60);
+    let id = format!("c-{}", std::process::id());
+
+    assert!(matches!(node_a.begin(7, &id).unwrap(), SendState::New));
+    assert!(matches!(node_b.begin(7, &id).unwrap(), SendState::InFlight));
+
+    node_a.finish(7, &id, stored(42)).unwrap();
+    match node_b.begin(7, &id).unwrap() {
+        SendState::Done(v) => assert_eq!(v.mid, 42),
+        v => panic!("unexpected state:{:?}", v),
+    }
+
+    node_a.abort(7, &id).unwrap();
+    assert!(matches!(node_b.begin(7, &id).unwrap(), SendState::New));
+    node_b.abort(7, &id).unwrap();
+}
@@ -0,0 +1,632 @@
+pub mod harness;
+
+use harness::{test_token, test_user, TestServer};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use v1::chat_system::request::UserUnreadCountRequest;
+use v1::client::ResponseError;
+use v1::models::chat_messages::FrontDisplayP2pChatMessageCount;
+use v1::utils::request::encode_body;
+use v1::{MessageCursor, MessageStateCode, PushChatMessage, RouterCode, UnreadBadge};
+
+fn state(e: anyhow::Error) -> u16 {
+    e.downcast_ref::<ResponseError>().expect("response error").state
+}
+
+#[tokio::test]
+async fn login_and_heartbeat() {
+    let server = TestServer::start().await;
+    let client = server.login(1001).await;
+
+    assert_eq!(client.uid(), 1001);
+    assert!(server.broker.is_online(1001));
+    client.heartbeat().await.unwrap();
+}
+
+#[tokio::test]
+async fn login_rejects_bad_token() {
+    let server = TestServer::start().await;
+    let client = server.client().await;
+
+    let e = client.login(1001, "token-1002").await.unwrap_err();
+
+    assert_eq!(state(e), MessageStateCode::GeneralError as u16);
+    assert!(!server.broker.is_online(1001));
+}
+
+#[tokio::test]
+async fn unauthenticated_request_rejected() {
+    let server = TestServer::start().await;
+    let client = server.client().await;
+
+    //heartbeat is served before login, history is not
+    client.heartbeat().await.unwrap();
+    let e = client.fetch_group_history(1, "", 10, 1).await.unwrap_err();
+
+    assert_eq!(state(e), MessageStateCode::GeneralError as u16);
+}
+
+#[tokio::test]
+async fn claimed_uid_must_match_session() {
+    let server = TestServer::start().await;
+    let client = server.login(1001).await;
+
+    let req = UserUnreadCountRequest { kingdom_read_timestamp: 0, uid: 1002 };
+    let body = encode_body(&req, client.length_prefix()).unwrap();
+    let resp = client.request(RouterCode::GetUserUnReadMessageCount, body).await.unwrap();
+
+    assert_eq!(resp.state, MessageStateCode::GeneralError as u16);
+    assert_eq!(resp.msg, "identity mismatch.");
+
+    //answered with an error state, so counted as an error
+    let (_, requests, errors, _) = server
+        .metrics
+        .snapshot()
+        .into_iter()
+        .find(|m| m.0 == RouterCode::GetUserUnReadMessageCount)
+        .unwrap();
+    assert_eq!((requests, errors), (1, 1));
+}
+
+#[tokio::test]
+async fn unknown_route_not_found() {
+    let server = TestServer::start().await;
+    let client = server.login(1001).await;
+
+    let resp = client.request(RouterCode::Unknown, Vec::new()).await.unwrap();
+
+    assert_eq!(resp.state, MessageStateCode::NotFound as u16);
+    assert_eq!(resp.msg, "unknown route.");
+}
+
+#[tokio::test]
+async fn disconnect_marks_user_offline() {
+    let server = TestServer::start().await;
+    let client = server.login(1001).await;
+    assert!(server.broker.is_online(1001));
+
+    drop(client);
+
+    for _ in 0..50 {
+        if !server.broker.is_online(1001) {
+            return;
+        }
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+    }
+    panic!("user still online after disconnect");
+}
+
+#[tokio::test]
+async fn user_online_while_another_node_holds_a_connection() {
+    let server = TestServer::start().await;
+    let node = server.join().await;
+
+    let first = server.login(1001).await;
+    //logging in again on a socket does not count a second connection
+    first.login(1001, &test_token(1001)).await.unwrap();
+    let second = node.login(1001).await;
+
+    drop(first);
+    tokio::time::delay_for(Duration::from_millis(200)).await;
+    assert!(server.broker.is_online(1001));
+
+    drop(second);
+    for _ in 0..50 {
+        if !server.broker.is_online(1001) {
+            return;
+        }
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+    }
+    panic!("user still online after every connection closed");
+}
+
+//users 1001 and 1002 play on server 7(kingdom 70) and share group 10.
+fn seed(server: &TestServer) {
+    server.store.add_server(7, 70);
+    server.store.add_user(test_user(1001, 7));
+    server.store.add_user(test_user(1002, 7));
+    server.store.add_group(10, "guild", 1001, &[1001, 1002]);
+}
+
+async fn next_push(pushes: &mut mpsc::UnboundedReceiver<PushChatMessage>) -> PushChatMessage {
+    timeout(Duration::from_secs(5), pushes.recv())
+        .await
+        .expect("push timeout")
+        .expect("push channel closed")
+}
+
+#[tokio::test]
+async fn group_message_pushed_and_counted_unread() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let member = server.login(1002).await;
+    let mut pushes = member.pushes().unwrap();
+
+    let sent = sender.send_message(2, 10, 1, "hello guild").await.unwrap();
+    assert_eq!(sent.content, "hello guild");
+
+    match next_push(&mut pushes).await {
+        PushChatMessage::Group(v) => {
+            assert_eq!(v.mid, sent.mid);
+            assert_eq!(v.gid, 10);
+            assert_eq!(v.group_name, "guild");
+            assert_eq!(v.send_user.uuid, 1001);
+        }
+        other => panic!("unexpected push {:?}", other),
+    }
+    member.ack_push(sent.mid).await.unwrap();
+
+    let unread = member.unread_counts(0).await.unwrap();
+    assert_eq!(unread.groups.len(), 1);
+    assert_eq!(unread.groups[0].unread_count, 1);
+    assert_eq!(unread.groups[0].latest_message.mid, sent.mid);
+    //the sender does not count its own message
+    assert!(sender.unread_counts(0).await.unwrap().groups.is_empty());
+
+    let history = member.fetch_group_history(10, "", 10, 1).await.unwrap().messages;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].content, "hello guild");
+
+    //reading the history clears the group unread count
+    assert!(member.unread_counts(0).await.unwrap().groups.is_empty());
+}
+
+#[tokio::test]
+async fn p2p_history_and_unread_count() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let peer = server.login(1002).await;
+    let mut pushes = peer.pushes().unwrap();
+
+    let first = sender.send_message(3, 1002, 1, "first").await.unwrap();
+    let second = sender.send_message(3, 1002, 1, "second").await.unwrap();
+
+    for mid in [first.mid, second.mid].iter() {
+        match next_push(&mut pushes).await {
+            PushChatMessage::P2p(v) => assert_eq!(v.mid, *mid),
+            other => panic!("unexpected push {:?}", other),
+        }
+    }
+
+    assert_eq!(peer.channel_unread_count(3, 1001).await.unwrap().unread_count, 2);
+    let unread = peer.unread_counts(0).await.unwrap();
+    assert_eq!(unread.p2ps.len(), 1);
+    assert_eq!(unread.p2ps[0].unread_count, 2);
+    assert_eq!(unread.p2ps[0].sender.uuid, 1001);
+
+    let history = peer.fetch_p2p_history(1001, "", 10, 0).await.unwrap().messages;
+    let contents = history.iter().map(|m| m.content.as_str()).collect::<Vec<_>>();
+    assert_eq!(contents, vec!["first", "second"]);
+
+    assert_eq!(peer.channel_unread_count(3, 1001).await.unwrap().unread_count, 0);
+    assert!(peer.unread_counts(0).await.unwrap().p2ps.is_empty());
+}
+
+#[tokio::test]
+async fn p2p_unread_latest_message_per_sender() {
+    let server = TestServer::start().await;
+    seed(&server);
+    server.store.add_user(test_user(1003, 7));
+    let first = server.login(1001).await;
+    let second = server.login(1003).await;
+    let peer = server.login(1002).await;
+
+    first.send_message(3, 1002, 1, "from 1001").await.unwrap();
+    second.send_message(3, 1002, 1, "from 1003").await.unwrap();
+
+    let mut p2ps = peer.unread_counts(0).await.unwrap().p2ps;
+    p2ps.sort_by_key(|c| c.sender.uuid);
+    let latest = p2ps
+        .iter()
+        .map(|c| (c.sender.uuid, c.latest_msg.content.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(latest, vec![(1001, "from 1001"), (1003, "from 1003")]);
+}
+
+#[tokio::test]
+async fn p2p_blacklisted_sender_rejected() {
+    let server = TestServer::start().await;
+    seed(&server);
+    server.store.add_blacklist(1002, 1001);
+    let sender = server.login(1001).await;
+
+    let e = sender.send_message(3, 1002, 1, "hello").await.unwrap_err();
+
+    assert_eq!(state(e), MessageStateCode::GeneralError as u16);
+    assert!(sender.fetch_p2p_history(1002, "", 10, 0).await.unwrap().messages.is_empty());
+}
+
+#[tokio::test]
+async fn kingdom_message_pushed_and_listed() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let member = server.login(1002).await;
+    let mut pushes = member.pushes().unwrap();
+
+    let sent = sender.send_message(1, 7, 1, "hello kingdom").await.unwrap();
+
+    match next_push(&mut pushes).await {
+        PushChatMessage::Kingdom(v) => {
+            assert_eq!(v.mid, sent.mid);
+            assert_eq!(v.to_id, 70);
+        }
+        other => panic!("unexpected push {:?}", other),
+    }
+
+    assert_eq!(member.channel_unread_count(1, 0).await.unwrap().unread_count, 1);
+    let unread = member.unread_counts(0).await.unwrap();
+    assert_eq!(unread.kingdom.unread_count, 1);
+
+    let history = member.fetch_kingdom_history("", 10, 1).await.unwrap().messages;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].send_user.uuid, 1001);
+}
+
+#[tokio::test]
+async fn p2p_history_pages_by_cursor() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let peer = server.login(1002).await;
+
+    //sent back to back, most share a millisecond
+    let mut mids = Vec::new();
+    for i in 0..5 {
+        mids.push(sender.send_message(3, 1002, 1, &format!("m{}", i)).await.unwrap().mid);
+    }
+
+    //newest first page, then walk back
+    let mut seen = Vec::new();
+    let mut page = peer.fetch_p2p_history(1001, "", 2, 1).await.unwrap();
+    loop {
+        assert!(page.messages.len() <= 2);
+        let mut older = page.messages.iter().map(|m| m.mid).collect::<Vec<_>>();
+        older.extend(seen);
+        seen = older;
+
+        if page.has_more == 0 {
+            break;
+        }
+        page = peer.fetch_p2p_history(1001, &page.before_cursor, 2, 1).await.unwrap();
+    }
+    assert_eq!(seen, mids);
+
+    //and forward from the oldest
+    let first = peer.fetch_p2p_history(1001, "", 3, 0).await.unwrap();
+    assert_eq!(first.has_more, 1);
+    let rest = peer.fetch_p2p_history(1001, &first.after_cursor, 3, 0).await.unwrap();
+    assert_eq!(rest.has_more, 0);
+    let forward = first.messages.iter().chain(rest.messages.iter()).map(|m| m.mid).collect::<Vec<_>>();
+    assert_eq!(forward, mids);
+
+    //around keeps the anchor and splits the limit
+    let anchor = peer.fetch_p2p_history(1001, &first.after_cursor, 1, 1).await.unwrap();
+    assert_eq!(anchor.messages[0].mid, mids[1]);
+    let around = peer.fetch_p2p_history(1001, &first.after_cursor, 3, 2).await.unwrap();
+    let around_mids = around.messages.iter().map(|m| m.mid).collect::<Vec<_>>();
+    assert_eq!(around_mids, vec![mids[1], mids[2], mids[3]]);
+    assert_eq!(around.has_more, 1);
+
+    //a bad cursor is rejected
+    let e = peer.fetch_p2p_history(1001, "zz", 3, 1).await.unwrap_err();
+    assert_eq!(state(e), MessageStateCode::GeneralError as u16);
+}
+
+#[tokio::test]
+async fn paging_forward_keeps_newer_unread() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let member = server.login(1002).await;
+
+    for i in 0..3 {
+        sender.send_message(2, 10, 1, &format!("g{}", i)).await.unwrap();
+        sender.send_message(3, 1002, 1, &format!("p{}", i)).await.unwrap();
+    }
+
+    //the oldest message only, two newer ones left
+    let first = member.fetch_group_history(10, "", 1, 0).await.unwrap();
+    assert_eq!(first.messages.len(), 1);
+    assert_eq!(member.unread_counts(0).await.unwrap().groups[0].unread_count, 2);
+    member.fetch_p2p_history(1001, "", 1, 0).await.unwrap();
+    assert_eq!(member.channel_unread_count(3, 1001).await.unwrap().unread_count, 2);
+
+    //the rest of the page clears it, an older page does not raise it again
+    member.fetch_group_history(10, &first.after_cursor, 2, 0).await.unwrap();
+    assert!(member.unread_counts(0).await.unwrap().groups.is_empty());
+    member.fetch_group_history(10, "", 1, 0).await.unwrap();
+    assert!(member.unread_counts(0).await.unwrap().groups.is_empty());
+
+    member.fetch_p2p_history(1001, "", 10, 1).await.unwrap();
+    assert_eq!(member.channel_unread_count(3, 1001).await.unwrap().unread_count, 0);
+}
+
+#[tokio::test]
+async fn v1_history_keeps_timestamp_layout() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let peer = server.login(1002).await;
+
+    let mut mids = Vec::new();
+    for i in 0..3 {
+        mids.push(sender.send_message(3, 1002, 1, &format!("m{}", i)).await.unwrap().mid);
+    }
+
+    //order 1 newest first, order 0 oldest first
+    let desc = peer.fetch_p2p_messages(1001, 0, 10, 1).await.unwrap();
+    assert_eq!(desc.iter().map(|m| m.mid).collect::<Vec<_>>(), vec![mids[2], mids[1], mids[0]]);
+    let asc = peer.fetch_p2p_messages(1001, 0, 2, 0).await.unwrap();
+    assert_eq!(asc.iter().map(|m| m.mid).collect::<Vec<_>>(), vec![mids[0], mids[1]]);
+
+    //the timestamp is exclusive
+    let after = peer.fetch_p2p_messages(1001, 1, 10, 0).await.unwrap();
+    assert_eq!(after.len(), 3);
+    let before = peer.fetch_p2p_messages(1001, desc[2].created_timestamp, 10, 1).await.unwrap();
+    assert!(before.is_empty());
+
+    assert_eq!(peer.fetch_kingdom_messages(0, 10, 1).await.unwrap().len(), 0);
+    assert_eq!(peer.fetch_group_messages(10, 0, 10, 1).await.unwrap().len(), 0);
+}
+
+#[tokio::test]
+async fn retried_send_stored_once() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let peer = server.login(1002).await;
+
+    let first = sender.send_message_with_id(3, 1002, 1, "hello", "c-1").await.unwrap();
+    let retry = sender.send_message_with_id(3, 1002, 1, "hello", "c-1").await.unwrap();
+    assert_eq!(retry.mid, first.mid);
+    assert_eq!(retry.created_timestamp, first.created_timestamp);
+
+    //a new id is a new message
+    let other = sender.send_message_with_id(3, 1002, 1, "hello", "c-2").await.unwrap();
+    assert_ne!(other.mid, first.mid);
+
+    //counted once per message
+    assert_eq!(peer.channel_unread_count(3, 1001).await.unwrap().unread_count, 2);
+    assert_eq!(peer.fetch_p2p_history(1001, "", 10, 0).await.unwrap().messages.len(), 2);
+}
+
+#[tokio::test]
+async fn retry_on_another_node_stored_once() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let other = server.join().await;
+    let sender = server.login(1001).await;
+    let peer = server.login(1002).await;
+
+    let first = sender.send_message_with_id(3, 1002, 1, "hello", "c-1").await.unwrap();
+    //the connection dropped and the retry was balanced to another node
+    let reconnected = other.login(1001).await;
+    let retry = reconnected.send_message_with_id(3, 1002, 1, "hello", "c-1").await.unwrap();
+
+    assert_eq!(retry.mid, first.mid);
+    assert_eq!(peer.channel_unread_count(3, 1001).await.unwrap().unread_count, 1);
+}
+
+async fn next_badge(badges: &mut mpsc::UnboundedReceiver<UnreadBadge>) -> UnreadBadge {
+    timeout(Duration::from_secs(5), badges.recv())
+        .await
+        .expect("badge timeout")
+        .expect("badge channel closed")
+}
+
+#[tokio::test]
+async fn mark_read_syncs_other_devices() {
+    let server = TestServer::start().await;
+    let node = server.join().await;
+    seed(&server);
+    let sender = node.login(1001).await;
+    //the same user on two nodes, tablet and phone
+    let tablet = node.login(1002).await;
+    let phone = server.login(1002).await;
+    let mut tablet_badges = tablet.badges().unwrap();
+    let mut phone_badges = phone.badges().unwrap();
+
+    let cursor = |m: &FrontDisplayP2pChatMessageCount| {
+        MessageCursor { created_timestamp: m.created_timestamp, mid: m.mid }.encode()
+    };
+    let mut sent = Vec::new();
+    for i in 0..3 {
+        sent.push(sender.send_message(3, 1002, 1, &format!("p{}", i)).await.unwrap());
+    }
+    let group_msg = sender.send_message(2, 10, 1, "hello guild").await.unwrap();
+
+    //reading the first of three p2p messages leaves two plus the group one
+    let badge = phone.mark_read(3, 1001, &cursor(&sent[0])).await.unwrap();
+    assert_eq!(badge.unread_count, 2);
+    assert_eq!(badge.total_unread, 3);
+
+    let pushed = next_badge(&mut tablet_badges).await;
+    assert_eq!(pushed.tid, 3);
+    assert_eq!(pushed.dst_id, 1001);
+    assert_eq!(pushed.read_cursor, cursor(&sent[0]));
+    assert_eq!(pushed.total_unread, 3);
+    assert_eq!(tablet.channel_unread_count(3, 1001).await.unwrap().unread_count, 2);
+
+    let badge = tablet.mark_read(2, 10, &cursor(&group_msg)).await.unwrap();
+    assert_eq!(badge.unread_count, 0);
+    assert_eq!(badge.total_unread, 2);
+    assert_eq!(next_badge(&mut phone_badges).await.total_unread, 2);
+
+    //going back does not unread anything
+    let badge = tablet.mark_read(3, 1001, &MessageCursor { created_timestamp: 0, mid: 1 }.encode()).await.unwrap();
+    assert_eq!(badge.unread_count, 2);
+
+    //the device that read gets its answer, not a push
+    assert!(timeout(Duration::from_millis(200), tablet_badges.recv()).await.is_err());
+
+    //not a member of the group
+    let e = sender.mark_read(2, 11, &cursor(&group_msg)).await.unwrap_err();
+    assert_eq!(state(e), MessageStateCode::GeneralError as u16);
+}
+
+#[tokio::test]
+async fn same_node_devices_each_served() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let phone = server.login(1002).await;
+    let tablet = server.client().await;
+    //a first request keeps the login frame apart from the phone's for the replay guard
+    tablet.heartbeat().await.unwrap();
+    tablet.login(1002, &test_token(1002)).await.unwrap();
+    let mut phone_pushes = phone.pushes().unwrap();
+    let mut tablet_pushes = tablet.pushes().unwrap();
+    let mut phone_badges = phone.badges().unwrap();
+    let mut tablet_badges = tablet.badges().unwrap();
+
+    let sent = sender.send_message(3, 1002, 1, "to both").await.unwrap();
+    for pushes in [&mut phone_pushes, &mut tablet_pushes].iter_mut() {
+        match next_push(pushes).await {
+            PushChatMessage::P2p(v) => assert_eq!(v.mid, sent.mid),
+            other => panic!("unexpected push {:?}", other),
+        }
+    }
+
+    let cursor = MessageCursor { created_timestamp: sent.created_timestamp, mid: sent.mid }.encode();
+    let badge = phone.mark_read(3, 1001, &cursor).await.unwrap();
+    assert_eq!(badge.unread_count, 0);
+    assert_eq!(next_badge(&mut tablet_badges).await.read_cursor, cursor);
+    assert!(timeout(Duration::from_millis(200), phone_badges.recv()).await.is_err());
+
+    //closing one device keeps the user online on the other
+    drop(tablet);
+    tokio::time::delay_for(Duration::from_millis(200)).await;
+    assert!(server.broker.is_online(1002));
+    let sent = sender.send_message(3, 1002, 1, "to the phone").await.unwrap();
+    match next_push(&mut phone_pushes).await {
+        PushChatMessage::P2p(v) => assert_eq!(v.mid, sent.mid),
+        other => panic!("unexpected push {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn kingdom_read_position_kept_by_server() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let phone = server.login(1002).await;
+
+    sender.send_message(1, 7, 1, "k0").await.unwrap();
+    sender.send_message(1, 7, 1, "k1").await.unwrap();
+    assert_eq!(phone.unread_counts(0).await.unwrap().kingdom.unread_count, 2);
+    assert_eq!(phone.fetch_kingdom_history("", 10, 1).await.unwrap().messages.len(), 2);
+
+    //a reinstalled app knows no timestamp, the server does
+    let node = server.join().await;
+    let reinstalled = node.login(1002).await;
+    assert_eq!(reinstalled.unread_counts(0).await.unwrap().kingdom.unread_count, 0);
+    assert_eq!(reinstalled.channel_unread_count(1, 0).await.unwrap().unread_count, 0);
+
+    let k2 = sender.send_message(1, 7, 1, "k2").await.unwrap();
+    assert_eq!(reinstalled.unread_counts(0).await.unwrap().kingdom.unread_count, 1);
+    let cursor = MessageCursor { created_timestamp: k2.created_timestamp, mid: k2.mid }.encode();
+    let badge = reinstalled.mark_read(1, 0, &cursor).await.unwrap();
+    assert_eq!(badge.dst_id, 70);
+    assert_eq!(badge.unread_count, 0);
+
+    //older clients still report their position, it never moves back
+    let k3 = sender.send_message(1, 7, 1, "k3").await.unwrap();
+    assert_eq!(reinstalled.unread_counts(1).await.unwrap().kingdom.unread_count, 1);
+    assert_eq!(reinstalled.channel_unread_count(1, k3.created_timestamp).await.unwrap().unread_count, 0);
+    assert_eq!(reinstalled.unread_counts(0).await.unwrap().kingdom.unread_count, 0);
+}
+
+#[tokio::test]
+async fn kingdom_read_position_keeps_same_millisecond_order() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let reader = server.login(1002).await;
+
+    let k0 = sender.send_message(1, 7, 1, "k0").await.unwrap();
+    //a position in k0's millisecond, right before it
+    let cursor = MessageCursor { created_timestamp: k0.created_timestamp, mid: k0.mid - 1 }.encode();
+
+    let badge = reader.mark_read(1, 0, &cursor).await.unwrap();
+    assert_eq!(badge.unread_count, 1);
+
+    let counts = reader.unread_counts(0).await.unwrap();
+    assert_eq!(counts.kingdom.unread_count, 1);
+    assert_eq!(counts.kingdom.latest_message.unwrap().mid, k0.mid);
+    assert_eq!(reader.channel_unread_count(1, 0).await.unwrap().unread_count, 1);
+
+    let cursor = MessageCursor { created_timestamp: k0.created_timestamp, mid: k0.mid }.encode();
+    assert_eq!(reader.mark_read(1, 0, &cursor).await.unwrap().unread_count, 0);
+    assert_eq!(reader.unread_counts(0).await.unwrap().kingdom.unread_count, 0);
+}
+
+#[tokio::test]
+async fn group_read_position_matches_every_count() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let sender = server.login(1001).await;
+    let reader = server.login(1002).await;
+
+    let g0 = sender.send_message(2, 10, 1, "g0").await.unwrap();
+    //the reader's own message is never unread
+    reader.send_message(2, 10, 1, "g1").await.unwrap();
+    //a position in g0's millisecond, right before it
+    let cursor = MessageCursor { created_timestamp: g0.created_timestamp, mid: g0.mid - 1 }.encode();
+
+    let badge = reader.mark_read(2, 10, &cursor).await.unwrap();
+    assert_eq!(badge.unread_count, 1);
+    assert_eq!(badge.total_unread, 1);
+
+    let counts = reader.unread_counts(0).await.unwrap();
+    assert_eq!(counts.groups.len(), 1);
+    assert_eq!(counts.groups[0].unread_count, 1);
+    assert_eq!(counts.groups[0].latest_message.mid, g0.mid);
+    assert_eq!(reader.channel_unread_count(2, 10).await.unwrap().unread_count, 1);
+
+    let cursor = MessageCursor { created_timestamp: g0.created_timestamp, mid: g0.mid }.encode();
+    assert_eq!(reader.mark_read(2, 10, &cursor).await.unwrap().unread_count, 0);
+    assert!(reader.unread_counts(0).await.unwrap().groups.is_empty());
+    assert_eq!(reader.channel_unread_count(2, 10).await.unwrap().unread_count, 0);
+}
+
+#[tokio::test]
+async fn push_ack_is_per_device() {
+    let server = TestServer::start().await;
+    seed(&server);
+    let pending = server.server.ctx.pending_pushes.clone();
+    let sender = server.login(1001).await;
+    let phone = server.login(1002).await;
+    let tablet = server.client().await;
+    tablet.heartbeat().await.unwrap();
+    tablet.login(1002, &test_token(1002)).await.unwrap();
+    let mut phone_pushes = phone.pushes().unwrap();
+    let mut tablet_pushes = tablet.pushes().unwrap();
+
+    let sent = sender.send_message(3, 1002, 1, "to both").await.unwrap();
+    next_push(&mut phone_pushes).await;
+    next_push(&mut tablet_pushes).await;
+    assert_eq!(pending.lock().await.waiting(1002, sent.mid).len(), 2);
+
+    //the phone's ack leaves the tablet waiting
+    phone.ack_push(sent.mid).await.unwrap();
+    assert_eq!(pending.lock().await.waiting(1002, sent.mid).len(), 1);
+
+    //a closed connection takes what it did not ack with it
+    let other = sender.send_message(3, 1002, 1, "second").await.unwrap();
+    next_push(&mut tablet_pushes).await;
+    drop(tablet);
+    for _ in 0..50 {
+        if pending.lock().await.waiting(1002, sent.mid).is_empty() {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+    }
+    let pending = pending.lock().await;
+    assert!(pending.waiting(1002, sent.mid).is_empty());
+    assert_eq!(pending.waiting(1002, other.mid).len(), 1);
+    assert_eq!(pending.len(), 1);
+}
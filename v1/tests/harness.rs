@@ -0,0 +1,135 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use v1::chat_system::push::PendingPushMap;
+use v1::client::{ChatClient, ClientConfig};
+use v1::models::user::FrontDisplayChatUser;
+use v1::server::{ChatServer, ChatServerConfig, ServerContext};
+use v1::utils::middleware::DEFAULT_RATE_LIMIT_PER_SECOND;
+use v1::{
+    build_routers, Clients, LocalBroker, MemorySendDedup, MemoryStore, PendingPushes, ReplayGuard, RouterMetrics,
+    SigningKey, SigningKeys, ThreadPool, TokenVerifier,
+};
+
+const KEY_ID: u16 = 1;
+const KEY: &str = "F9B14CEC-60B6-810F-1FF7-8BAE688466AC";
+
+//every user logs in with the token "token-<uid>".
+pub fn test_token(uid: u64) -> String {
+    format!("token-{}", uid)
+}
+
+struct TestTokenVerifier;
+
+impl TokenVerifier for TestTokenVerifier {
+    fn verify(&self, uid: u64, token: &str) -> Result<()> {
+        if token == test_token(uid) {
+            Ok(())
+        } else {
+            Err(anyhow!("invalid token."))
+        }
+    }
+}
+
+fn signing_key() -> SigningKey {
+    SigningKey {
+        key_id: KEY_ID,
+        secret: KEY.to_string(),
+        expires_at: None,
+    }
+}
+
+//a chat user playing on server_id.
+pub fn test_user(uid: u64, server_id: i32) -> FrontDisplayChatUser {
+    FrontDisplayChatUser {
+        uuid: uid as i64,
+        uid: uid as i32,
+        name: format!("user-{}", uid),
+        avatar: String::new(),
+        server_id,
+        action_points: 0,
+    }
+}
+
+//in-process server on an ephemeral port, no redis or postgres needed.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub broker: Arc<LocalBroker>,
+    pub store: Arc<MemoryStore>,
+    pub send_dedup: Arc<MemorySendDedup>,
+    pub metrics: Arc<RouterMetrics>,
+    pub server: ChatServer,
+}
+
+impl TestServer {
+    pub async fn start() -> TestServer {
+        TestServer::start_with(
+            Arc::new(LocalBroker::new()),
+            Arc::new(MemoryStore::new()),
+            Arc::new(MemorySendDedup::new(600, 10_000)),
+        )
+        .await
+    }
+
+    //another node of the same cluster, sharing the broker, store and send dedup.
+    pub async fn join(&self) -> TestServer {
+        TestServer::start_with(self.broker.clone(), self.store.clone(), self.send_dedup.clone()).await
+    }
+
+    async fn start_with(
+        broker: Arc<LocalBroker>,
+        store: Arc<MemoryStore>,
+        send_dedup: Arc<MemorySendDedup>,
+    ) -> TestServer {
+        let metrics = Arc::new(RouterMetrics::default());
+        let ctx = Arc::new(ServerContext::new(
+            build_routers(metrics.clone(), DEFAULT_RATE_LIMIT_PER_SECOND),
+            Clients::new(Mutex::new(HashMap::new())),
+            PendingPushes::new(Mutex::new(PendingPushMap::new(256, 10_000))),
+            store.clone(),
+            Arc::new(ThreadPool::new(4, 64)),
+            Arc::new(TestTokenVerifier),
+            broker.clone(),
+            SigningKeys::new(vec![signing_key()]),
+            ReplayGuard::new(300, 10_000, 1_000),
+            send_dedup.clone(),
+            v1::DEFAULT_MAX_FRAME_LENGTH,
+            Duration::from_secs(30),
+        ));
+
+        let config = ChatServerConfig {
+            tcp_addr: Some("127.0.0.1:0".parse().unwrap()),
+            tls: None,
+            ws_addr: None,
+            push_ack_timeout: Duration::from_secs(10),
+            push_max_attempts: 5,
+        };
+        let server = ChatServer::start(ctx, config).await.unwrap();
+
+        TestServer {
+            addr: server.tcp_addr.unwrap(),
+            broker,
+            store,
+            send_dedup,
+            metrics,
+            server,
+        }
+    }
+
+    pub async fn client(&self) -> ChatClient {
+        let mut config = ClientConfig::new(signing_key());
+        config.request_timeout = Duration::from_secs(5);
+
+        ChatClient::connect(self.addr, config).await.unwrap()
+    }
+
+    pub async fn login(&self, uid: u64) -> ChatClient {
+        let client = self.client().await;
+        client.login(uid, &test_token(uid)).await.unwrap();
+
+        client
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use v1::chat_system::push::PendingPushMap;
+use v1::models::user::FrontDisplayChatUser;
+use v1::{FrontDisplayP2pChatMessage, PushChatMessage};
+
+fn user(uid: i32) -> FrontDisplayChatUser {
+    FrontDisplayChatUser {
+        uuid: uid as i64,
+        uid,
+        name: "kay".to_string(),
+        avatar: "".to_string(),
+        server_id: 7,
+        action_points: 0,
+    }
+}
+
+fn push(mid: i64) -> PushChatMessage {
+    PushChatMessage::P2p(FrontDisplayP2pChatMessage {
+        mid,
+        send_user: user(1001),
+        dst_user: user(1002),
+        content: "hi".to_string(),
+        created_timestamp: 1599561154000,
+        kind: 3,
+        msg_type: 1,
+    })
+}
+
+fn conns(ids: &[u64]) -> HashSet<u64> {
+    ids.iter().copied().collect()
+}
+
+#[test]
+fn ack_removes_one_connection() {
+    let mut pending = PendingPushMap::new(16, 16);
+    pending.track(1002, &push(1), conns(&[1, 2]));
+
+    assert!(pending.ack(1002, 1, 1));
+    assert!(!pending.ack(1002, 1, 1));
+    assert_eq!(pending.waiting(1002, 1), vec![2]);
+
+    //only the connection that did not ack is resent to
+    let resend = pending.due(Duration::from_secs(0), 5);
+    assert_eq!(resend.len(), 1);
+    assert_eq!(resend[0].1, vec![2]);
+
+    assert!(pending.ack(1002, 2, 1));
+    assert!(pending.is_empty());
+}
+
+#[test]
+fn closed_connection_forgets_its_pushes() {
+    let mut pending = PendingPushMap::new(16, 16);
+    pending.track(1002, &push(1), conns(&[1, 2]));
+    pending.track(1002, &push(2), conns(&[2]));
+
+    pending.remove_connection(1002, 2);
+
+    assert_eq!(pending.waiting(1002, 1), vec![1]);
+    assert!(pending.waiting(1002, 2).is_empty());
+    assert_eq!(pending.len(), 1);
+}
+
+#[test]
+fn pending_pushes_bounded() {
+    let mut pending = PendingPushMap::new(2, 3);
+
+    //the oldest push of a user makes room
+    for mid in 1..=3 {
+        assert!(pending.track(1002, &push(mid), conns(&[1])));
+    }
+    assert!(pending.waiting(1002, 1).is_empty());
+    assert_eq!(pending.len(), 2);
+
+    //past the total limit pushes go out untracked
+    assert!(pending.track(1003, &push(4), conns(&[2])));
+    assert!(!pending.track(1004, &push(5), conns(&[3])));
+    assert!(pending.waiting(1004, 5).is_empty());
+    assert_eq!(pending.len(), 3);
+}
+
+#[test]
+fn resent_until_max_attempts() {
+    let mut pending = PendingPushMap::new(16, 16);
+    pending.track(1002, &push(1), conns(&[1]));
+
+    for _ in 1..5 {
+        assert_eq!(pending.due(Duration::from_secs(0), 5).len(), 1);
+    }
+    assert!(pending.due(Duration::from_secs(0), 5).is_empty());
+    assert!(pending.is_empty());
+}
@@ -0,0 +1,39 @@
+use r2d2::ManageConnection;
+use std::time::{Duration, Instant};
+use v1::utils::redis_db::RedisConnectionManager;
+use v1::RedisPoolConfig;
+
+#[test]
+fn pool_builds_without_redis_and_times_out_on_checkout() {
+    let mut config = RedisPoolConfig::new("redis://127.0.0.1:1/");
+    config.max_size = 2;
+    config.connect_timeout = Duration::from_millis(200);
+
+    //nothing listens there, building must not connect
+    let pool = config.build().unwrap();
+
+    let start = Instant::now();
+    assert!(pool.get().is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn connect_to_unreachable_host_times_out() {
+    //a blackholed address never answers the syn, only the connect timeout ends the attempt
+    let manager = RedisConnectionManager::new(
+        "redis://10.255.255.1:6379/",
+        Duration::from_millis(200),
+        Some(Duration::from_millis(200)),
+    )
+    .unwrap();
+
+    //a proxy in front of the network may answer instead, either way connect returns in time
+    let start = Instant::now();
+    let _ = manager.connect();
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn invalid_url_rejected() {
+    assert!(RedisPoolConfig::new("not a redis url").build().is_err());
+}
@@ -0,0 +1,169 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use v1::chat_system::request::{
+    KingdomMessageContentRequest, KingdomMessagePageRequest, MarkReadRequest, SendMessageRequest,
+    MAX_PAGE_LIMIT,
+};
+use v1::utils::common::LengthPrefix;
+use v1::utils::request::{encode_body, RequestBody};
+use v1::{MessageCursor, PageMode};
+
+fn send_message_body(content: &[u8], content_length: u16) -> Vec<u8> {
+    let mut body = vec![];
+    body.write_u64::<LittleEndian>(3455115140489977330).unwrap();
+    body.write_u8(1).unwrap();
+    body.write_u64::<LittleEndian>(1001).unwrap();
+    body.write_u16::<LittleEndian>(1).unwrap();
+    body.write_u16::<LittleEndian>(content_length).unwrap();
+    body.extend_from_slice(content);
+    body
+}
+
+#[test]
+fn parse_send_message() {
+    let req = SendMessageRequest::parse(&send_message_body(b"hello", 5), LengthPrefix::I16).unwrap();
+
+    assert_eq!(req.uid, 3455115140489977330);
+    assert_eq!(req.tid, 1);
+    assert_eq!(req.dst_id, 1001);
+    assert_eq!(req.msg_type, 1);
+    assert_eq!(req.content, "hello");
+    //bodies without a client message id still parse
+    assert_eq!(req.client_msg_id, "");
+
+    let mut body = send_message_body(b"hello", 5);
+    body.write_i16::<LittleEndian>(3).unwrap();
+    body.extend_from_slice(b"c-1");
+    let req = SendMessageRequest::parse(&body, LengthPrefix::I16).unwrap();
+    assert_eq!(req.content, "hello");
+    assert_eq!(req.client_msg_id, "c-1");
+}
+
+#[test]
+fn reject_malformed_send_message() {
+    //content shorter than its declared length
+    assert!(SendMessageRequest::parse(&send_message_body(b"hi", 5), LengthPrefix::I16).is_err());
+    //not utf8
+    assert!(SendMessageRequest::parse(&send_message_body(&[0xff, 0xfe], 2), LengthPrefix::I16).is_err());
+    //empty content
+    assert!(SendMessageRequest::parse(&send_message_body(b"", 0), LengthPrefix::I16).is_err());
+    //truncated header
+    assert!(SendMessageRequest::parse(&[1, 2, 3], LengthPrefix::I16).is_err());
+    //client message id shorter than its declared length
+    let mut body = send_message_body(b"hello", 5);
+    body.write_i16::<LittleEndian>(8).unwrap();
+    body.extend_from_slice(b"c-1");
+    assert!(SendMessageRequest::parse(&body, LengthPrefix::I16).is_err());
+}
+
+#[test]
+fn validate_order_request() {
+    let body = |limit: i16, order: i16| {
+        let mut body = vec![];
+        body.write_i64::<LittleEndian>(0).unwrap();
+        body.write_i16::<LittleEndian>(limit).unwrap();
+        body.write_i16::<LittleEndian>(order).unwrap();
+        body.write_i64::<LittleEndian>(8331054938119228637).unwrap();
+        body
+    };
+
+    assert!(KingdomMessageContentRequest::parse(&body(10, 1), LengthPrefix::I16).is_ok());
+    assert!(KingdomMessageContentRequest::parse(&body(0, 1), LengthPrefix::I16).is_err());
+    assert!(KingdomMessageContentRequest::parse(&body(10, 2), LengthPrefix::I16).is_err());
+    //missing uid used to silently default
+    assert!(KingdomMessageContentRequest::parse(&body(10, 1)[..12], LengthPrefix::I16).is_err());
+}
+
+#[test]
+fn validate_page_request() {
+    //CURSOR_PAGE_VERSION bodies use u32 lengths.
+    let body = |cursor: &str, limit: i16, mode: i16| {
+        let mut body = vec![];
+        body.write_u32::<LittleEndian>(cursor.len() as u32).unwrap();
+        body.extend_from_slice(cursor.as_bytes());
+        body.write_i16::<LittleEndian>(limit).unwrap();
+        body.write_i16::<LittleEndian>(mode).unwrap();
+        body.write_i64::<LittleEndian>(8331054938119228637).unwrap();
+        body
+    };
+    let parse = |body: &[u8]| KingdomMessagePageRequest::parse(body, LengthPrefix::U32);
+    let cursor = MessageCursor { created_timestamp: 1599731395000, mid: 42 };
+    let encoded = cursor.encode();
+
+    let req = parse(&body(&encoded, 80, 2)).unwrap();
+    let page = req.page();
+    assert_eq!(page.cursor, Some(cursor));
+    assert_eq!(page.mode, PageMode::Around);
+    assert_eq!(page.limit, MAX_PAGE_LIMIT as i64);
+    assert!(parse(&body("", 10, 1)).is_ok());
+    assert!(parse(&body("", 0, 1)).is_err());
+    assert!(parse(&body("", 10, 3)).is_err());
+    //around needs a position
+    assert!(parse(&body("", 10, 2)).is_err());
+    assert!(parse(&body("not a cursor", 10, 1)).is_err());
+    assert!(parse(&body("", 10, 1)[..8]).is_err());
+}
+
+#[test]
+fn validate_mark_read_request() {
+    let cursor = MessageCursor { created_timestamp: 1599731395000, mid: 42 }.encode();
+    let parse = |tid: i16, dst_id: i64, cursor: &str| {
+        let req = MarkReadRequest { tid, dst_id, cursor: cursor.to_string(), uid: 1001 };
+        MarkReadRequest::parse(&encode_body(&req, LengthPrefix::I16).unwrap(), LengthPrefix::I16)
+    };
+
+    assert!(parse(3, 1002, &cursor).is_ok());
+    //the kingdom comes from the user
+    assert!(parse(1, 0, &cursor).is_ok());
+    assert!(parse(2, 0, &cursor).is_err());
+    assert!(parse(4, 10, &cursor).is_err());
+    //a read position is required
+    assert!(parse(2, 10, "").is_err());
+    assert!(parse(2, 10, "not a cursor").is_err());
+}
+
+#[test]
+fn encoded_request_parses_back() {
+    for prefix in [LengthPrefix::I16, LengthPrefix::U32].iter() {
+        let req = SendMessageRequest {
+            uid: 3455115140489977330,
+            tid: 3,
+            dst_id: 1001,
+            msg_type: 1,
+            content: "hello".to_string(),
+            client_msg_id: "c-1".to_string(),
+        };
+        let body = encode_body(&req, *prefix).unwrap();
+        let parsed = SendMessageRequest::parse(&body, *prefix).unwrap();
+
+        assert_eq!(parsed.dst_id, 1001);
+        assert_eq!(parsed.content, "hello");
+        assert_eq!(parsed.client_msg_id, "c-1");
+    }
+
+    let req = KingdomMessageContentRequest {
+        timestamp: 1599731395,
+        limit: 10,
+        order: 1,
+        uid: 8331054938119228637,
+    };
+    let body = encode_body(&req, LengthPrefix::I16).unwrap();
+    let parsed = KingdomMessageContentRequest::parse(&body, LengthPrefix::I16).unwrap();
+
+    assert_eq!(body.len(), 20);
+    assert_eq!(parsed.timestamp, 1599731395);
+    assert_eq!(parsed.uid, 8331054938119228637);
+
+    let cursor = MessageCursor { created_timestamp: 1599731395000, mid: 42 };
+    let req = KingdomMessagePageRequest {
+        cursor: cursor.encode(),
+        limit: 10,
+        mode: 1,
+        uid: 8331054938119228637,
+    };
+    let body = encode_body(&req, LengthPrefix::U32).unwrap();
+    let parsed = KingdomMessagePageRequest::parse(&body, LengthPrefix::U32).unwrap();
+
+    assert_eq!(body.len(), 48);
+    assert_eq!(MessageCursor::parse(&parsed.cursor).unwrap(), Some(cursor));
+    assert_eq!(parsed.uid, 8331054938119228637);
+}
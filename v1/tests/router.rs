@@ -0,0 +1,28 @@
+use std::sync::Arc;
+use v1::{build_routers, RouterCode, RouterMetrics};
+
+#[test]
+fn unknown_code_is_not_aliased() {
+    assert_eq!(RouterCode::from_u16(2001), RouterCode::ConnectionState);
+    assert_eq!(RouterCode::from_u16(2999), RouterCode::Unknown);
+    assert_eq!(RouterCode::from_u16(0), RouterCode::Unknown);
+}
+
+#[test]
+fn call_falls_back_to_default_version() {
+    let routers = build_routers(Arc::new(RouterMetrics::default()), 50);
+
+    assert!(routers.call(RouterCode::SendMessage, 1).is_ok());
+    assert!(routers.call(RouterCode::SendMessage, 7).is_ok());
+    assert!(routers.call(RouterCode::Unknown, 1).is_err());
+}
+
+#[test]
+fn rate_limit_window_per_connection() {
+    let limiter = v1::utils::middleware::RateLimitMiddleware::new(2);
+
+    assert!(limiter.allow(1));
+    assert!(limiter.allow(1));
+    assert!(!limiter.allow(1));
+    assert!(limiter.allow(2));
+}
@@ -0,0 +1,104 @@
+use chrono::Utc;
+use v1::{ReplayError, ReplayGuard, SigningKey, SigningKeys};
+
+fn keys() -> SigningKeys {
+    SigningKeys::new(vec![
+        SigningKey {
+            key_id: 2,
+            secret: "current-secret".to_string(),
+            expires_at: None,
+        },
+        SigningKey {
+            key_id: 1,
+            secret: "previous-secret".to_string(),
+            expires_at: Some(Utc::now().timestamp() + 3600),
+        },
+        SigningKey {
+            key_id: 3,
+            secret: "retired-secret".to_string(),
+            expires_at: Some(Utc::now().timestamp() - 1),
+        },
+    ])
+}
+
+#[test]
+fn verify_current_and_previous_key() {
+    let keys = keys();
+
+    for key_id in [1, 2].iter() {
+        let sign = keys.sign(*key_id, 2002, 1, 7, 1599561154, b"body").unwrap();
+        assert!(keys.verify(*key_id, &sign, 2002, 1, 7, 1599561154, b"body").is_ok());
+    }
+}
+
+#[test]
+fn reject_tampered_or_expired_signature() {
+    let keys = keys();
+    let sign = keys.sign(2, 2002, 1, 7, 1599561154, b"body").unwrap();
+
+    assert!(keys.verify(2, &sign, 2002, 1, 7, 1599561154, b"other").is_err());
+    assert!(keys.verify(2, &sign, 2007, 1, 7, 1599561154, b"body").is_err());
+    assert!(keys.verify(1, &sign, 2002, 1, 7, 1599561154, b"body").is_err());
+    assert!(keys.sign(3, 2002, 1, 7, 1599561154, b"body").is_err());
+    assert!(keys.verify(9, &sign, 2002, 1, 7, 1599561154, b"body").is_err());
+}
+
+#[test]
+fn reject_changed_version_or_key_id() {
+    let keys = SigningKeys::new(vec![
+        SigningKey {
+            key_id: 2,
+            secret: "shared-secret".to_string(),
+            expires_at: None,
+        },
+        SigningKey {
+            key_id: 4,
+            secret: "shared-secret".to_string(),
+            expires_at: None,
+        },
+    ]);
+    let sign = keys.sign(2, 2002, 1, 7, 1599561154, b"body").unwrap();
+
+    assert!(keys.verify(2, &sign, 2002, 1, 7, 1599561154, b"body").is_ok());
+    //version 2 would read the body with u32 lengths
+    assert!(keys.verify(2, &sign, 2002, 2, 7, 1599561154, b"body").is_err());
+    assert!(keys.verify(4, &sign, 2002, 1, 7, 1599561154, b"body").is_err());
+}
+
+#[test]
+fn reject_stale_and_replayed_requests() {
+    let guard = ReplayGuard::new(300, 16, 8);
+    let now = 1599561154;
+    let sign = [1; 32];
+
+    assert!(guard.check_at(now, 7, now - 10, &sign).is_ok());
+    assert_eq!(guard.check_at(now, 7, now - 10, &sign), Err(ReplayError::Replayed));
+    assert!(guard.check_at(now, 8, now - 10, &sign).is_ok());
+    assert!(matches!(
+        guard.check_at(now, 7, now - 301, &[2; 32]),
+        Err(ReplayError::Expired { .. })
+    ));
+}
+
+#[test]
+fn full_cache_keeps_in_window_frames() {
+    let guard = ReplayGuard::new(300, 4, 2);
+    let now = 1599561154;
+
+    //fill one session, then the whole cache
+    assert!(guard.check_at(now, 1, now - 10, &[1; 32]).is_ok());
+    assert!(guard.check_at(now, 1, now - 10, &[2; 32]).is_ok());
+    assert_eq!(guard.check_at(now, 1, now - 10, &[3; 32]), Err(ReplayError::CacheFull));
+    assert!(guard.check_at(now, 2, now - 10, &[1; 32]).is_ok());
+    assert!(guard.check_at(now, 3, now - 10, &[1; 32]).is_ok());
+    assert_eq!(guard.check_at(now, 4, now - 10, &[1; 32]), Err(ReplayError::CacheFull));
+
+    //nothing was forgotten to make room
+    assert_eq!(guard.check_at(now, 1, now - 10, &[1; 32]), Err(ReplayError::Replayed));
+    assert_eq!(guard.check_at(now, 2, now - 10, &[1; 32]), Err(ReplayError::Replayed));
+
+    //once the window moved past them the keys make room again
+    let later = now + 300;
+    assert!(guard.check_at(later, 1, later, &[3; 32]).is_ok());
+    assert!(guard.check_at(later, 4, later, &[1; 32]).is_ok());
+}
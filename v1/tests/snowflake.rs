@@ -0,0 +1,63 @@
+use chrono::Utc;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+use v1::utils::snowflake::{id_timestamp_millis, id_worker_id, MAX_WORKER_ID};
+use v1::Snowflake;
+
+#[test]
+fn ids_increase_monotonically() {
+    let generator = Snowflake::new();
+
+    let mut last = generator.next_id();
+    //well past one millisecond worth of sequence
+    for _ in 0..10_000 {
+        let id = generator.next_id();
+        assert!(id > last);
+        last = id;
+    }
+}
+
+#[test]
+fn ids_unique_across_threads() {
+    let generator = Arc::new(Snowflake::with_worker_id(3).unwrap());
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let generator = generator.clone();
+            thread::spawn(move || (0..5_000).map(|_| generator.next_id()).collect::<Vec<_>>())
+        })
+        .collect();
+
+    let mut seen = HashSet::new();
+    for h in handles {
+        for id in h.join().unwrap() {
+            assert!(seen.insert(id));
+        }
+    }
+    assert_eq!(seen.len(), 40_000);
+}
+
+#[test]
+fn id_carries_worker_id_and_timestamp() {
+    let generator = Snowflake::with_worker_id(MAX_WORKER_ID).unwrap();
+
+    let before = Utc::now().timestamp_millis();
+    let id = generator.next_id();
+    let after = Utc::now().timestamp_millis();
+
+    assert!(id > 0);
+    assert_eq!(id_worker_id(id), MAX_WORKER_ID);
+    //may borrow a millisecond ahead when the sequence runs out
+    let ts = id_timestamp_millis(id);
+    assert!(ts >= before && ts <= after + 1);
+}
+
+#[test]
+fn out_of_range_worker_id_rejected() {
+    assert!(Snowflake::with_worker_id(MAX_WORKER_ID + 1).is_err());
+
+    let generator = Snowflake::with_worker_id(5).unwrap();
+    assert!(generator.set_worker_id(u16::MAX).is_err());
+    assert_eq!(generator.worker_id(), 5);
+}
@@ -0,0 +1,63 @@
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use v1::ThreadPool;
+
+#[tokio::test]
+async fn run_returns_job_result() {
+    let pool = ThreadPool::new(2, 4);
+
+    assert_eq!(pool.run(|| 40 + 2).await.unwrap(), 42);
+
+    let m = pool.metrics();
+    assert_eq!(m.completed, 1);
+    assert_eq!(m.queued, 0);
+    assert_eq!(m.running, 0);
+}
+
+#[tokio::test]
+async fn panicking_job_does_not_kill_worker() {
+    let pool = ThreadPool::new(1, 1);
+
+    let e = pool.run(|| -> u32 { panic!("boom") }).await.unwrap_err();
+    assert_eq!(e.to_string(), "blocking job panicked.");
+
+    //the single worker still serves jobs
+    assert_eq!(pool.run(|| 1).await.unwrap(), 1);
+    assert_eq!(pool.metrics().panicked, 1);
+}
+
+#[tokio::test]
+async fn full_queue_applies_backpressure() {
+    let pool = Arc::new(ThreadPool::new(1, 1));
+    let (release, blocked) = mpsc::channel::<()>();
+    let blocked = Arc::new(std::sync::Mutex::new(blocked));
+
+    //one job running, one queued behind it
+    let mut jobs = Vec::new();
+    for _ in 0..2 {
+        let (pool, blocked) = (pool.clone(), blocked.clone());
+        jobs.push(tokio::spawn(async move {
+            pool.run(move || blocked.lock().unwrap().recv().unwrap()).await
+        }));
+    }
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+
+    //no slot left, the third caller waits
+    let third = pool.run(|| 3);
+    tokio::pin!(third);
+    assert!(timeout(Duration::from_millis(100), &mut third).await.is_err());
+
+    let m = pool.metrics();
+    assert_eq!(m.running, 1);
+    assert_eq!(m.queued, 2);
+
+    release.send(()).unwrap();
+    release.send(()).unwrap();
+    assert_eq!(timeout(Duration::from_secs(5), third).await.unwrap().unwrap(), 3);
+    for job in jobs.into_iter() {
+        job.await.unwrap().unwrap();
+    }
+    assert_eq!(pool.metrics().completed, 3);
+}